@@ -22,6 +22,7 @@ use crate::{
     context::Av1anContext,
     finish_progress_bar,
     get_done,
+    probe_history::ProbeHistories,
     progress_bar::{
         dec_bar,
         inc_mp_bar,
@@ -33,12 +34,15 @@ use crate::{
     Chunk,
     DoneChunk,
     Instant,
+    TargetQuality,
 };
 
 #[derive(Debug)]
 pub struct Broker<'a> {
-    pub chunk_queue: Vec<Chunk>,
-    pub project:     &'a Av1anContext,
+    pub chunk_queue:            Vec<Chunk>,
+    pub project:                &'a Av1anContext,
+    /// Probe histories of finished chunks used to seed target quality
+    pub(crate) probe_histories: ProbeHistories,
 }
 
 #[derive(Clone)]
@@ -213,25 +217,23 @@ impl Broker<'_> {
         let padding = printable_base10_digits(self.chunk_queue.len() - 1) as usize;
         update_mp_chunk(worker_id, chunk.index, padding);
 
-        if let Some((min, max)) = chunk.target_quality.target {
+        // The quantizer may have already been decided by the size budget
+        if chunk.target_quality.is_enabled() && chunk.tq_cq.is_none() {
             update_mp_msg(
                 worker_id,
-                format!(
-                    "Targeting {metric} Quality: {min}-{max}",
-                    metric = chunk.target_quality.metric,
-                    min = min,
-                    max = max
-                ),
+                format!("Targeting {}", chunk.target_quality.describe_target()),
             );
             for r#try in 1..=self.project.args.max_tries {
-                let res = chunk.target_quality.per_shot_target_quality(
+                let res = chunk.target_quality.search(
                     chunk,
                     Some(worker_id),
                     self.project.args.vapoursynth_plugins,
+                    Some(&self.probe_histories),
                 );
                 match res {
-                    Ok(cq) => {
+                    Ok((cq, resize_denominator)) => {
                         chunk.tq_cq = Some(cq);
+                        chunk.resize_denominator = resize_denominator;
                         break;
                     },
                     Err(e) => {
@@ -249,7 +251,7 @@ impl Broker<'_> {
 
             if chunk.target_quality.params_copied
                 && chunk.tq_cq.is_some()
-                && chunk.target_quality.probing_rate == 1
+                && !chunk.target_quality.samples_frames()
                 && self.project.args.ffmpeg_filter_args.is_empty()
                 && chunk.proxy.is_none()
             {
@@ -259,11 +261,12 @@ impl Broker<'_> {
                     crate::encoder::Encoder::x265 => "hevc",
                     _ => "ivf",
                 };
-                let probe_file =
-                    std::path::Path::new(&self.project.args.temp).join("split").join({
-                        let q_str = crate::encoder::format_q(optimal_q);
-                        format!("v_{:05}_{}.{}", chunk.index, q_str, extension)
-                    });
+                let probe_file = TargetQuality::probe_path(
+                    chunk,
+                    chunk.encoder,
+                    chunk.resize_denominator,
+                    optimal_q,
+                );
 
                 if probe_file.exists() {
                     let encode_dir = std::path::Path::new(&self.project.args.temp).join("encode");
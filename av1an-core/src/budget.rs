@@ -0,0 +1,461 @@
+use std::io::IsTerminal;
+
+use anyhow::Context;
+use colored::*;
+use tracing::{debug, warn};
+
+use crate::{
+    chunk::Chunk,
+    probe_cache::ProbeCache,
+    progress_bar::{finish_progress_bar, inc_bar, init_progress_bar},
+    target_quality::TargetQuality,
+    vapoursynth::VapoursynthPlugins,
+    TargetMetric,
+    Verbosity,
+};
+
+/// Number of bisection rounds used to find the common quality level
+const ALLOCATION_ROUNDS: usize = 64;
+
+/// A single probe of a chunk used to build its size/quality curve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct CurvePoint {
+    pub quantizer: f32,
+    /// Estimated size of the whole chunk in bytes
+    pub size:      f64,
+    /// Quality of the probe where higher is always better
+    pub quality:   f64,
+}
+
+/// Size/quality curve of a chunk, sorted by quantizer and made monotonic so
+/// that quality and size never increase with the quantizer
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkCurve {
+    points: Vec<CurvePoint>,
+}
+
+impl ChunkCurve {
+    pub(crate) fn new(mut points: Vec<CurvePoint>) -> Self {
+        debug_assert!(!points.is_empty());
+        points.sort_by(|a, b| a.quantizer.total_cmp(&b.quantizer));
+        // Segments between equal quantizers have no width to interpolate over
+        points.dedup_by(|b, a| a.quantizer == b.quantizer);
+        // Metrics are noisy, so remove any non-monotonic bumps
+        for i in 1..points.len() {
+            points[i].quality = points[i].quality.min(points[i - 1].quality);
+            points[i].size = points[i].size.min(points[i - 1].size);
+        }
+
+        Self {
+            points,
+        }
+    }
+
+    fn best_quality(&self) -> f64 {
+        self.points[0].quality
+    }
+
+    fn worst_quality(&self) -> f64 {
+        self.points[self.points.len() - 1].quality
+    }
+
+    /// Highest quantizer on the curve that reaches `quality`, clamped to the
+    /// probed quantizers
+    fn quantizer_for_quality(&self, quality: f64) -> f32 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if quality > first.quality {
+            return first.quantizer;
+        }
+        if quality <= last.quality {
+            return last.quantizer;
+        }
+
+        // Search from the back to prefer the highest quantizer for flat segments
+        self.points
+            .windows(2)
+            .rev()
+            .find(|w| w[0].quality >= quality && quality >= w[1].quality)
+            .map_or(last.quantizer, |w| {
+                if w[0].quality == w[1].quality {
+                    return w[1].quantizer;
+                }
+                let t = (w[0].quality - quality) / (w[0].quality - w[1].quality);
+                (t as f32).mul_add(w[1].quantizer - w[0].quantizer, w[0].quantizer)
+            })
+    }
+
+    /// Scales the sizes of the curve so that the size at `quantizer` is the
+    /// `measured` size
+    fn calibrate(&mut self, quantizer: f32, measured: f64) {
+        let estimated = self.size_at(quantizer);
+        if estimated <= 0.0 || measured <= 0.0 {
+            return;
+        }
+        let ratio = measured / estimated;
+        for point in &mut self.points {
+            point.size *= ratio;
+        }
+    }
+
+    /// Estimated size at `quantizer`, interpolated logarithmically between
+    /// the probed quantizers
+    fn size_at(&self, quantizer: f32) -> f64 {
+        let first = self.points[0];
+        let last = self.points[self.points.len() - 1];
+        if quantizer <= first.quantizer {
+            return first.size;
+        }
+        if quantizer >= last.quantizer {
+            return last.size;
+        }
+
+        self.points
+            .windows(2)
+            .find(|w| w[0].quantizer <= quantizer && quantizer <= w[1].quantizer)
+            .map_or(last.size, |w| {
+                let t = f64::from((quantizer - w[0].quantizer) / (w[1].quantizer - w[0].quantizer));
+                let (low, high) = (w[0].size.max(1.0).ln(), w[1].size.max(1.0).ln());
+                t.mul_add(high - low, low).exp()
+            })
+    }
+}
+
+/// Parses a file size such as `4G`, `700MB`, `1.5GiB` or `123456789` into
+/// bytes. Decimal suffixes use powers of 1000 and binary suffixes use powers of
+/// 1024.
+#[inline]
+pub fn parse_target_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (value, suffix) = s.split_at(split);
+    let value = value.parse::<f64>().map_err(|_| format!("Invalid size: {s}"))?;
+    let multiplier: f64 = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "t" | "tb" => 1e12,
+        "ki" | "kib" => 1024.0,
+        "mi" | "mib" => 1024.0 * 1024.0,
+        "gi" | "gib" => 1024.0 * 1024.0 * 1024.0,
+        "ti" | "tib" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("Invalid size suffix: {suffix}")),
+    };
+    let bytes = (value * multiplier).round();
+    if bytes < 1.0 {
+        return Err("Size must be greater than 0".to_string());
+    }
+
+    Ok(bytes as u64)
+}
+
+/// Solves for the quantizer of every chunk so that the estimated sizes add
+/// up to `budget` bytes while keeping the quality of all chunks as even as
+/// possible.
+///
+/// Returns `None` if the budget cannot be met even at the highest probed
+/// quantizers, in which case the highest quantizers should be used.
+pub(crate) fn allocate_quantizers(curves: &[ChunkCurve], budget: f64) -> Option<Vec<f32>> {
+    let allocation = |quality: f64| -> (Vec<f32>, f64) {
+        let quantizers: Vec<f32> =
+            curves.iter().map(|curve| curve.quantizer_for_quality(quality)).collect();
+        let size = curves.iter().zip(&quantizers).map(|(curve, &q)| curve.size_at(q)).sum();
+        (quantizers, size)
+    };
+
+    let mut high = curves.iter().map(ChunkCurve::best_quality).fold(f64::MIN, f64::max);
+    let mut low = curves.iter().map(ChunkCurve::worst_quality).fold(f64::MAX, f64::min);
+
+    let (best, best_size) = allocation(high);
+    if best_size <= budget {
+        return Some(best);
+    }
+    let (worst, worst_size) = allocation(low);
+    if worst_size > budget {
+        return None;
+    }
+
+    // Size only grows with quality, so bisect for the highest quality within budget
+    let mut quantizers = worst;
+    for _ in 0..ALLOCATION_ROUNDS {
+        let middle = f64::midpoint(low, high);
+        let (candidate, size) = allocation(middle);
+        if size <= budget {
+            low = middle;
+            quantizers = candidate;
+        } else {
+            high = middle;
+        }
+    }
+
+    Some(quantizers)
+}
+
+/// Quantizers spread evenly across `min_q..=max_q`, without the duplicates
+/// rounding produces when the range is narrower than the number of probes
+fn curve_quantizers(min_q: u32, max_q: u32, probes: u32) -> Vec<f32> {
+    let probes = probes.max(2);
+    let mut quantizers: Vec<f32> = (0..probes)
+        .map(|i| (min_q as f32 + (max_q - min_q) as f32 * i as f32 / (probes - 1) as f32).round())
+        .collect();
+    quantizers.dedup();
+    quantizers
+}
+
+/// Probes a chunk at quantizers spread evenly across its quantizer range.
+/// Unless the probes already encode every frame with the final parameters,
+/// the sizes are corrected by the ratio between a probe and a full encode
+/// with the final parameters at the middle quantizer. The quality of the
+/// probes is not corrected, and neither is the remaining difference in size
+/// between one and two passes or away from the middle quantizer.
+fn probe_chunk_curve(
+    chunk: &Chunk,
+    plugins: Option<VapoursynthPlugins>,
+) -> anyhow::Result<ChunkCurve> {
+    let tq = &chunk.target_quality;
+    let probed_frames = tq.sampling(chunk).frame_count(chunk.frames());
+    let mut probe_cache = ProbeCache::load(&chunk.temp, &chunk.name());
+
+    let quantizers = curve_quantizers(tq.min_q, tq.max_q, tq.probes);
+    let points = quantizers
+        .iter()
+        .map(|&quantizer| {
+            let probe = tq.cached_probe(chunk, quantizer, plugins, &mut probe_cache)?;
+            let quality = match tq.metric {
+                // There is no quality metric to even out, so even out the quantizer instead
+                TargetMetric::Bitrate => -f64::from(quantizer),
+                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -probe.score,
+                _ => probe.score,
+            };
+
+            Ok(CurvePoint {
+                quantizer,
+                size: probe.size as f64 * chunk.frames() as f64 / probed_frames as f64,
+                quality,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut curve = ChunkCurve::new(points);
+
+    if !tq.params_copied || tq.samples_frames() {
+        let quantizer = quantizers[quantizers.len() / 2];
+        // Only the size of the full encode is needed, which the bitrate metric measures
+        let full_encode = TargetQuality {
+            metric: TargetMetric::Bitrate,
+            constraints: vec![],
+            video_params: Some(chunk.video_params.clone()),
+            params_copied: true,
+            probing_rate: 1,
+            probing_windows: None,
+            frame_floor: None,
+            ..tq.clone()
+        };
+        let size = full_encode.cached_probe(chunk, quantizer, plugins, &mut probe_cache)?.size;
+        curve.calibrate(quantizer, size as f64);
+    }
+
+    debug!(
+        "chunk {name}: size budget curve {points:.2?}",
+        name = chunk.name(),
+        points = curve
+            .points
+            .iter()
+            .map(|p| (p.quantizer, p.size, p.quality))
+            .collect::<Vec<_>>()
+    );
+
+    Ok(curve)
+}
+
+/// Probes every chunk and sets its `tq_cq` so that the estimated size of all
+/// chunks adds up to `budget` bytes.
+pub(crate) fn allocate_size_budget(
+    chunks: &mut [&mut Chunk],
+    budget: u64,
+    workers: usize,
+    plugins: Option<VapoursynthPlugins>,
+    verbosity: Verbosity,
+) -> anyhow::Result<()> {
+    if chunks.is_empty() {
+        return Ok(());
+    }
+
+    if verbosity != Verbosity::Quiet {
+        if std::io::stderr().is_terminal() {
+            eprintln!("{}", "Size budget probing".bold());
+        } else {
+            eprintln!("Size budget probing");
+        }
+        init_progress_bar(chunks.len() as u64, 0, None);
+    }
+
+    let curves = {
+        let chunks = &*chunks;
+        let (sender, receiver) = crossbeam_channel::bounded(chunks.len());
+        for idx in 0..chunks.len() {
+            sender.send(idx)?;
+        }
+        drop(sender);
+
+        let mut curves: Vec<Option<ChunkCurve>> = vec![None; chunks.len()];
+        crossbeam_utils::thread::scope(|s| -> anyhow::Result<()> {
+            let consumers: Vec<_> = std::iter::repeat_with(|| {
+                let rx = receiver.clone();
+                s.spawn(move |_| -> anyhow::Result<Vec<(usize, ChunkCurve)>> {
+                    let mut results = Vec::new();
+                    while let Ok(idx) = rx.recv() {
+                        let chunk = &chunks[idx];
+                        let curve = probe_chunk_curve(chunk, plugins).with_context(|| {
+                            format!("Size budget probing failed on chunk {}", chunk.index)
+                        })?;
+                        results.push((idx, curve));
+                        if verbosity != Verbosity::Quiet {
+                            inc_bar(1);
+                        }
+                    }
+                    Ok(results)
+                })
+            })
+            .take(workers.max(1))
+            .collect();
+            for consumer in consumers {
+                for (idx, curve) in consumer.join().expect("consumer should join successfully")? {
+                    curves[idx] = Some(curve);
+                }
+            }
+            Ok(())
+        })
+        .expect("thread should spawn successfully")?;
+
+        curves
+            .into_iter()
+            .map(|curve| curve.expect("every chunk was probed"))
+            .collect::<Vec<_>>()
+    };
+
+    if verbosity != Verbosity::Quiet {
+        finish_progress_bar();
+    }
+
+    let quantizers = allocate_quantizers(&curves, budget as f64).unwrap_or_else(|| {
+        warn!(
+            "Size budget of {budget} bytes cannot be met within the quantizer range, using the \
+             highest quantizers"
+        );
+        chunks.iter().map(|chunk| chunk.target_quality.max_q as f32).collect()
+    });
+
+    let mut estimated_size = 0.0;
+    for ((chunk, curve), quantizer) in chunks.iter_mut().zip(&curves).zip(quantizers) {
+        // Round up to the next supported step to stay within the budget
+        let step = chunk.target_quality.quantizer_step();
+        let quantizer = ((quantizer / step).ceil() * step).clamp(
+            chunk.target_quality.min_q as f32,
+            chunk.target_quality.max_q as f32,
+        );
+        estimated_size += curve.size_at(quantizer);
+        chunk.tq_cq = Some(quantizer);
+    }
+
+    debug!(
+        "size budget: {budget} bytes, estimated size: {estimated_size:.0} bytes, quantizers: {:?}",
+        chunks.iter().map(|chunk| chunk.tq_cq.unwrap_or_default()).collect::<Vec<_>>()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[(f32, f64, f64)]) -> ChunkCurve {
+        ChunkCurve::new(
+            points
+                .iter()
+                .map(|&(quantizer, size, quality)| CurvePoint {
+                    quantizer,
+                    size,
+                    quality,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_target_size_suffixes() {
+        assert_eq!(parse_target_size("123456"), Ok(123_456));
+        assert_eq!(parse_target_size("4G"), Ok(4_000_000_000));
+        assert_eq!(parse_target_size("700MB"), Ok(700_000_000));
+        assert_eq!(parse_target_size("1.5GiB"), Ok(1_610_612_736));
+        assert_eq!(parse_target_size("2 kib"), Ok(2048));
+        assert!(parse_target_size("0").is_err());
+        assert!(parse_target_size("4X").is_err());
+        assert!(parse_target_size("G").is_err());
+    }
+
+    #[test]
+    fn curve_is_made_monotonic() {
+        let curve = curve(&[(40.0, 1000.0, 90.0), (20.0, 4000.0, 95.0), (30.0, 2000.0, 96.0)]);
+        assert_eq!(curve.best_quality(), 95.0);
+        assert_eq!(curve.quantizer_for_quality(95.0), 30.0);
+        assert_eq!(curve.quantizer_for_quality(92.5), 35.0);
+        assert!((curve.size_at(25.0) - 2828.43).abs() < 0.01);
+    }
+
+    #[test]
+    fn narrow_quantizer_range_has_no_duplicate_probes() {
+        assert_eq!(curve_quantizers(30, 32, 6), vec![30.0, 31.0, 32.0]);
+        assert_eq!(curve_quantizers(30, 30, 4), vec![30.0]);
+        assert_eq!(curve_quantizers(10, 50, 3), vec![10.0, 30.0, 50.0]);
+
+        let curve = curve(&[(30.0, 2000.0, 95.0), (30.0, 1900.0, 94.0), (31.0, 1000.0, 90.0)]);
+        assert_eq!(curve.points.len(), 2);
+        let size = curve.size_at(30.5);
+        assert!(size.is_finite() && size > 1000.0 && size < 2000.0);
+        assert_eq!(curve.size_at(30.0), 2000.0);
+    }
+
+    #[test]
+    fn calibration_scales_sizes() {
+        let mut curve = curve(&[(20.0, 4000.0, 95.0), (30.0, 2000.0, 90.0), (40.0, 1000.0, 85.0)]);
+        curve.calibrate(30.0, 3000.0);
+        assert!((curve.size_at(30.0) - 3000.0).abs() < 1e-6);
+        assert!((curve.size_at(20.0) - 6000.0).abs() < 1e-6);
+        assert!((curve.size_at(40.0) - 1500.0).abs() < 1e-6);
+        assert_eq!(curve.quantizer_for_quality(90.0), 30.0);
+    }
+
+    #[test]
+    fn allocation_meets_budget() {
+        let curves = [
+            curve(&[(10.0, 4000.0, 98.0), (30.0, 1000.0, 90.0), (50.0, 250.0, 80.0)]),
+            curve(&[(10.0, 8000.0, 96.0), (30.0, 2000.0, 88.0), (50.0, 500.0, 70.0)]),
+        ];
+
+        // Enough for the lowest quantizers
+        assert_eq!(
+            allocate_quantizers(&curves, 20000.0),
+            Some(vec![10.0, 10.0])
+        );
+        // Not enough even for the highest quantizers
+        assert_eq!(allocate_quantizers(&curves, 500.0), None);
+
+        let quantizers = allocate_quantizers(&curves, 1500.0).expect("budget can be met");
+        let size: f64 = curves.iter().zip(&quantizers).map(|(c, &q)| c.size_at(q)).sum();
+        assert!(size <= 1500.0 && size > 1490.0);
+        // The more complex chunk gets a lower quantizer to keep the quality even
+        assert!(quantizers[1] < quantizers[0]);
+        let qualities: Vec<f64> = curves
+            .iter()
+            .zip(&quantizers)
+            .map(|(c, &q)| {
+                let (a, b) = (c.points[1], c.points[2]);
+                let t = f64::from((q - a.quantizer) / (b.quantizer - a.quantizer));
+                t.mul_add(b.quality - a.quality, a.quality)
+            })
+            .collect();
+        assert!((qualities[0] - qualities[1]).abs() < 0.01);
+    }
+}
@@ -31,6 +31,10 @@ pub struct Chunk {
     /// Optional target quality CQ level
     #[serde(rename = "per_shot_target_quality_cq")]
     pub tq_cq:                 Option<f32>,
+    /// Resize denominator of the encoder, if the chunk is encoded at 8/n of
+    /// the size of its frames
+    #[serde(default)]
+    pub resize_denominator:    Option<u8>,
     pub ignore_frame_mismatch: bool,
 }
 
@@ -23,6 +23,7 @@ fn chunk_name_1() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -51,6 +52,7 @@ fn chunk_name_10000() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -80,6 +82,7 @@ fn chunk_output() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("d", Encoder::x264),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -113,6 +116,7 @@ fn chunk_frames() {
         frame_rate:            30.0,
         target_quality:        TargetQuality::default("none", Encoder::x264),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
@@ -150,6 +154,7 @@ fn apply_photon_noise_args_with_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
@@ -187,6 +192,7 @@ fn apply_photon_noise_args_no_noise() -> anyhow::Result<()> {
             Encoder::svt_av1,
         ),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::svt_av1,
@@ -224,6 +230,7 @@ fn apply_photon_noise_args_unsupported_encoder() -> anyhow::Result<()> {
             Encoder::x264,
         ),
         tq_cq:                 None,
+        resize_denominator:    None,
         passes:                1,
         video_params:          vec![],
         encoder:               Encoder::x264,
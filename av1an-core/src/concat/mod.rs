@@ -4,13 +4,13 @@ mod tests;
 use std::{
     fmt::{Display, Write as FmtWrite},
     fs::{self, DirEntry, File},
-    io::Write,
+    io::{Read, Write},
     path::{Path, PathBuf},
     process::{Command, Stdio},
     sync::Arc,
 };
 
-use anyhow::{anyhow, Context};
+use anyhow::{anyhow, ensure, Context};
 use av_format::{
     buffer::AccReader,
     demuxer::{Context as DemuxerContext, Event},
@@ -64,6 +64,48 @@ pub fn sort_files_by_filename(files: &mut [PathBuf]) {
     });
 }
 
+/// Frame size in the header of an IVF file, which is the size of the frames
+/// given to the encoder even if it coded them at a lower resolution
+fn ivf_frame_size(file: &Path) -> anyhow::Result<(u16, u16)> {
+    let mut header = [0; 32];
+    File::open(file)
+        .and_then(|mut ivf| ivf.read_exact(&mut header))
+        .with_context(|| format!("Failed to read the IVF header of {}", file.display()))?;
+    ensure!(
+        &header[..4] == b"DKIF",
+        "{} is not an IVF file",
+        file.display()
+    );
+
+    Ok((
+        u16::from_le_bytes([header[12], header[13]]),
+        u16::from_le_bytes([header[14], header[15]]),
+    ))
+}
+
+/// Checks that every IVF chunk has the frame size of the first, as chunks of
+/// different sizes cannot be concatenated into a single stream
+fn ensure_same_frame_size(files: &[PathBuf]) -> anyhow::Result<()> {
+    let Some((first, rest)) = files.split_first() else {
+        return Ok(());
+    };
+    let (width, height) = ivf_frame_size(first)?;
+    for file in rest {
+        let size = ivf_frame_size(file)?;
+        ensure!(
+            size == (width, height),
+            "{} is {}x{}, but {} is {width}x{height}. Chunks must keep the frame size to be \
+             concatenated",
+            file.display(),
+            size.0,
+            size.1,
+            first.display()
+        );
+    }
+
+    Ok(())
+}
+
 #[tracing::instrument(level = "debug")]
 pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
     let mut files: Vec<PathBuf> = read_in_dir(input)?.collect();
@@ -71,6 +113,7 @@ pub fn ivf(input: &Path, out: &Path) -> anyhow::Result<()> {
     sort_files_by_filename(&mut files);
 
     assert!(!files.is_empty());
+    ensure_same_frame_size(&files)?;
 
     let output = File::create(out)?;
 
@@ -222,6 +265,16 @@ pub fn mkvmerge(
         })
         .collect();
 
+    if encoder.output_extension() == "ivf" {
+        ensure_same_frame_size(
+            &chunk_groups
+                .iter()
+                .flatten()
+                .map(|chunk| encode_dir.join(chunk))
+                .collect::<Vec<_>>(),
+        )?;
+    }
+
     chunk_groups.iter().enumerate().try_for_each(|(group_index, chunk_group)| {
         let group_options_path =
             PathBuf::from(&temp_dir).join(format!("group_options_{group_index:05}.json"));
@@ -31,3 +31,55 @@ fn mkvmerge_options_json_with_audio() {
         r#"["-o", "output.mkv", "audio.mkv", "--default-duration", "0:30/1fps", "[", "00000.ivf", "00001.ivf","]"]"#
     );
 }
+
+/// Writes an IVF file with one packet per frame, and `size` as the frame
+/// size of its header
+fn write_ivf(path: &Path, (width, height): (u16, u16), packets: &[&[u8]]) -> anyhow::Result<()> {
+    let mut ivf = b"DKIF".to_vec();
+    ivf.extend(0u16.to_le_bytes());
+    ivf.extend(32u16.to_le_bytes());
+    ivf.extend(b"AV01");
+    ivf.extend(width.to_le_bytes());
+    ivf.extend(height.to_le_bytes());
+    ivf.extend(30u32.to_le_bytes());
+    ivf.extend(1u32.to_le_bytes());
+    ivf.extend(u32::try_from(packets.len())?.to_le_bytes());
+    ivf.extend(0u32.to_le_bytes());
+    for (pts, packet) in packets.iter().enumerate() {
+        ivf.extend(u32::try_from(packet.len())?.to_le_bytes());
+        ivf.extend(u64::try_from(pts)?.to_le_bytes());
+        ivf.extend(*packet);
+    }
+    fs::write(path, ivf)?;
+
+    Ok(())
+}
+
+#[test]
+fn ivf_concatenates_chunks_resized_by_the_encoder() -> anyhow::Result<()> {
+    let temp = tempfile::tempdir()?;
+    let encode = temp.path().join("encode");
+    fs::create_dir_all(&encode)?;
+    // The second chunk is coded at 8/12 of the size, which the encoder keeps
+    // out of the header
+    write_ivf(&encode.join("00000.ivf"), (1920, 1080), &[
+        b"1920x1080 key",
+        b"1920x1080",
+    ])?;
+    write_ivf(&encode.join("00001.ivf"), (1920, 1080), &[b"1280x720 key"])?;
+
+    let output = temp.path().join("output.ivf");
+    ivf(&encode, &output)?;
+    assert_eq!(ivf_frame_size(&output)?, (1920, 1080));
+    let concatenated = fs::read(&output)?;
+    for packet in [&b"1920x1080 key"[..], b"1920x1080", b"1280x720 key"] {
+        assert!(concatenated.windows(packet.len()).any(|window| window == packet));
+    }
+
+    // Chunks scaled before encoding change the size of the stream
+    write_ivf(&encode.join("00001.ivf"), (1280, 720), &[b"1280x720 key"])?;
+    let error = ivf(&encode, &output).expect_err("the frame sizes differ");
+    assert!(error.to_string().contains("is 1280x720, but"));
+
+    Ok(())
+}
@@ -4,7 +4,7 @@ use std::{
     ffi::OsString,
     fs::{self, File},
     io::{BufRead, BufReader, Write},
-    iter,
+    mem,
     path::{Path, PathBuf},
     process::{exit, ChildStderr, Command, Stdio},
     sync::{
@@ -16,17 +16,17 @@ use std::{
     thread::{self, available_parallelism},
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 use av1_grain::TransferFunction;
 use av_decoders::VapoursynthDecoder;
 use colored::*;
-use itertools::Itertools;
 use num_traits::cast::ToPrimitive;
 use rand::{prelude::SliceRandom, rng};
 use tracing::{debug, error, info, warn};
 
 use crate::{
     broker::{Broker, EncoderCrash},
+    budget,
     chunk::Chunk,
     concat::{self, ConcatMethod},
     create_dir,
@@ -35,7 +35,12 @@ use crate::{
     get_done,
     init_done,
     into_vec,
-    metrics::vmaf,
+    metrics::{
+        analysis::{self, AnalysisOptions},
+        vmaf,
+    },
+    probe_cache::PROBE_CACHE_DIR,
+    probe_history::ProbeHistories,
     progress_bar::{
         finish_progress_bar,
         inc_bar,
@@ -51,9 +56,10 @@ use crate::{
     },
     read_chunk_queue,
     save_chunk_queue,
-    scenes::{Scene, SceneFactory, ZoneOptions},
+    scenes::{import::detect_format, Scene, SceneFactory, ZoneOptions},
     settings::{EncodeArgs, InputPixelFormat},
-    split::segment,
+    split::{segment, snap_to_keyframes},
+    tq_report,
     vapoursynth::create_vs_file,
     zones::{parse_zones, validate_zones},
     ChunkMethod,
@@ -61,6 +67,7 @@ use crate::{
     DashMap,
     DoneJson,
     Input,
+    SceneFormat,
     Verbosity,
 };
 
@@ -93,12 +100,25 @@ impl Av1anContext {
     #[tracing::instrument(level = "debug")]
     fn initialize(&mut self) -> anyhow::Result<()> {
         if !self.args.resume && Path::new(&self.args.temp).is_dir() {
-            fs::remove_dir_all(&self.args.temp).with_context(|| {
-                format!(
-                    "Failed to remove temporary directory {temp}",
-                    temp = self.args.temp
-                )
-            })?;
+            // Keep the probe cache, probes of the same input content with the same settings
+            // do not need to be redone
+            for entry in fs::read_dir(&self.args.temp)? {
+                let path = entry?.path();
+                if path.file_name().is_some_and(|name| name == PROBE_CACHE_DIR) {
+                    continue;
+                }
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                }
+                .with_context(|| {
+                    format!(
+                        "Failed to clear temporary directory {temp}",
+                        temp = self.args.temp
+                    )
+                })?;
+            }
         }
 
         create_dir!(Path::new(&self.args.temp))?;
@@ -284,6 +304,19 @@ impl Av1anContext {
         );
 
         let splits = self.split_routine()?.to_vec();
+        let source_name = self.args.input.as_path().file_name().map_or_else(
+            || self.args.input.as_path().to_string_lossy(),
+            |name| name.to_string_lossy(),
+        );
+        for export in &self.args.sc_export {
+            export.write(&splits, fps, &source_name)?;
+            info!(
+                "scenecut: wrote {} scene(s) as {} to {}",
+                splits.len(),
+                export.format,
+                export.path.display()
+            );
+        }
 
         if self.args.sc_only {
             debug!("scene detection only");
@@ -385,6 +418,7 @@ impl Av1anContext {
             let broker = Broker {
                 chunk_queue,
                 project: self,
+                probe_histories: ProbeHistories::default(),
             };
 
             let (tx, rx) = mpsc::channel();
@@ -450,7 +484,7 @@ impl Av1anContext {
                 },
             }
 
-            if self.args.vmaf {
+            if self.args.vmaf || !self.args.analysis_metrics.is_empty() {
                 let vmaf_res = if self.args.target_quality.vmaf_res == "inputres" {
                     let inputres = self.args.input.clip_info()?.resolution;
                     format!("{width}x{height}", width = inputres.0, height = inputres.1)
@@ -466,10 +500,9 @@ impl Av1anContext {
                     .target_quality
                     .vmaf_filter
                     .as_deref());
+                let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
 
                 if self.args.vmaf {
-                    let vmaf_threads = available_parallelism().map_or(1, std::num::NonZero::get);
-
                     if let Err(e) = vmaf::plot(
                         self.args.output_file.as_ref(),
                         &self.args.input,
@@ -484,6 +517,42 @@ impl Av1anContext {
                         error!("VMAF calculation failed with error: {e}");
                     }
                 }
+
+                let options = AnalysisOptions {
+                    model:         vmaf_model,
+                    res:           vmaf_res,
+                    probe_res:     self.args.target_quality.probe_res,
+                    scaler:        &self.args.target_quality.vmaf_scaler,
+                    filter:        vmaf_filter,
+                    threads:       vmaf_threads,
+                    vmaf_features: &self.args.target_quality.probing_vmaf_features,
+                    pix_format:    self.args.target_quality.pix_format,
+                    plugins:       self.args.vapoursynth_plugins,
+                };
+                let chunks: Vec<_> =
+                    splits.iter().map(|scene| (scene.start_frame, scene.end_frame)).collect();
+                let frame_rate = fps_ratio.to_f64().expect("frame rate should not be NaN");
+                for &metric in &self.args.analysis_metrics {
+                    if let Err(e) = analysis::analyze(
+                        metric,
+                        self.args.output_file.as_ref(),
+                        &self.args.input,
+                        &chunks,
+                        Path::new(&self.args.temp),
+                        frame_rate,
+                        &self.args.target_quality.probing_statistic,
+                        &options,
+                    ) {
+                        error!("{metric} analysis failed with error: {e:#}");
+                    }
+                }
+            }
+
+            if self.args.tq_report {
+                let report_path = tq_report::report_path(&self.args.output_file);
+                if let Err(e) = tq_report::write_combined_report(&self.args.temp, &report_path) {
+                    error!("Failed to write target quality report: {e:#}");
+                }
             }
 
             if !Path::new(&self.args.output_file).exists() {
@@ -541,7 +610,15 @@ impl Av1anContext {
             .join("split")
             .join(format!("{name}_fpf", name = chunk.name()));
 
-        let video_params = chunk.video_params.clone();
+        let mut video_params = chunk.video_params.clone();
+        // Chunks picked at a lower resolution by the convex hull are scaled by the
+        // encoder, which keeps the size of the sequence header
+        if let Some(args) = chunk
+            .resize_denominator
+            .and_then(|denominator| chunk.encoder.resize_args(denominator))
+        {
+            video_params.extend(args);
+        }
 
         let mut enc_cmd = if chunk.passes == 1 {
             chunk.encoder.compose_1_1_pass(video_params, chunk.output())
@@ -558,7 +635,7 @@ impl Av1anContext {
         };
 
         if let Some(per_shot_target_quality_cq) = chunk.tq_cq {
-            enc_cmd = chunk.encoder.man_command(enc_cmd, per_shot_target_quality_cq);
+            enc_cmd = chunk.target_quality.apply_search_result(enc_cmd, per_shot_target_quality_cq);
         }
 
         let (source_pipe_stderr, ffmpeg_pipe_stderr, enc_output, enc_stderr, frame) =
@@ -843,8 +920,26 @@ impl Av1anContext {
             || Cow::Owned(Path::new(&self.args.temp).join("scenes.json")),
             |path| Cow::Borrowed(path.as_path()),
         );
-        if scene_file.exists() && (self.args.scenes.is_some() || self.args.resume) {
+        let format = match self.args.scenes_format {
+            SceneFormat::Auto if scene_file.exists() => detect_format(&scene_file)?,
+            format => format,
+        };
+        if !scene_file.exists() && !matches!(format, SceneFormat::Auto | SceneFormat::Av1an) {
+            bail!(
+                "{format} scene list {} does not exist",
+                scene_file.display()
+            );
+        }
+        if scene_file.exists()
+            && (self.args.scenes.is_some() || self.args.resume)
+            && matches!(format, SceneFormat::Auto | SceneFormat::Av1an)
+        {
             self.scene_factory = SceneFactory::from_scenes_file(&scene_file)?;
+        } else if scene_file.exists() && self.args.scenes.is_some() {
+            // Scene lists of other tools only hold the scenecuts
+            let zones = parse_zones(&self.args, self.frames)?;
+            validate_zones(&self.args, &zones)?;
+            self.scene_factory.import_scenes(&scene_file, format, &self.args, &zones)?;
         } else {
             let zones = parse_zones(&self.args, self.frames)?;
             validate_zones(&self.args, &zones)?;
@@ -926,18 +1021,18 @@ impl Av1anContext {
                 ovr.target_quality.clone().map_or(self.args.target_quality.clone(), |tq| tq)
             }),
             tq_cq: None,
+            resize_denominator: None,
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
         chunk.apply_photon_noise_args(
             overrides.map_or(self.args.photon_noise, |ovr| ovr.photon_noise),
             self.args.chroma_noise,
         )?;
-        if chunk.target_quality.target.is_some() {
-            chunk.tq_cq = Some(chunk.target_quality.per_shot_target_quality(
-                &chunk,
-                None,
-                self.args.vapoursynth_plugins,
-            )?);
+        if chunk.target_quality.is_enabled() {
+            let (quantizer, resize_denominator) =
+                chunk.target_quality.search(&chunk, None, self.args.vapoursynth_plugins, None)?;
+            chunk.tq_cq = Some(quantizer);
+            chunk.resize_denominator = resize_denominator;
         }
         Ok(chunk)
     }
@@ -1045,6 +1140,7 @@ impl Av1anContext {
                 },
             ),
             tq_cq: None,
+            resize_denominator: None,
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
         chunk.apply_photon_noise_args(
@@ -1130,6 +1226,31 @@ impl Av1anContext {
             .to_f64()
             .expect("frame rate should not be NaN");
 
+        // Segments are stream copies, which can only start at a keyframe of the source
+        let keyframes = crate::ffmpeg::get_keyframes(input)?;
+        let zones = parse_zones(&self.args, self.frames)?;
+        let (scenes, changes) = snap_to_keyframes(
+            scenes,
+            &zones,
+            &keyframes,
+            self.args.min_scene_len,
+            self.args.extra_splits_len,
+        )?;
+        if changes.moved > 0 {
+            warn!(
+                "{moved} scenecut(s) are not keyframes of the source, moved them to the nearest \
+                 keyframe for the segment chunk method",
+                moved = changes.moved
+            );
+        }
+        if changes.added > 0 {
+            debug!(
+                "Added {added} extra split(s) at keyframes of the source to keep the snapped \
+                 scenes within the extra split length",
+                added = changes.added
+            );
+        }
+
         debug!("Splitting video");
         segment(
             input,
@@ -1146,15 +1267,36 @@ impl Av1anContext {
             "Error: No files found in temp/split, probably splitting not working"
         );
 
+        let segments = Self::segment_ranges(&queue_files)?;
+        if segments.len() != scenes.len()
+            || segments
+                .iter()
+                .zip(&scenes)
+                .any(|(&(start, end), scene)| (start, end) != (scene.start_frame, scene.end_frame))
+        {
+            warn!(
+                "FFmpeg split the video into {} segment(s) that differ from the {} planned \
+                 scene(s), using the frames of the segments",
+                segments.len(),
+                scenes.len()
+            );
+        }
+
         let chunk_queue: Vec<Chunk> = queue_files
             .iter()
+            .zip(segments)
             .enumerate()
-            .map(|(index, file)| {
+            .map(|(index, (file, (start, end)))| {
+                let overrides = scenes
+                    .iter()
+                    .find(|scene| (scene.start_frame..scene.end_frame).contains(&start))
+                    .and_then(|scene| scene.zone_overrides.clone());
                 self.create_chunk_from_segment(
                     index,
                     &file.as_path().to_string_lossy(),
+                    end - start,
                     frame_rate,
-                    scenes[index].zone_overrides.clone(),
+                    overrides,
                 )
             })
             .collect::<anyhow::Result<Vec<_>>>()?;
@@ -1181,20 +1323,34 @@ impl Av1anContext {
             .collect();
 
         debug!("Segmenting video");
-        segment(input, &self.args.temp, &to_split[1..])?;
+        segment(
+            input,
+            &self.args.temp,
+            to_split.get(1..).unwrap_or_default(),
+        )?;
         debug!("Segment done");
 
         let source_path = Path::new(&self.args.temp).join("split");
         let queue_files = Self::read_queue_files(&source_path)?;
 
-        let kf_list = to_split.iter().copied().chain(iter::once(self.frames)).tuple_windows();
+        // The scenes are selected from the frames actually in each segment, in case
+        // FFmpeg did not cut exactly at the requested keyframes
+        let kf_list = Self::segment_ranges(&queue_files)?;
+        if !kf_list.iter().map(|&(start, _)| start).eq(to_split.iter().copied()) {
+            warn!(
+                "FFmpeg split the video into {} segment(s) that differ from the {} keyframe(s) \
+                 planned, selecting the scenes from the frames of the segments",
+                kf_list.len(),
+                to_split.len()
+            );
+        }
 
         let mut segments = Vec::with_capacity(scenes.len());
         for (file, (x, y)) in queue_files.iter().zip(kf_list) {
             for s in scenes {
-                let s0 = s.start_frame;
-                let s1 = s.end_frame;
-                if s0 >= x && s1 <= y && s0 < s1 {
+                let s0 = s.start_frame.max(x);
+                let s1 = s.end_frame.min(y);
+                if s0 < s1 {
                     segments.push((file.as_path(), (s0 - x, s1 - x, s)));
                 }
             }
@@ -1218,11 +1374,25 @@ impl Av1anContext {
         Ok(chunk_queue)
     }
 
+    /// Frame ranges of the source that the segment files cover, counted from
+    /// the frames in each file
+    fn segment_ranges(queue_files: &[PathBuf]) -> anyhow::Result<Vec<(usize, usize)>> {
+        let mut start = 0;
+        queue_files
+            .iter()
+            .map(|file| {
+                let end = start + get_num_frames(file)?;
+                Ok((mem::replace(&mut start, end), end))
+            })
+            .collect()
+    }
+
     #[tracing::instrument(level = "debug")]
     fn create_chunk_from_segment(
         &self,
         index: usize,
         file: &str,
+        num_frames: usize,
         frame_rate: f64,
         overrides: Option<ZoneOptions>,
     ) -> anyhow::Result<Chunk> {
@@ -1245,8 +1415,6 @@ impl Av1anContext {
 
         let output_ext = self.args.encoder.output_extension();
 
-        let num_frames = get_num_frames(Path::new(file))?;
-
         let mut chunk = Chunk {
             temp: self.args.temp.clone(),
             input: Input::Video {
@@ -1279,6 +1447,7 @@ impl Av1anContext {
                 ovr.target_quality.clone().map_or(self.args.target_quality.clone(), |tq| tq)
             }),
             tq_cq: None,
+            resize_denominator: None,
             ignore_frame_mismatch: self.args.ignore_frame_mismatch,
         };
         chunk.apply_photon_noise_args(
@@ -1296,15 +1465,56 @@ impl Av1anContext {
 
             let done = get_done();
 
+            // The size budget may have been interrupted, so allocate what is left of it
+            // to the chunks that are not done
+            if let Some(target_size) = self.args.target_size {
+                if chunks
+                    .iter()
+                    .any(|chunk| chunk.tq_cq.is_none() && !done.done.contains_key(&chunk.name()))
+                {
+                    let done_size = done.done.iter().map(|chunk| chunk.size_bytes).sum::<u64>();
+                    let mut remaining: Vec<&mut Chunk> = chunks
+                        .iter_mut()
+                        .filter(|chunk| !done.done.contains_key(&chunk.name()))
+                        .collect();
+                    self.allocate_size_budget(
+                        &mut remaining,
+                        target_size.saturating_sub(done_size).max(1),
+                    )?;
+                    save_chunk_queue(&self.args.temp, &chunks)?;
+                }
+            }
+
             // only keep the chunks that are not done
             chunks.retain(|chunk| !done.done.contains_key(&chunk.name()));
 
             Ok((chunks, num_chunks))
         } else {
-            let chunks = self.create_encoding_queue(splits)?;
+            let mut chunks = self.create_encoding_queue(splits)?;
             let num_chunks = chunks.len();
+            // Save the queue first so an interrupted allocation can be resumed
             save_chunk_queue(&self.args.temp, &chunks)?;
+            if let Some(target_size) = self.args.target_size {
+                self.allocate_size_budget(&mut chunks.iter_mut().collect::<Vec<_>>(), target_size)?;
+                save_chunk_queue(&self.args.temp, &chunks)?;
+            }
             Ok((chunks, num_chunks))
         }
     }
+
+    fn allocate_size_budget(&self, chunks: &mut [&mut Chunk], budget: u64) -> anyhow::Result<()> {
+        let workers = if self.args.workers == 0 {
+            determine_workers(&self.args)? as usize
+        } else {
+            self.args.workers
+        };
+
+        budget::allocate_size_budget(
+            chunks,
+            budget,
+            cmp::min(workers, chunks.len()),
+            self.args.vapoursynth_plugins,
+            self.args.verbosity,
+        )
+    }
 }
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+/// A probe of a chunk at a resolution and quantizer on the rate-quality plane
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct HullPoint {
+    /// Resize denominator the probe was encoded with, or None at the source
+    /// resolution
+    pub denominator: Option<u8>,
+    pub quantizer:   f32,
+    /// Bitrate of the probe in kbps
+    pub bitrate:     f64,
+    /// Score normalized so that higher is better
+    pub score:       f64,
+}
+
+/// Upper convex hull of the rate-quality points of a chunk, ordered by
+/// increasing bitrate. Every point on it gives the best quality for its
+/// bitrate, and the quality gained per bitrate decreases along it.
+pub(crate) fn upper_convex_hull(mut points: Vec<HullPoint>) -> Vec<HullPoint> {
+    points.sort_by(|a, b| a.bitrate.total_cmp(&b.bitrate).then(b.score.total_cmp(&a.score)));
+
+    let mut hull: Vec<HullPoint> = Vec::with_capacity(points.len());
+    for point in points {
+        // Points that cost more without scoring higher are never worth it
+        if hull.last().is_some_and(|last| point.score <= last.score) {
+            continue;
+        }
+        // Drop points below the line from their predecessor to the new point
+        while let [.., a, b] = hull[..] {
+            let cross = (b.bitrate - a.bitrate).mul_add(
+                point.score - a.score,
+                -(b.score - a.score) * (point.bitrate - a.bitrate),
+            );
+            if cross < 0.0 {
+                break;
+            }
+            hull.pop();
+        }
+        hull.push(point);
+    }
+
+    hull
+}
+
+/// Point of `hull` where the quality gained by moving to the next point drops
+/// below `slope` score per Mbps
+pub(crate) fn point_at_slope(hull: &[HullPoint], slope: f64) -> Option<HullPoint> {
+    hull.windows(2)
+        .find(|pair| {
+            let gain = pair[1].score - pair[0].score;
+            let cost = (pair[1].bitrate - pair[0].bitrate) / 1000.0;
+            gain / cost < slope
+        })
+        .map(|pair| pair[0])
+        .or_else(|| hull.last().copied())
+}
+
+/// Cheapest point of `hull` scoring at least `minimum_score`, or the highest
+/// scoring point if none of them do
+pub(crate) fn cheapest_point_meeting(hull: &[HullPoint], minimum_score: f64) -> Option<HullPoint> {
+    hull.iter()
+        .find(|point| point.score >= minimum_score)
+        .or_else(|| hull.last())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convex_hull_selection() {
+        let point = |denominator, bitrate, score| HullPoint {
+            denominator,
+            quantizer: 30.0,
+            bitrate,
+            score,
+        };
+        let hull = upper_convex_hull(vec![
+            point(None, 4000.0, 96.0),
+            point(None, 2000.0, 92.0),
+            point(Some(12), 1000.0, 88.0),
+            // Below the line between its neighbours
+            point(Some(12), 1500.0, 89.0),
+            // Costs more than a better point
+            point(Some(12), 2500.0, 91.0),
+        ]);
+        assert_eq!(
+            hull.iter().map(|p| (p.bitrate, p.score)).collect::<Vec<_>>(),
+            [(1000.0, 88.0), (2000.0, 92.0), (4000.0, 96.0)]
+        );
+
+        assert_eq!(
+            cheapest_point_meeting(&hull, 90.0).map(|p| p.bitrate),
+            Some(2000.0)
+        );
+        assert_eq!(
+            cheapest_point_meeting(&hull, 98.0).map(|p| p.bitrate),
+            Some(4000.0)
+        );
+        // 4 points per Mbps up to 2000 kbps, then 2 points per Mbps
+        assert_eq!(point_at_slope(&hull, 3.0).map(|p| p.bitrate), Some(2000.0));
+        assert_eq!(
+            point_at_slope(&hull, 5.0).map(|p| p.denominator),
+            Some(Some(12))
+        );
+        assert_eq!(point_at_slope(&hull, 1.0).map(|p| p.bitrate), Some(4000.0));
+    }
+}
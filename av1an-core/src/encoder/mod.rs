@@ -6,7 +6,7 @@ use std::{
     cmp,
     fmt::Display,
     iter::Iterator,
-    path::PathBuf,
+    path::Path,
     process::Command,
     sync::OnceLock,
 };
@@ -55,6 +55,8 @@ use crate::{
     into_array,
     into_vec,
     list_index,
+    sampling::FrameSampling,
+    search_param::SearchParameter,
 };
 
 const NULL: &str = if cfg!(windows) { "nul" } else { "/dev/null" };
@@ -504,6 +506,16 @@ impl Encoder {
         }
     }
 
+    /// Returns the entire quantizer range of the encoder
+    #[inline]
+    pub const fn get_quantizer_range(self) -> (usize, usize) {
+        match self {
+            Self::aom | Self::vpx | Self::svt_av1 => (0, 63),
+            Self::rav1e => (0, 255),
+            Self::x264 | Self::x265 => (0, 51),
+        }
+    }
+
     /// Returns quantizer percentage (0-1) relative to entire range for encoder
     #[inline]
     pub fn get_cq_relative_percentage(self, quantizer: usize) -> f64 {
@@ -631,6 +643,30 @@ impl Encoder {
         }
     }
 
+    /// Arguments making the encoder scale every frame down to 8/`denominator`
+    /// of its size internally. The sequence header keeps the size of the
+    /// input, so chunks encoded at different scales can still be
+    /// concatenated. Returns None if the encoder cannot resize frames.
+    #[inline]
+    pub fn resize_args(self, denominator: u8) -> Option<Vec<String>> {
+        match self {
+            Self::aom => Some(vec![
+                "--resize-mode=1".into(),
+                format!("--resize-denominator={denominator}"),
+                format!("--resize-kf-denominator={denominator}"),
+            ]),
+            Self::svt_av1 => Some(vec![
+                "--resize-mode".into(),
+                "1".into(),
+                "--resize-denom".into(),
+                denominator.to_string(),
+                "--resize-kf-denom".into(),
+                denominator.to_string(),
+            ]),
+            Self::rav1e | Self::vpx | Self::x264 | Self::x265 => None,
+        }
+    }
+
     /// Returns function pointer used for matching Q/CRF arguments in command
     /// line
     fn q_match_fn(self) -> fn(&str) -> bool {
@@ -672,6 +708,21 @@ impl Encoder {
         output
     }
 
+    /// Returns the q/crf set in command line arguments, if any
+    #[inline]
+    pub fn get_q(self, params: &[String]) -> Option<f32> {
+        if params.is_empty() {
+            return None;
+        }
+        let index = list_index(params, self.q_match_fn())?;
+        match self {
+            Self::aom | Self::vpx => params[index].strip_prefix("--cq-level=")?.parse().ok(),
+            Self::rav1e | Self::svt_av1 | Self::x264 | Self::x265 => {
+                params.get(index + 1)?.parse().ok()
+            },
+        }
+    }
+
     /// Returns changed q/crf in command line arguments
     #[inline]
     pub fn man_command(self, mut params: Vec<String>, q: f32) -> Vec<String> {
@@ -965,30 +1016,19 @@ impl Encoder {
     /// Constructs tuple of commands for target quality probing
     pub fn probe_cmd(
         self,
-        temp: String,
-        chunk_index: usize,
+        probe: &Path,
         q: f32,
         pix_fmt: FFPixelFormat,
-        probing_rate: usize,
+        sampling: &FrameSampling,
+        resize_denominator: Option<u8>,
         vmaf_threads: usize,
         custom_video_params: Option<Vec<String>>,
+        search_parameter: Option<(&SearchParameter, f32)>,
     ) -> (Option<Vec<String>>, Vec<Cow<'static, str>>) {
-        let pipe = (probing_rate > 1).then(|| {
-            compose_ffmpeg_pipe(
-                ["-vf", format!("select=not(mod(n\\,{probing_rate}))").as_str(), "-vsync", "0"],
-                pix_fmt,
-            )
-        });
+        let pipe = sampling
+            .ffmpeg_select()
+            .map(|select| compose_ffmpeg_pipe(["-vf", select.as_str(), "-vsync", "0"], pix_fmt));
 
-        let extension = match self {
-            Encoder::x264 => "264",
-            Encoder::x265 => "hevc",
-            _ => "ivf",
-        };
-        let q_str = format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk_index);
-
-        let probe = PathBuf::from(temp).join("split").join(&probe_name);
         let probe_path = probe.to_string_lossy().to_string();
 
         let params: Vec<Cow<str>> = custom_video_params.map_or_else(
@@ -1008,6 +1048,24 @@ impl Encoder {
                 ps
             },
         );
+        // Applied last so that it overrides the same parameter in the probe defaults
+        let params = match search_parameter {
+            Some((parameter, value)) => parameter
+                .apply(params.into_iter().map(Cow::into_owned).collect(), value)
+                .into_iter()
+                .map(Cow::Owned)
+                .collect(),
+            None => params,
+        };
+        let params: Vec<Cow<str>> = chain!(
+            params,
+            resize_denominator
+                .and_then(|denominator| self.resize_args(denominator))
+                .into_iter()
+                .flatten()
+                .map(Cow::Owned)
+        )
+        .collect();
 
         let output: Vec<Cow<str>> = match self {
             Self::svt_av1 => chain!(params, into_array!["-b", probe_path]).collect(),
@@ -1,4 +1,10 @@
-use crate::encoder::parse_svt_av1_version;
+use std::path::Path;
+
+use crate::{
+    encoder::{parse_svt_av1_version, Encoder},
+    ffmpeg::FFPixelFormat,
+    sampling::FrameSampling,
+};
 
 #[test]
 fn svt_av1_parsing() {
@@ -17,3 +23,34 @@ fn svt_av1_parsing() {
         assert_eq!(parse_svt_av1_version(s.as_bytes()), ans);
     }
 }
+
+#[test]
+fn resized_probes_keep_the_input_size() {
+    let probe = |encoder: Encoder, resize_denominator| {
+        encoder.probe_cmd(
+            Path::new("probe.ivf"),
+            30.0,
+            FFPixelFormat::YUV420P10LE,
+            &FrameSampling::Every(1),
+            resize_denominator,
+            1,
+            Some(vec!["--preset".to_string(), "8".to_string()]),
+            None,
+        )
+    };
+
+    // The encoder scales the frames, so they are piped at the input size
+    let (pipe, aom) = probe(Encoder::aom, Some(12));
+    assert!(pipe.is_none());
+    assert!(aom.iter().any(|arg| arg == "--resize-mode=1"));
+    assert!(aom.iter().any(|arg| arg == "--resize-denominator=12"));
+    assert!(aom.iter().any(|arg| arg == "--resize-kf-denominator=12"));
+
+    let (_, svt_av1) = probe(Encoder::svt_av1, Some(16));
+    assert!(svt_av1.windows(2).any(|args| args[0] == "--resize-denom" && args[1] == "16"));
+    assert_eq!(svt_av1[svt_av1.len() - 2], "-b");
+
+    let (_, unscaled) = probe(Encoder::aom, None);
+    assert!(!unscaled.iter().any(|arg| arg.starts_with("--resize")));
+    assert!(Encoder::x264.resize_args(12).is_none());
+}
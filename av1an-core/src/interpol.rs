@@ -399,6 +399,77 @@ pub fn akima_interpolate(x: &[f64; 4], y: &[f64; 4], xi: f64) -> Option<f64> {
     ))
 }
 
+/// Fits a monotone log-linear model to any number of score/quantizer pairs
+/// and predicts the quantizer for score `xi`.
+///
+/// The scores are first made non-increasing in quantizer with isotonic
+/// regression, so noisy points cannot break the fit. Then `ln(y + 1) = a + b *
+/// x` is fitted with least squares. Returns the predicted quantizer with a
+/// confidence between 0 and 1: the coefficient of determination of the fit,
+/// reduced by the share of points that isotonic regression had to adjust.
+pub fn monotone_fit(x: &[f64], y: &[f64], xi: f64) -> Option<(f64, f64)> {
+    let n = x.len();
+    if n < 2 || n != y.len() {
+        return None;
+    }
+
+    let mut points: Vec<(f64, f64)> = y.iter().copied().zip(x.iter().copied()).collect();
+    points.sort_by(|(q1, _), (q2, _)| q1.total_cmp(q2));
+
+    // Pool adjacent violators: blocks of (sum of scores, number of points) whose
+    // means are non-increasing in quantizer
+    let mut blocks: Vec<(f64, usize)> = Vec::with_capacity(n);
+    for &(_, score) in &points {
+        blocks.push((score, 1));
+        while let [.., (previous_sum, previous_count), (sum, count)] = blocks[..] {
+            if previous_sum / previous_count as f64 >= sum / count as f64 {
+                break;
+            }
+            blocks.pop();
+            *blocks.last_mut()? = (previous_sum + sum, previous_count + count);
+        }
+    }
+    let fitted_scores: Vec<f64> = blocks
+        .iter()
+        .flat_map(|&(sum, count)| std::iter::repeat_n(sum / count as f64, count))
+        .collect();
+    let adjusted = points
+        .iter()
+        .zip(&fitted_scores)
+        .filter(|((_, score), fitted)| (score - *fitted).abs() > f64::EPSILON)
+        .count();
+
+    let log_quantizers: Vec<f64> = points.iter().map(|(q, _)| q.max(0.0).ln_1p()).collect();
+    let mean_score = fitted_scores.iter().sum::<f64>() / n as f64;
+    let mean_log_quantizer = log_quantizers.iter().sum::<f64>() / n as f64;
+    let (covariance, score_variance, log_quantizer_variance) =
+        fitted_scores.iter().zip(&log_quantizers).fold((0.0, 0.0, 0.0), |acc, (s, lq)| {
+            let (ds, dq) = (s - mean_score, lq - mean_log_quantizer);
+            (
+                ds.mul_add(dq, acc.0),
+                ds.mul_add(ds, acc.1),
+                dq.mul_add(dq, acc.2),
+            )
+        });
+    if score_variance <= 0.0 || log_quantizer_variance <= 0.0 {
+        return None;
+    }
+
+    let slope = covariance / score_variance;
+    if slope >= 0.0 {
+        // Quality does not decrease with quantizer
+        return None;
+    }
+    let intercept = slope.mul_add(-mean_score, mean_log_quantizer);
+    let r_squared = covariance.powi(2) / (score_variance * log_quantizer_variance);
+    let confidence = r_squared * (1.0 - adjusted as f64 / n as f64);
+
+    Some((
+        slope.mul_add(xi, intercept).exp_m1(),
+        confidence.clamp(0.0, 1.0),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::{
@@ -406,6 +477,7 @@ mod tests {
         catmull_rom_interpolate as catmull_rom_interpolate_impl,
         cubic_polynomial_interpolate as cubic_polynomial_interpolate_impl,
         linear_interpolate as linear_interpolate_impl,
+        monotone_fit as monotone_fit_impl,
         natural_cubic_spline as natural_cubic_spline_impl,
         pchip_interpolate as pchip_interpolate_impl,
         quadratic_interpolate as quadratic_interpolate_impl,
@@ -927,4 +999,26 @@ mod tests {
         assert_eq!(akima_interpolate_impl(&x, &y, 70.0), None); // Below range
         assert_eq!(akima_interpolate_impl(&x, &y, 95.0), None); // Above range
     }
+
+    #[test]
+    fn monotone_fit() {
+        // Scores of ln(q + 1) = 5 - 0.05 * score, as they would be probed
+        let y = [10.0, 20.0, 30.0, 40.0, 50.0];
+        let x: Vec<f64> = y.iter().map(|q: &f64| (5.0 - q.ln_1p()) / 0.05).collect();
+        let (quantizer, confidence) =
+            monotone_fit_impl(&x, &y, (5.0 - 25.0_f64.ln_1p()) / 0.05).expect("fit should exist");
+        assert!((quantizer - 25.0).abs() < 1e-6);
+        assert!(confidence > 0.999);
+
+        // A noisy point that breaks monotonicity lowers the confidence
+        let mut noisy = x.clone();
+        noisy[3] = x[1];
+        let (quantizer, noisy_confidence) =
+            monotone_fit_impl(&noisy, &y, x[2]).expect("fit should exist");
+        assert!((20.0..40.0).contains(&quantizer));
+        assert!(noisy_confidence < confidence);
+
+        // Quality increasing with quantizer cannot be fitted
+        assert_eq!(monotone_fit_impl(&[80.0, 90.0], &[10.0, 20.0], 85.0), None);
+    }
 }
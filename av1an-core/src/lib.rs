@@ -26,11 +26,29 @@ use strum::{Display, EnumString, IntoStaticStr};
 use tracing::info;
 
 pub use crate::{
+    budget::parse_target_size,
     concat::ConcatMethod,
     context::Av1anContext,
     encoder::Encoder,
+    metrics::{
+        analysis::{measure_metric, write_metric_scores, AnalysisOptions, MetricScores},
+        expression::StatisticExpression,
+        statistics::FrameStatistics,
+    },
+    sampling::ProbingWindows,
+    scene_cache::default_scene_cache_dir,
+    scenes::export::SceneExport,
+    search_param::SearchParameter,
     settings::{EncodeArgs, InputPixelFormat, PixelFormat},
-    target_quality::{InterpolationMethod, TargetQuality},
+    target_quality::{
+        AlignmentCheck,
+        FloorStatistic,
+        FrameFloor,
+        InterpolationMethod,
+        QualityConstraint,
+        TargetFallback,
+        TargetQuality,
+    },
     util::read_in_dir,
 };
 use crate::{
@@ -40,25 +58,37 @@ use crate::{
 };
 
 mod broker;
+mod budget;
 mod chunk;
 mod concat;
 mod context;
+mod convex_hull;
 mod encoder;
 pub mod ffmpeg;
 mod metrics {
+    pub mod analysis;
     pub mod butteraugli;
+    pub mod expression;
+    pub mod native;
+    pub mod ssimulacra2;
     pub mod statistics;
     pub mod vmaf;
     pub mod xpsnr;
 }
 mod interpol;
 mod parse;
+mod probe_cache;
+mod probe_history;
 mod progress_bar;
+mod sampling;
+mod scene_cache;
 mod scene_detect;
 mod scenes;
+mod search_param;
 mod settings;
 mod split;
 mod target_quality;
+mod tq_report;
 mod util;
 pub mod vapoursynth;
 mod zones;
@@ -463,6 +493,67 @@ pub enum SplitMethod {
     None,
 }
 
+/// Format of the scene file given with `--scenes`
+#[derive(
+    Serialize,
+    Deserialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    EnumString,
+    IntoStaticStr,
+    Display,
+)]
+pub enum SceneFormat {
+    /// Guess the format from the extension and contents of the file
+    #[default]
+    #[strum(serialize = "auto")]
+    Auto,
+    /// Scenes JSON written by av1an
+    #[strum(serialize = "av1an")]
+    Av1an,
+    /// Scene list CSV written by PySceneDetect
+    #[strum(serialize = "pyscenedetect")]
+    PySceneDetect,
+    /// Log of the FFmpeg scdet or showinfo filter
+    #[strum(serialize = "ffmpeg")]
+    FFmpeg,
+    /// x264/x265 qpfile, where I and K frames start a scene
+    #[strum(serialize = "qpfile")]
+    Qpfile,
+    /// Matroska XML chapters or simple OGM chapters
+    #[strum(serialize = "chapters")]
+    Chapters,
+    /// List of frame numbers
+    #[strum(serialize = "frames")]
+    Frames,
+    /// List of timecodes
+    #[strum(serialize = "timecodes")]
+    Timecodes,
+}
+
+/// Format of a scene list written with `--sc-export`
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, EnumString, IntoStaticStr, Display,
+)]
+pub enum SceneExportFormat {
+    /// Matroska XML chapters, one chapter per scene
+    #[strum(serialize = "chapters")]
+    Chapters,
+    /// x264/x265 qpfile with a keyframe at the start of every scene
+    #[strum(serialize = "qpfile")]
+    Qpfile,
+    /// CMX 3600 edit decision list, one event per scene
+    #[strum(serialize = "edl")]
+    Edl,
+    /// List of the start timecodes of the scenes
+    #[strum(serialize = "timecodes")]
+    Timecodes,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, EnumString, IntoStaticStr, Display)]
 pub enum ScenecutMethod {
     #[strum(serialize = "fast")]
@@ -542,21 +633,50 @@ pub enum VmafFeature {
 }
 
 #[derive(
-    PartialEq, Eq, Copy, Clone, Serialize, Deserialize, Debug, Display, EnumString, IntoStaticStr,
+    PartialEq,
+    Eq,
+    Hash,
+    Copy,
+    Clone,
+    Serialize,
+    Deserialize,
+    Debug,
+    Display,
+    EnumString,
+    IntoStaticStr,
 )]
 pub enum TargetMetric {
     #[strum(serialize = "vmaf")]
     VMAF,
+    /// Calculated by Vapoursynth-HIP or VapourSynth-Zig Image Process when
+    /// they can be used, otherwise natively with slightly different scores
     #[strum(serialize = "ssimulacra2")]
     SSIMULACRA2,
+    /// Requires Vapoursynth-HIP or vapoursynth-julek-plugin, a native
+    /// implementation is out of scope
     #[strum(serialize = "butteraugli-inf")]
     ButteraugliINF,
+    /// Requires Vapoursynth-HIP, a native implementation is out of scope
     #[strum(serialize = "butteraugli-3")]
     Butteraugli3,
     #[strum(serialize = "xpsnr")]
     XPSNR,
     #[strum(serialize = "xpsnr-weighted")]
     XPSNRWeighted,
+    #[strum(serialize = "bitrate")]
+    Bitrate,
+    /// Luma PSNR in dB, calculated natively on a single thread from frames
+    /// decoded by the ffmpeg executable
+    #[strum(serialize = "psnr")]
+    PSNR,
+    /// Luma SSIM, calculated natively on a single thread from frames decoded
+    /// by the ffmpeg executable
+    #[strum(serialize = "ssim")]
+    SSIM,
+    /// Luma multi-scale SSIM, calculated natively on a single thread from
+    /// frames decoded by the ffmpeg executable
+    #[strum(serialize = "ms-ssim")]
+    MSSSIM,
 }
 
 /// Determine the optimal number of workers for an encoder
@@ -676,12 +796,19 @@ pub enum ProbingStatisticName {
     RootMeanSquare = 8,
     #[strum(serialize = "auto")]
     Automatic = 9,
+    #[strum(serialize = "expression")]
+    Expression = 10,
+    #[strum(serialize = "window-minimum")]
+    WindowMinimum = 11,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProbingStatistic {
-    pub name:  ProbingStatisticName,
-    pub value: Option<f64>,
+    pub name:       ProbingStatisticName,
+    pub value:      Option<f64>,
+    /// Expression evaluated with the `expression` statistic
+    #[serde(default)]
+    pub expression: Option<StatisticExpression>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -0,0 +1,612 @@
+use std::{
+    ffi::OsStr,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context};
+use num_traits::cast::ToPrimitive;
+use plotters::prelude::*;
+use serde::Serialize;
+use smallvec::SmallVec;
+
+use crate::{
+    ffmpeg::FFPixelFormat,
+    metrics::{
+        butteraugli::ButteraugliSubMetric,
+        native::{measure_native, NativeMetric},
+        statistics::{FrameStatistics, MetricStatistics},
+        vmaf::{read_vmaf_file, run_vmaf},
+        xpsnr::{read_xpsnr_file, run_xpsnr, XPSNRSubMetric},
+    },
+    ref_smallvec,
+    sampling::FrameSampling,
+    util::printable_base10_digits,
+    vapoursynth::{measure_butteraugli, measure_ssimulacra2, VSZipVersion, VapoursynthPlugins},
+    Input,
+    ProbingStatistic,
+    TargetMetric,
+    VmafFeature,
+};
+
+/// Settings of the metric pipelines used to analyze a whole encode
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions<'a> {
+    pub model:         Option<&'a Path>,
+    /// Resolution VMAF and XPSNR are calculated at, as `widthxheight`
+    pub res:           String,
+    /// Resolution the other metrics are calculated at, or the resolution of
+    /// the reference if None
+    pub probe_res:     Option<(u32, u32)>,
+    pub scaler:        &'a str,
+    pub filter:        Option<&'a str>,
+    pub threads:       usize,
+    pub vmaf_features: &'a [VmafFeature],
+    pub pix_format:    FFPixelFormat,
+    pub plugins:       Option<VapoursynthPlugins>,
+}
+
+/// Command piping the reference as y4m, and the arguments passed to vspipe
+pub(crate) fn reference_pipe(reference: &Input) -> (SmallVec<[&OsStr; 8]>, Vec<String>) {
+    match reference {
+        Input::Video {
+            ref path, ..
+        } => (
+            ref_smallvec!(OsStr, 8, [
+                "ffmpeg",
+                "-i",
+                path,
+                "-strict",
+                "-1",
+                "-f",
+                "yuv4mpegpipe",
+                "-"
+            ]),
+            vec![],
+        ),
+        Input::VapourSynth {
+            ref path,
+            vspipe_args,
+            ..
+        } => (
+            ref_smallvec!(OsStr, 8, ["vspipe", "-c", "y4m", path, "-"]),
+            vspipe_args.to_owned(),
+        ),
+    }
+}
+
+/// Calculates the `metric` score of every frame of `encoded` against
+/// `reference`, or against the frames of `frame_range` of it, with the same
+/// pipelines as target quality. VMAF and XPSNR write their logs to
+/// `stat_file`.
+pub(crate) fn frame_scores(
+    metric: TargetMetric,
+    encoded: &Path,
+    reference: &Input,
+    frame_range: Option<(usize, usize)>,
+    stat_file: &Path,
+    frame_rate: f64,
+    options: &AnalysisOptions,
+) -> anyhow::Result<Vec<f64>> {
+    let (pipe_cmd, vspipe_args) = reference_pipe(reference);
+    // The reference pipe is trimmed like the windows of a probe, while
+    // VapourSynth trims the source node itself
+    let pipe_sampling = frame_range.map_or(FrameSampling::Every(1), |range| {
+        FrameSampling::Windows(vec![range])
+    });
+    let sampling = FrameSampling::Every(1);
+    let frame_range = || -> anyhow::Result<(u32, u32)> {
+        let (start, end) = match frame_range {
+            Some(range) => range,
+            None => (0, reference.clip_info()?.num_frames),
+        };
+        Ok((start as u32, end as u32))
+    };
+
+    let scores = match metric {
+        TargetMetric::VMAF => {
+            run_vmaf(
+                encoded,
+                &pipe_cmd,
+                vspipe_args,
+                stat_file,
+                options.model,
+                &options.res,
+                options.scaler,
+                &pipe_sampling,
+                options.filter,
+                options.threads,
+                frame_rate,
+                false,
+                options.vmaf_features,
+            )?;
+            read_vmaf_file(stat_file)?
+        },
+        TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => {
+            run_xpsnr(
+                encoded,
+                &pipe_cmd,
+                vspipe_args,
+                stat_file,
+                &options.res,
+                options.scaler,
+                &pipe_sampling,
+                frame_rate,
+            )?;
+            let submetric = if metric == TargetMetric::XPSNR {
+                XPSNRSubMetric::Minimum
+            } else {
+                XPSNRSubMetric::Weighted
+            };
+            read_xpsnr_file(stat_file, submetric)?.1
+        },
+        TargetMetric::SSIMULACRA2 => {
+            let plugins = options.plugins.filter(|p| p.vship || p.vszip != VSZipVersion::None);
+            match plugins {
+                // The plugins need a VapourSynth source
+                Some(plugins) if reference.is_vapoursynth_script() => measure_ssimulacra2(
+                    reference,
+                    encoded,
+                    frame_range()?,
+                    options.probe_res,
+                    &sampling,
+                    plugins,
+                )?,
+                _ => measure_native(
+                    NativeMetric::Ssimulacra2,
+                    encoded,
+                    &pipe_cmd,
+                    vspipe_args,
+                    options.probe_res,
+                    options.scaler,
+                    &pipe_sampling,
+                    options.pix_format,
+                )?,
+            }
+        },
+        TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
+            let Some(plugins) = options.plugins else {
+                bail!("Butteraugli requires Vapoursynth to be installed");
+            };
+            measure_butteraugli(
+                if metric == TargetMetric::ButteraugliINF {
+                    ButteraugliSubMetric::InfiniteNorm
+                } else {
+                    ButteraugliSubMetric::ThreeNorm
+                },
+                reference,
+                encoded,
+                frame_range()?,
+                options.probe_res,
+                &sampling,
+                plugins,
+            )?
+        },
+        TargetMetric::PSNR | TargetMetric::SSIM | TargetMetric::MSSSIM => measure_native(
+            match metric {
+                TargetMetric::PSNR => NativeMetric::Psnr,
+                TargetMetric::SSIM => NativeMetric::Ssim,
+                _ => NativeMetric::MsSsim,
+            },
+            encoded,
+            &pipe_cmd,
+            vspipe_args,
+            options.probe_res,
+            options.scaler,
+            &pipe_sampling,
+            options.pix_format,
+        )?,
+        TargetMetric::Bitrate => bail!("Bitrate has no per-frame scores"),
+    };
+
+    Ok(scores)
+}
+
+/// Statistics of the scores of the frames of a single chunk
+#[derive(Debug, Clone, Serialize)]
+struct ChunkScores {
+    chunk:       usize,
+    start_frame: usize,
+    /// Exclusive
+    end_frame:   usize,
+    /// Scores of the chunk aggregated with the probing statistic
+    score:       f64,
+    statistics:  FrameStatistics,
+}
+
+/// Per-frame scores of a metric on the whole encode, aggregated per chunk
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct MetricReport {
+    metric:     TargetMetric,
+    statistic:  ProbingStatistic,
+    score:      f64,
+    statistics: FrameStatistics,
+    chunks:     Vec<ChunkScores>,
+    frames:     Vec<f64>,
+}
+
+impl MetricReport {
+    /// Aggregates `scores` over the whole encode and over each chunk, given
+    /// as `(start_frame, end_frame)` ranges with an exclusive end, with
+    /// `statistic` as well as into a summary
+    pub(crate) fn new(
+        metric: TargetMetric,
+        scores: Vec<f64>,
+        chunks: &[(usize, usize)],
+        statistic: &ProbingStatistic,
+        frame_rate: f64,
+    ) -> anyhow::Result<Self> {
+        if scores.is_empty() {
+            bail!("{metric} calculation returned no scores");
+        }
+
+        let mut chunk_scores = Vec::with_capacity(chunks.len());
+        for (chunk, &(start_frame, end_frame)) in chunks.iter().enumerate() {
+            // Chunks beyond the scored frames are left out if the frame counts
+            // do not match
+            let Some(scores) = scores.get(start_frame..end_frame.min(scores.len())) else {
+                continue;
+            };
+            if scores.is_empty() {
+                continue;
+            }
+            let mut statistics = MetricStatistics::new(scores.to_vec()).with_frame_rate(frame_rate);
+            chunk_scores.push(ChunkScores {
+                chunk,
+                start_frame,
+                end_frame,
+                score: statistics.aggregate(statistic, metric)?,
+                statistics: statistics.summary(),
+            });
+        }
+
+        let mut statistics = MetricStatistics::new(scores.clone()).with_frame_rate(frame_rate);
+        Ok(Self {
+            metric,
+            statistic: statistic.clone(),
+            score: statistics.aggregate(statistic, metric)?,
+            statistics: statistics.summary(),
+            chunks: chunk_scores,
+            frames: scores,
+        })
+    }
+
+    /// Index of the chunk containing every frame
+    fn frame_chunks(&self) -> impl Iterator<Item = Option<usize>> + '_ {
+        (0..self.frames.len()).map(|frame| {
+            self.chunks
+                .iter()
+                .find(|chunk| (chunk.start_frame..chunk.end_frame).contains(&frame))
+                .map(|chunk| chunk.chunk)
+        })
+    }
+
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,chunk,score\n");
+        for (frame, (chunk, score)) in self.frame_chunks().zip(&self.frames).enumerate() {
+            let chunk = chunk.map_or_else(String::new, |chunk| chunk.to_string());
+            writeln!(csv, "{frame},{chunk},{score}").expect("writing to a string should succeed");
+        }
+        csv
+    }
+
+    /// Path of the report written next to `encoded` with `extension`
+    fn path(&self, encoded: &Path, extension: &str) -> PathBuf {
+        encoded.with_extension(format!("{}.{extension}", self.metric))
+    }
+
+    /// Writes `<output>.<metric>.json`, `<output>.<metric>.csv` and
+    /// `<output>.<metric>.svg` next to `encoded`
+    pub(crate) fn write(&self, encoded: &Path) -> anyhow::Result<()> {
+        let json_path = self.path(encoded, "json");
+        fs::write(&json_path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", json_path.display()))?;
+
+        let csv_path = self.path(encoded, "csv");
+        fs::write(&csv_path, self.to_csv())
+            .with_context(|| format!("Failed to write {}", csv_path.display()))?;
+
+        let plot_path = self.path(encoded, "svg");
+        self.plot(&plot_path)
+            .with_context(|| format!("Failed to plot {}", plot_path.display()))
+    }
+
+    /// Plots the per-frame scores with the mean of every chunk, separated by
+    /// the chunk boundaries
+    fn plot(&self, plot_path: &Path) -> anyhow::Result<()> {
+        let length = self.frames.len() as u32;
+        let plot_width = 1600 + (printable_base10_digits(self.frames.len()) * 200);
+        let plot_height = 600;
+
+        // Identical frames may score infinitely high, e.g. with XPSNR
+        let (minimum, maximum) = self
+            .frames
+            .iter()
+            .filter(|score| score.is_finite())
+            .fold((f64::MAX, f64::MIN), |(minimum, maximum), &score| {
+                (minimum.min(score), maximum.max(score))
+            });
+        let (minimum, maximum) = if minimum >= maximum {
+            (minimum - 1.0, minimum + 1.0)
+        } else {
+            (minimum, maximum)
+        };
+
+        let root =
+            SVGBackend::new(plot_path.as_os_str(), (plot_width, plot_height)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .caption(self.metric.to_string(), ("sans-serif", 20))
+            .set_label_area_size(LabelAreaPosition::Bottom, (5).percent())
+            .set_label_area_size(LabelAreaPosition::Left, (5).percent())
+            .set_label_area_size(LabelAreaPosition::Right, (7).percent())
+            .set_label_area_size(LabelAreaPosition::Top, (5).percent())
+            .margin((1).percent())
+            .build_cartesian_2d(0_u32..length, minimum.floor()..maximum.ceil())?;
+
+        chart.configure_mesh().draw()?;
+
+        // Chunk boundaries
+        chart.draw_series(self.chunks.iter().skip(1).map(|chunk| {
+            let frame = chunk.start_frame as u32;
+            PathElement::new(
+                vec![(frame, minimum.floor()), (frame, maximum.ceil())],
+                BLACK.mix(0.2),
+            )
+        }))?;
+
+        let mean = self.statistics.mean;
+        chart
+            .draw_series(LineSeries::new((0..=length).map(|x| (x, mean)), BLACK))?
+            .label(format!("Mean: {mean}"))
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
+
+        let percentile_1 = self.statistics.percentile_1;
+        chart
+            .draw_series(LineSeries::new(
+                (0..=length).map(|x| (x, percentile_1)),
+                RED,
+            ))?
+            .label(format!("1%: {percentile_1}"))
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+
+        // Data
+        chart.draw_series(LineSeries::new(
+            (0..).zip(self.frames.iter()).map(|(x, y)| (x, y.clamp(minimum, maximum))),
+            BLUE,
+        ))?;
+
+        // Mean of every chunk
+        chart
+            .draw_series(self.chunks.iter().map(|chunk| {
+                let mean = chunk.statistics.mean;
+                PathElement::new(
+                    vec![(chunk.start_frame as u32, mean), (chunk.end_frame as u32, mean)],
+                    GREEN,
+                )
+            }))?
+            .label("Chunk mean")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        root.present()?;
+
+        Ok(())
+    }
+}
+
+/// Calculates `metric` on the whole of `encoded` and writes the per-frame
+/// scores with per-chunk aggregates next to it. `chunks` are the frame ranges
+/// of the chunks with an exclusive end, and the VMAF and XPSNR logs are kept in
+/// `temp`.
+#[expect(clippy::too_many_arguments)]
+pub(crate) fn analyze(
+    metric: TargetMetric,
+    encoded: &Path,
+    reference: &Input,
+    chunks: &[(usize, usize)],
+    temp: &Path,
+    frame_rate: f64,
+    statistic: &ProbingStatistic,
+    options: &AnalysisOptions,
+) -> anyhow::Result<()> {
+    println!(":: {metric} Run");
+
+    let stat_file = temp.join(format!("analysis_{metric}.log"));
+    let scores = frame_scores(
+        metric, encoded, reference, None, &stat_file, frame_rate, options,
+    )?;
+    MetricReport::new(metric, scores, chunks, statistic, frame_rate)?.write(encoded)
+}
+
+/// Scores of a metric of a distorted video against its reference
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricScores {
+    pub metric:     TargetMetric,
+    pub statistic:  ProbingStatistic,
+    /// Per-frame scores aggregated with `statistic`
+    pub score:      f64,
+    pub statistics: FrameStatistics,
+    pub frames:     Vec<f64>,
+}
+
+/// Calculates `metric` on `distorted` against `reference`, or against the
+/// frames of `frame_range` of it with an exclusive end, and aggregates the
+/// per-frame scores with `statistic`
+#[inline]
+pub fn measure_metric(
+    metric: TargetMetric,
+    distorted: &Path,
+    reference: &Input,
+    frame_range: Option<(usize, usize)>,
+    statistic: &ProbingStatistic,
+    options: &AnalysisOptions,
+) -> anyhow::Result<MetricScores> {
+    ensure!(
+        metric != TargetMetric::Bitrate,
+        "Bitrate cannot be measured as it has no per-frame scores"
+    );
+    if let Some((start, end)) = frame_range {
+        let frames = reference.clip_info()?.num_frames;
+        ensure!(
+            start < end && end <= frames,
+            "Frame range {start}-{end} is not within the {frames} frames of the reference"
+        );
+    }
+
+    let frame_rate = reference
+        .clip_info()?
+        .frame_rate
+        .to_f64()
+        .expect("frame rate should not be NaN");
+    // Every call gets its own directory, which is removed when it is dropped, so
+    // concurrent calls never share a stat file
+    let stat_dir = tempfile::Builder::new()
+        .prefix("av1an-metrics-")
+        .tempdir()
+        .context("Failed to create a temporary directory for the metric stat file")?;
+    let stat_file = stat_dir.path().join(format!("{metric}.log"));
+    let scores = frame_scores(
+        metric,
+        distorted,
+        reference,
+        frame_range,
+        &stat_file,
+        frame_rate,
+        options,
+    )?;
+    ensure!(
+        !scores.is_empty(),
+        "{metric} calculation returned no scores"
+    );
+
+    let mut statistics = MetricStatistics::new(scores.clone()).with_frame_rate(frame_rate);
+    Ok(MetricScores {
+        metric,
+        statistic: statistic.clone(),
+        score: statistics.aggregate(statistic, metric)?,
+        statistics: statistics.summary(),
+        frames: scores,
+    })
+}
+
+/// Writes the scores of every metric to `path` as a JSON array
+#[inline]
+pub fn write_metric_scores(path: &Path, scores: &[MetricScores]) -> anyhow::Result<()> {
+    fs::write(path, serde_json::to_string_pretty(scores)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metric_report_aggregates_chunks() {
+        let scores = vec![90.0, 92.0, 94.0, 80.0, 82.0];
+        let statistic = crate::TargetQuality::parse_probing_statistic("window-minimum=1").unwrap();
+        // The last chunk is beyond the scored frames
+        let report = MetricReport::new(
+            TargetMetric::SSIMULACRA2,
+            scores,
+            &[(0, 3), (3, 5), (5, 8)],
+            &statistic,
+            2.0,
+        )
+        .expect("report should be created");
+
+        assert_eq!(report.statistics.frames, 5);
+        // Windows of two frames
+        assert_eq!(report.score, 81.0);
+        assert_eq!(
+            report.chunks.iter().map(|chunk| chunk.score).collect::<Vec<_>>(),
+            [91.0, 81.0]
+        );
+        assert_eq!(
+            report
+                .chunks
+                .iter()
+                .map(|chunk| (chunk.chunk, chunk.statistics.mean, chunk.statistics.minimum))
+                .collect::<Vec<_>>(),
+            [(0, 92.0, 90.0), (1, 81.0, 80.0)]
+        );
+        assert_eq!(
+            report.to_csv(),
+            "frame,chunk,score\n0,0,90\n1,0,92\n2,0,94\n3,1,80\n4,1,82\n"
+        );
+        assert_eq!(
+            report.path(Path::new("output.mkv"), "csv"),
+            PathBuf::from("output.ssimulacra2.csv")
+        );
+
+        assert!(MetricReport::new(TargetMetric::VMAF, vec![], &[(0, 3)], &statistic, 2.0).is_err());
+    }
+
+    #[test]
+    fn metric_report_writes_per_frame_scores() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let statistic = crate::TargetQuality::parse_probing_statistic("mean")?;
+        let report = MetricReport::new(
+            TargetMetric::SSIMULACRA2,
+            vec![90.0, 92.0, 80.0, 84.0],
+            &[(0, 2), (2, 4)],
+            &statistic,
+            24.0,
+        )?;
+        report.write(&temp.path().join("output.mkv"))?;
+
+        assert_eq!(
+            fs::read_to_string(temp.path().join("output.ssimulacra2.csv"))?,
+            "frame,chunk,score\n0,0,90\n1,0,92\n2,1,80\n3,1,84\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(
+            temp.path().join("output.ssimulacra2.json"),
+        )?)?;
+        assert_eq!(
+            json["metric"],
+            serde_json::to_value(TargetMetric::SSIMULACRA2)?
+        );
+        assert_eq!(json["score"], 86.5);
+        assert_eq!(json["frames"], serde_json::json!([90.0, 92.0, 80.0, 84.0]));
+        let chunks = json["chunks"].as_array().map(Vec::as_slice).unwrap_or_default();
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|chunk| (
+                    chunk["chunk"].as_u64(),
+                    chunk["start_frame"].as_u64(),
+                    chunk["end_frame"].as_u64(),
+                    chunk["score"].as_f64()
+                ))
+                .collect::<Vec<_>>(),
+            [(Some(0), Some(0), Some(2), Some(91.0)), (Some(1), Some(2), Some(4), Some(82.0))]
+        );
+
+        let svg = fs::read_to_string(temp.path().join("output.ssimulacra2.svg"))?;
+        assert!(svg.starts_with("<svg"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn scores_aggregate_with_probing_statistic() {
+        let scores = vec![70.0, 80.0, 90.0, 100.0];
+        let aggregate = |stat: &str, metric| {
+            let statistic = crate::TargetQuality::parse_probing_statistic(stat).unwrap();
+            MetricStatistics::new(scores.clone()).aggregate(&statistic, metric).unwrap()
+        };
+
+        assert_eq!(aggregate("auto", TargetMetric::VMAF), 70.0);
+        assert_eq!(aggregate("auto", TargetMetric::SSIMULACRA2), 85.0);
+        assert_eq!(aggregate("percentile=50", TargetMetric::XPSNR), 90.0);
+        assert_eq!(aggregate("maximum", TargetMetric::PSNR), 100.0);
+    }
+}
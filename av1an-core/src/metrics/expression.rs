@@ -0,0 +1,430 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::statistics::MetricStatistics;
+
+/// Statistic of the per-frame scores used as a variable of an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    Mean,
+    Median,
+    Harmonic,
+    Mode,
+    Minimum,
+    Maximum,
+    StandardDeviation,
+    Variance,
+    RootMeanSquare,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "mean" | "average" => Self::Mean,
+            "median" => Self::Median,
+            "harmonic" => Self::Harmonic,
+            "mode" => Self::Mode,
+            "min" | "minimum" => Self::Minimum,
+            "max" | "maximum" => Self::Maximum,
+            "stddev" => Self::StandardDeviation,
+            "variance" => Self::Variance,
+            "rms" => Self::RootMeanSquare,
+            _ => return None,
+        })
+    }
+
+    fn evaluate(self, statistics: &mut MetricStatistics) -> f64 {
+        match self {
+            Self::Mean => statistics.mean(),
+            Self::Median => statistics.median(),
+            Self::Harmonic => statistics.harmonic_mean(),
+            Self::Mode => statistics.mode(),
+            Self::Minimum => statistics.minimum(),
+            Self::Maximum => statistics.maximum(),
+            Self::StandardDeviation => statistics.standard_deviation(),
+            Self::Variance => statistics.variance(),
+            Self::RootMeanSquare => statistics.root_mean_square(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Min,
+    Max,
+    Abs,
+    Clamp,
+    Percentile,
+    WindowMinimum,
+    WindowPercentile,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "min" => Self::Min,
+            "max" => Self::Max,
+            "abs" => Self::Abs,
+            "clamp" => Self::Clamp,
+            "percentile" => Self::Percentile,
+            "window_min" => Self::WindowMinimum,
+            "window_percentile" => Self::WindowPercentile,
+            _ => return None,
+        })
+    }
+
+    /// Range of the number of arguments the function takes
+    const fn arity(self) -> (usize, usize) {
+        match self {
+            Self::Min | Self::Max => (1, usize::MAX),
+            Self::Abs | Self::Percentile | Self::WindowMinimum => (1, 1),
+            Self::WindowPercentile => (2, 2),
+            Self::Clamp => (3, 3),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expression {
+    Number(f64),
+    Variable(Variable),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+impl Expression {
+    fn evaluate(&self, statistics: &mut MetricStatistics) -> anyhow::Result<f64> {
+        Ok(match self {
+            Self::Number(number) => *number,
+            Self::Variable(variable) => variable.evaluate(statistics),
+            Self::Negate(operand) => -operand.evaluate(statistics)?,
+            Self::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(statistics)?, right.evaluate(statistics)?);
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                }
+            },
+            Self::Call(function, arguments) => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| argument.evaluate(statistics))
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                match (function, &arguments[..]) {
+                    (Function::Min, _) => arguments.iter().copied().fold(f64::INFINITY, f64::min),
+                    (Function::Max, _) => {
+                        arguments.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+                    },
+                    (Function::Abs, &[value]) => value.abs(),
+                    (Function::Clamp, &[value, low, high]) => value.max(low).min(high),
+                    (Function::Percentile, &[percentile]) => {
+                        statistics.percentile(percentile.clamp(0.0, 100.0) as usize)
+                    },
+                    (Function::WindowMinimum, &[seconds]) => statistics.window_minimum(seconds)?,
+                    (Function::WindowPercentile, &[seconds, percentile]) => statistics
+                        .window_percentile(seconds, percentile.clamp(0.0, 100.0) as usize)?,
+                    _ => unreachable!("arity is checked when parsing"),
+                }
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Operator(Operator),
+    OpenParenthesis,
+    CloseParenthesis,
+    Comma,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    // Collects the characters of a number or identifier
+    fn take_while(
+        chars: &mut std::iter::Peekable<std::str::Chars>,
+        predicate: impl Fn(char) -> bool,
+    ) -> String {
+        let mut word = String::new();
+        while let Some(&c) = chars.peek().filter(|&&c| predicate(c)) {
+            word.push(c);
+            chars.next();
+        }
+        word
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            },
+            '0'..='9' | '.' => {
+                let number = take_while(&mut chars, |c| c.is_ascii_digit() || c == '.');
+                tokens.push(Token::Number(
+                    number.parse().map_err(|_| format!("Invalid number: {number}"))?,
+                ));
+                continue;
+            },
+            'a'..='z' | '_' => {
+                tokens.push(Token::Identifier(take_while(&mut chars, |c| {
+                    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'
+                })));
+                continue;
+            },
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '(' => Token::OpenParenthesis,
+            ')' => Token::CloseParenthesis,
+            ',' => Token::Comma,
+            c => return Err(format!("Unexpected character: {c}")),
+        };
+        tokens.push(token);
+        chars.next();
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser of the grammar
+///
+/// ```text
+/// sum     = product (("+" | "-") product)*
+/// product = unary (("*" | "/") unary)*
+/// unary   = "-" unary | primary
+/// primary = number | variable | function "(" sum ("," sum)* ")" | "(" sum ")"
+/// ```
+struct Parser {
+    tokens:   Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == *expected => Ok(()),
+            Some(token) => Err(format!("Expected {expected:?}, found {token:?}")),
+            None => Err(format!("Expected {expected:?} at the end")),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expression, String> {
+        let mut expression = self.product()?;
+        while let Some(&Token::Operator(operator @ (Operator::Add | Operator::Subtract))) =
+            self.peek()
+        {
+            self.next();
+            expression =
+                Expression::Binary(operator, Box::new(expression), Box::new(self.product()?));
+        }
+        Ok(expression)
+    }
+
+    fn product(&mut self) -> Result<Expression, String> {
+        let mut expression = self.unary()?;
+        while let Some(&Token::Operator(operator @ (Operator::Multiply | Operator::Divide))) =
+            self.peek()
+        {
+            self.next();
+            expression =
+                Expression::Binary(operator, Box::new(expression), Box::new(self.unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.peek() == Some(&Token::Operator(Operator::Subtract)) {
+            self.next();
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::OpenParenthesis) => {
+                let expression = self.sum()?;
+                self.expect(&Token::CloseParenthesis)?;
+                Ok(expression)
+            },
+            // `min` and `max` are functions when called and statistics otherwise
+            Some(Token::Identifier(name)) if self.peek() == Some(&Token::OpenParenthesis) => {
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| format!("Unknown function: {name}"))?;
+                self.next();
+                let mut arguments = vec![self.sum()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.next();
+                    arguments.push(self.sum()?);
+                }
+                self.expect(&Token::CloseParenthesis)?;
+
+                let (minimum, maximum) = function.arity();
+                if !(minimum..=maximum).contains(&arguments.len()) {
+                    return Err(format!(
+                        "{name} takes {minimum} to {maximum} arguments, but {} were given",
+                        arguments.len()
+                    ));
+                }
+                if let [Expression::Number(percentile)] = arguments[..] {
+                    if function == Function::Percentile && !(0.0..=100.0).contains(&percentile) {
+                        return Err(format!("Percentile {percentile} is not between 0 and 100"));
+                    }
+                }
+                Ok(Expression::Call(function, arguments))
+            },
+            Some(Token::Identifier(name)) => Variable::from_name(&name)
+                .map(Expression::Variable)
+                .ok_or_else(|| format!("Unknown statistic: {name}")),
+            Some(token) => Err(format!("Unexpected {token:?}")),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Expression over the statistics of the per-frame scores of a probe, such as
+/// `min(percentile(5), mean - 1.5*stddev)`. It is parsed once and evaluated
+/// for every probe, and serialized as its source.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct StatisticExpression {
+    source:     String,
+    expression: Expression,
+}
+
+impl StatisticExpression {
+    #[inline]
+    pub fn evaluate(&self, statistics: &mut MetricStatistics) -> anyhow::Result<f64> {
+        self.expression.evaluate(statistics)
+    }
+}
+
+impl FromStr for StatisticExpression {
+    type Err = String;
+
+    #[inline]
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let source = source.trim().to_lowercase();
+        let mut parser = Parser {
+            tokens:   tokenize(&source)?,
+            position: 0,
+        };
+        let expression = parser.sum()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {token:?} after the expression"));
+        }
+
+        Ok(Self {
+            source,
+            expression,
+        })
+    }
+}
+
+impl TryFrom<String> for StatisticExpression {
+    type Error = String;
+
+    #[inline]
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        source.parse()
+    }
+}
+
+impl From<StatisticExpression> for String {
+    #[inline]
+    fn from(expression: StatisticExpression) -> Self {
+        expression.source
+    }
+}
+
+impl fmt::Display for StatisticExpression {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statistic_expressions() {
+        let evaluate = |source: &str| {
+            let expression: StatisticExpression = source.parse().unwrap();
+            // Two scores per second
+            let mut statistics =
+                MetricStatistics::new(vec![60.0, 80.0, 80.0, 90.0, 90.0]).with_frame_rate(2.0);
+            expression.evaluate(&mut statistics).unwrap()
+        };
+
+        assert_eq!(evaluate("mean"), 80.0);
+        assert_eq!(evaluate("0.5*mean + 0.5 * min"), 70.0);
+        assert_eq!(evaluate("min(percentile(0), mean - 0.5*stddev)"), 60.0);
+        assert_eq!(evaluate("max(min, median - (max - min) / 10)"), 77.0);
+        assert_eq!(evaluate("-clamp(abs(min - max), 0, 20)"), -20.0);
+        // Windows of one second average to 70, 80, 85 and 90
+        assert_eq!(evaluate("window_min(1)"), 70.0);
+        assert_eq!(evaluate("window_percentile(1, 50)"), 85.0);
+        assert_eq!(evaluate("window_min(10)"), 80.0);
+        assert!("window_min(1)"
+            .parse::<StatisticExpression>()
+            .unwrap()
+            .evaluate(&mut MetricStatistics::new(vec![80.0]))
+            .is_err());
+
+        for invalid in [
+            "",
+            "mean +",
+            "mean mean",
+            "percentile",
+            "percentile(101)",
+            "clamp(mean, 0)",
+            "window_percentile(1)",
+            "sqrt(mean)",
+            "mean % 2",
+            "(mean",
+        ] {
+            assert!(
+                invalid.parse::<StatisticExpression>().is_err(),
+                "{invalid} should not parse"
+            );
+        }
+
+        let expression: StatisticExpression = "MIN(percentile(5), mean)".parse().unwrap();
+        let json = serde_json::to_string(&expression).unwrap();
+        assert_eq!(json, "\"min(percentile(5), mean)\"");
+        assert_eq!(
+            serde_json::from_str::<StatisticExpression>(&json).unwrap(),
+            expression
+        );
+    }
+}
@@ -0,0 +1,565 @@
+use std::{
+    ffi::OsStr,
+    io::Read,
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use av_decoders::{Decoder, DecoderError, DecoderImpl, Y4mDecoder};
+
+use crate::{
+    ffmpeg::FFPixelFormat,
+    metrics::ssimulacra2::{ssimulacra2, Image},
+    sampling::FrameSampling,
+};
+
+/// PSNR reported for identical frames, which have no error
+const PSNR_CAP: f64 = 100.0;
+
+/// Frames of the encode compared to the reference when checking the alignment
+const ALIGNMENT_FRAMES: usize = 8;
+
+/// Largest offset in frames looked for when checking the alignment
+const MAX_ALIGNMENT_OFFSET: usize = 2;
+
+/// Mean PSNR in dB by which an offset has to beat no offset to be reported
+const ALIGNMENT_MARGIN: f64 = 1.0;
+
+/// Weights of the scales of MS-SSIM, from the finest to the coarsest
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// Metrics computed in Rust on a single thread, without any FFmpeg filter or
+/// VapourSynth plugin. The frames are still decoded and scaled by the ffmpeg
+/// executable. PSNR, SSIM and MS-SSIM only compare the luma plane. A native
+/// Butteraugli is out of scope, so it still needs the VapourSynth plugins and
+/// a VapourSynth chunk method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NativeMetric {
+    Psnr,
+    Ssim,
+    MsSsim,
+    Ssimulacra2,
+}
+
+impl NativeMetric {
+    /// Pixel format both videos are decoded to. SSIMULACRA2 compares colors,
+    /// so it needs full resolution chroma.
+    const fn pix_format(self, pix_format: FFPixelFormat) -> FFPixelFormat {
+        match self {
+            Self::Psnr | Self::Ssim | Self::MsSsim => pix_format,
+            Self::Ssimulacra2 => FFPixelFormat::YUV444P12LE,
+        }
+    }
+
+    /// Number of planes the metric is computed on
+    const fn planes(self) -> usize {
+        match self {
+            Self::Psnr | Self::Ssim | Self::MsSsim => 1,
+            Self::Ssimulacra2 => 3,
+        }
+    }
+}
+
+/// Plane with samples normalized to `0.0..=1.0`
+#[derive(Debug, Clone)]
+struct Plane {
+    width:  usize,
+    height: usize,
+    data:   Vec<f32>,
+}
+
+impl Plane {
+    fn from_rows<'a>(
+        rows: impl Iterator<Item = &'a [u16]>,
+        width: usize,
+        bit_depth: usize,
+    ) -> Self {
+        let peak = ((1u32 << bit_depth) - 1) as f32;
+        let data: Vec<f32> = rows
+            .flat_map(|row| row[..width].iter().map(|&sample| f32::from(sample) / peak))
+            .collect();
+
+        Self {
+            width,
+            height: data.len() / width,
+            data,
+        }
+    }
+
+    /// Halves the resolution by averaging 2x2 blocks
+    fn downsample(&self) -> Self {
+        let (width, height) = (self.width / 2, self.height / 2);
+        let mut data = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let top = &self.data[2 * y * self.width..];
+            let bottom = &self.data[(2 * y + 1) * self.width..];
+            for x in 0..width {
+                data.push((top[2 * x] + top[2 * x + 1] + bottom[2 * x] + bottom[2 * x + 1]) / 4.0);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            data,
+        }
+    }
+}
+
+/// Luma PSNR in dB, capped at [`PSNR_CAP`]
+fn psnr(reference: &Plane, distorted: &Plane) -> f64 {
+    let squared_error: f64 = reference
+        .data
+        .iter()
+        .zip(&distorted.data)
+        .map(|(&a, &b)| f64::from(a - b).powi(2))
+        .sum();
+    let mse = squared_error / reference.data.len() as f64;
+    if mse == 0.0 {
+        return PSNR_CAP;
+    }
+
+    (-10.0 * mse.log10()).min(PSNR_CAP)
+}
+
+/// Mean SSIM and mean contrast-structure term over 8x8 windows with a stride
+/// of 4, as in FFmpeg's `ssim` filter
+fn ssim_components(reference: &Plane, distorted: &Plane) -> (f64, f64) {
+    const WINDOW: usize = 8;
+    const STRIDE: usize = 4;
+    const C1: f64 = 0.01 * 0.01;
+    const C2: f64 = 0.03 * 0.03;
+
+    let (width, height) = (reference.width, reference.height);
+    if width < WINDOW || height < WINDOW {
+        return (1.0, 1.0);
+    }
+
+    let (mut ssim_sum, mut cs_sum, mut windows) = (0.0, 0.0, 0usize);
+    let n = (WINDOW * WINDOW) as f64;
+    for y in (0..=height - WINDOW).step_by(STRIDE) {
+        for x in (0..=width - WINDOW).step_by(STRIDE) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + WINDOW {
+                let start = row * width + x;
+                for (&a, &b) in reference.data[start..start + WINDOW]
+                    .iter()
+                    .zip(&distorted.data[start..start + WINDOW])
+                {
+                    let (a, b) = (f64::from(a), f64::from(b));
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let variance_a = mean_a.mul_add(-mean_a, sum_aa / n);
+            let variance_b = mean_b.mul_add(-mean_b, sum_bb / n);
+            let covariance = mean_a.mul_add(-mean_b, sum_ab / n);
+
+            let luminance = (2.0 * mean_a).mul_add(mean_b, C1)
+                / mean_a.mul_add(mean_a, mean_b.mul_add(mean_b, C1));
+            let contrast_structure =
+                2.0f64.mul_add(covariance, C2) / (variance_a + variance_b + C2);
+            ssim_sum += luminance * contrast_structure;
+            cs_sum += contrast_structure;
+            windows += 1;
+        }
+    }
+
+    (ssim_sum / windows as f64, cs_sum / windows as f64)
+}
+
+/// Multi-scale SSIM over up to 5 scales. Scales smaller than a window are
+/// skipped and the weights of the remaining scales renormalized.
+fn ms_ssim(reference: &Plane, distorted: &Plane) -> f64 {
+    let mut scales = vec![(reference.clone(), distorted.clone())];
+    while scales.len() < MS_SSIM_WEIGHTS.len() {
+        let (reference, distorted) = scales.last().expect("scales are not empty");
+        if reference.width < 16 || reference.height < 16 {
+            break;
+        }
+        scales.push((reference.downsample(), distorted.downsample()));
+    }
+
+    let weights = &MS_SSIM_WEIGHTS[..scales.len()];
+    let total_weight: f64 = weights.iter().sum();
+    let last = scales.len() - 1;
+    scales
+        .iter()
+        .zip(weights)
+        .enumerate()
+        .map(|(i, ((reference, distorted), weight))| {
+            let (ssim, contrast_structure) = ssim_components(reference, distorted);
+            let term = if i == last { ssim } else { contrast_structure };
+            term.max(0.0).powf(weight / total_weight)
+        })
+        .product()
+}
+
+fn score(metric: NativeMetric, reference: &[Plane], distorted: &[Plane], bit_depth: usize) -> f64 {
+    match metric {
+        NativeMetric::Psnr => psnr(&reference[0], &distorted[0]),
+        NativeMetric::Ssim => ssim_components(&reference[0], &distorted[0]).0,
+        NativeMetric::MsSsim => ms_ssim(&reference[0], &distorted[0]),
+        NativeMetric::Ssimulacra2 => {
+            let to_image = |planes: &[Plane]| {
+                Image::from_yuv(
+                    planes[0].width,
+                    planes[0].height,
+                    [&planes[0].data, &planes[1].data, &planes[2].data],
+                    bit_depth,
+                )
+            };
+            ssimulacra2(&to_image(reference), &to_image(distorted))
+        },
+    }
+}
+
+/// Spawns FFmpeg converting `input` to a y4m stream on its stdout
+fn spawn_y4m_pipe(
+    input: &OsStr,
+    stdin: Option<Stdio>,
+    filters: &[String],
+    pix_format: FFPixelFormat,
+) -> anyhow::Result<(Child, Decoder)> {
+    let mut cmd = Command::new("ffmpeg");
+    cmd.args(["-loglevel", "error", "-hide_banner", "-i"]);
+    cmd.arg(input);
+    if !filters.is_empty() {
+        cmd.args(["-vf", &filters.join(",")]);
+    }
+    cmd.args([
+        "-vsync",
+        "0",
+        "-pix_fmt",
+        pix_format.to_pix_fmt_string(),
+        "-strict",
+        "-1",
+        "-f",
+        "yuv4mpegpipe",
+        "-",
+    ]);
+    cmd.stdin(stdin.unwrap_or_else(Stdio::null));
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::null());
+
+    let mut child = cmd.spawn().context("Failed to spawn FFmpeg")?;
+    let stdout = child.stdout.take().expect("ffmpeg should have stdout");
+    let decoder = Decoder::from_decoder_impl(DecoderImpl::Y4m(Y4mDecoder::new(
+        Box::new(stdout) as Box<dyn Read>
+    )?))?;
+
+    Ok((child, decoder))
+}
+
+/// Reads the first `planes` planes of the next frame, or None at the end of
+/// the stream
+fn read_planes(decoder: &mut Decoder, planes: usize) -> anyhow::Result<Option<Vec<Plane>>> {
+    let bit_depth = decoder.get_video_details().bit_depth;
+    match decoder.read_video_frame::<u16>() {
+        Ok(frame) => Ok(Some(
+            frame.planes[..planes]
+                .iter()
+                .map(|plane| Plane::from_rows(plane.rows_iter(), plane.cfg.width, bit_depth))
+                .collect(),
+        )),
+        Err(DecoderError::EndOfFile) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Spawns the reference pipe followed by FFmpeg keeping the sampled frames and
+/// applying `reference_filters`, and FFmpeg scaling `encoded` to the resolution
+/// of the reference. Returns the processes and the decoders of the reference
+/// and of the encode.
+fn spawn_comparison(
+    encoded: &Path,
+    reference_pipe_cmd: &[impl AsRef<OsStr>],
+    vspipe_args: Vec<String>,
+    sampling: &FrameSampling,
+    reference_filters: &[String],
+    scaler: &str,
+    pix_format: FFPixelFormat,
+) -> anyhow::Result<([Child; 3], Decoder, Decoder)> {
+    let mut source_pipe = if let [cmd, args @ ..] = reference_pipe_cmd {
+        let mut source_pipe = Command::new(cmd);
+        // Append vspipe python arguments to the environment if there are any
+        for arg in vspipe_args {
+            source_pipe.args(["-a", &arg]);
+        }
+        source_pipe.args(args);
+        source_pipe.stdout(Stdio::piped());
+        source_pipe.stderr(Stdio::null());
+        source_pipe.spawn()?
+    } else {
+        unreachable!()
+    };
+
+    let mut filters = Vec::new();
+    let sampling_filter = sampling.ffmpeg_reference_filter();
+    if let Some(filter) = sampling_filter.strip_suffix(',') {
+        filters.push(filter.to_string());
+    }
+    filters.extend_from_slice(reference_filters);
+    let (reference_ffmpeg, reference) = spawn_y4m_pipe(
+        OsStr::new("-"),
+        Some(source_pipe.stdout.take().expect("source_pipe stdout should exist").into()),
+        &filters,
+        pix_format,
+    )?;
+
+    let (width, height) = {
+        let details = reference.get_video_details();
+        (details.width, details.height)
+    };
+    let (distorted_ffmpeg, distorted) = spawn_y4m_pipe(
+        encoded.as_os_str(),
+        None,
+        &[format!("scale={width}:{height}:flags={scaler}")],
+        pix_format,
+    )?;
+
+    Ok((
+        [source_pipe, reference_ffmpeg, distorted_ffmpeg],
+        reference,
+        distorted,
+    ))
+}
+
+/// Calculates the per-frame `metric` scores of `encoded` against the sampled
+/// frames of the reference pipe. FFmpeg decodes both to y4m streams, which
+/// are read with av-decoders, with the reference scaled to `res` if given and
+/// the encode scaled to the resolution of the reference.
+#[expect(clippy::too_many_arguments)]
+pub fn measure_native(
+    metric: NativeMetric,
+    encoded: &Path,
+    reference_pipe_cmd: &[impl AsRef<OsStr>],
+    vspipe_args: Vec<String>,
+    res: Option<(u32, u32)>,
+    scaler: &str,
+    sampling: &FrameSampling,
+    pix_format: FFPixelFormat,
+) -> anyhow::Result<Vec<f64>> {
+    let pix_format = metric.pix_format(pix_format);
+    let mut reference_filters = Vec::new();
+    if let Some((width, height)) = res {
+        reference_filters.push(format!("scale={width}:{height}:flags={scaler}"));
+    }
+    let (mut children, mut reference, mut distorted) = spawn_comparison(
+        encoded,
+        reference_pipe_cmd,
+        vspipe_args,
+        sampling,
+        &reference_filters,
+        scaler,
+        pix_format,
+    )?;
+    let bit_depth = reference.get_video_details().bit_depth;
+
+    let mut scores = Vec::new();
+    let result = loop {
+        match (
+            read_planes(&mut reference, metric.planes()),
+            read_planes(&mut distorted, metric.planes()),
+        ) {
+            (Ok(Some(reference)), Ok(Some(distorted))) => {
+                scores.push(score(metric, &reference, &distorted, bit_depth));
+            },
+            (Ok(None), Ok(None)) => break Ok(()),
+            (Ok(_), Ok(_)) => {
+                break Err(anyhow::anyhow!(
+                    "Frame count mismatch between the reference and {} after {} frames",
+                    encoded.display(),
+                    scores.len()
+                ))
+            },
+            (Err(e), _) | (_, Err(e)) => break Err(e),
+        }
+    };
+
+    for child in &mut children {
+        if result.is_err() {
+            child.kill().ok();
+        }
+        child.wait().ok();
+    }
+    result?;
+    if scores.is_empty() {
+        bail!("No frames were decoded from {}", encoded.display());
+    }
+
+    Ok(scores)
+}
+
+/// Frame offset between an encode and its reference, found by comparing their
+/// first frames at every offset
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FrameAlignment {
+    /// Frames the reference is ahead of the encode, 0 if they are aligned
+    pub offset:       isize,
+    /// Mean luma PSNR of the frames compared without any offset
+    pub aligned_psnr: f64,
+    /// Mean luma PSNR of the frames compared at `offset`
+    pub best_psnr:    f64,
+}
+
+/// Finds the offset of at most `max_offset` frames at which `distorted` matches
+/// `reference` best. Another offset has to beat no offset by
+/// [`ALIGNMENT_MARGIN`] so that static scenes are considered aligned.
+fn best_alignment(reference: &[Plane], distorted: &[Plane], max_offset: usize) -> FrameAlignment {
+    let max_offset = max_offset as isize;
+    // The same frames of the encode are compared at every offset
+    let frames = max_offset as usize..distorted.len().saturating_sub(max_offset as usize);
+    let mean_psnr = |offset: isize| {
+        let (sum, count) = frames
+            .clone()
+            .filter_map(|frame| {
+                let reference = reference.get(frame.checked_add_signed(offset)?)?;
+                Some(psnr(reference, &distorted[frame]))
+            })
+            .fold((0.0, 0), |(sum, count), psnr| (sum + psnr, count + 1));
+        if count == 0 {
+            f64::NEG_INFINITY
+        } else {
+            sum / f64::from(count)
+        }
+    };
+
+    let aligned_psnr = mean_psnr(0);
+    let (offset, best_psnr) = (-max_offset..=max_offset)
+        .map(|offset| (offset, mean_psnr(offset)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("offsets should not be empty");
+
+    if best_psnr > aligned_psnr + ALIGNMENT_MARGIN {
+        FrameAlignment {
+            offset,
+            aligned_psnr,
+            best_psnr,
+        }
+    } else {
+        FrameAlignment {
+            offset: 0,
+            aligned_psnr,
+            best_psnr: aligned_psnr,
+        }
+    }
+}
+
+/// Compares the first [`ALIGNMENT_FRAMES`] frames of `encoded` to the sampled
+/// frames of the reference pipe at offsets of up to [`MAX_ALIGNMENT_OFFSET`]
+/// frames in either direction. Both are downscaled so that the check is cheap.
+pub(crate) fn check_alignment(
+    encoded: &Path,
+    reference_pipe_cmd: &[impl AsRef<OsStr>],
+    vspipe_args: Vec<String>,
+    sampling: &FrameSampling,
+) -> anyhow::Result<FrameAlignment> {
+    let (mut children, mut reference, mut distorted) = spawn_comparison(
+        encoded,
+        reference_pipe_cmd,
+        vspipe_args,
+        sampling,
+        &["scale=320:-2".to_string()],
+        "bilinear",
+        FFPixelFormat::YUV420P,
+    )?;
+
+    let frames = ALIGNMENT_FRAMES + 2 * MAX_ALIGNMENT_OFFSET;
+    let read_frames = |decoder: &mut Decoder| -> anyhow::Result<Vec<Plane>> {
+        let mut planes = Vec::with_capacity(frames);
+        while planes.len() < frames {
+            match read_planes(decoder, 1)? {
+                Some(mut frame) => planes.push(frame.swap_remove(0)),
+                None => break,
+            }
+        }
+        Ok(planes)
+    };
+    let result = read_frames(&mut reference)
+        .and_then(|reference| Ok((reference, read_frames(&mut distorted)?)));
+
+    // Only the first frames are needed
+    for child in &mut children {
+        child.kill().ok();
+        child.wait().ok();
+    }
+    let (reference, distorted) = result?;
+    if distorted.len() <= 2 * MAX_ALIGNMENT_OFFSET {
+        bail!("Too few frames were decoded from {}", encoded.display());
+    }
+
+    Ok(best_alignment(&reference, &distorted, MAX_ALIGNMENT_OFFSET))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn native_metrics_of_identical_and_distorted_planes() {
+        let (width, height) = (64, 48);
+        let rows: Vec<Vec<u16>> = (0..height)
+            .map(|y| (0..width).map(|x| ((x * 3 + y * 5) % 256) as u16).collect())
+            .collect();
+        let reference = Plane::from_rows(rows.iter().map(Vec::as_slice), width, 8);
+        assert_eq!((reference.width, reference.height), (64, 48));
+
+        assert!((psnr(&reference, &reference) - PSNR_CAP).abs() < f64::EPSILON);
+        assert!((ssim_components(&reference, &reference).0 - 1.0).abs() < 1e-9);
+        assert!((ms_ssim(&reference, &reference) - 1.0).abs() < 1e-9);
+
+        let noisy: Vec<Vec<u16>> = rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(x, &v)| {
+                        if x % 2 == 0 {
+                            v.saturating_add(8).min(255)
+                        } else {
+                            v.saturating_sub(8)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let distorted = Plane::from_rows(noisy.iter().map(Vec::as_slice), width, 8);
+        let distorted_psnr = psnr(&reference, &distorted);
+        assert!(distorted_psnr > 25.0 && distorted_psnr < 35.0);
+        let ssim = ssim_components(&reference, &distorted).0;
+        assert!(ssim > 0.0 && ssim < 1.0);
+        let ms_ssim = ms_ssim(&reference, &distorted);
+        assert!(ms_ssim > 0.0 && ms_ssim < 1.0);
+    }
+
+    #[test]
+    fn best_alignment_finds_shifted_frames() {
+        let (width, height) = (16, 16);
+        let frames: Vec<Plane> = (0..12)
+            .map(|frame| {
+                let rows: Vec<Vec<u16>> = (0..height)
+                    .map(|y| {
+                        (0..width).map(|x| ((x * 7 + y * 3 + frame * 37) % 256) as u16).collect()
+                    })
+                    .collect();
+                Plane::from_rows(rows.iter().map(Vec::as_slice), width, 8)
+            })
+            .collect();
+
+        let aligned = best_alignment(&frames, &frames, 2);
+        assert_eq!(aligned.offset, 0);
+
+        // The encode is missing the first frame of the reference
+        let shifted = best_alignment(&frames, &frames[1..], 2);
+        assert_eq!(shifted.offset, 1);
+        assert!(shifted.best_psnr > shifted.aligned_psnr + ALIGNMENT_MARGIN);
+    }
+}
@@ -0,0 +1,348 @@
+//! SSIMULACRA2 computed in Rust, following the reference implementation in
+//! libjxl, so that it can be used without any VapourSynth plugin
+
+/// Number of scales the image is compared at, each half the size of the
+/// previous one
+const NUM_SCALES: usize = 6;
+
+/// Standard deviation of the Gaussian blur used for the local statistics
+const BLUR_SIGMA: f64 = 1.5;
+
+/// Weights of the SSIM, artifact and detail loss features with norms 1 and 4,
+/// one row per scale of every XYB channel
+#[rustfmt::skip]
+const WEIGHTS: [f64; 108] = [
+    // X
+    0.0, 0.000_737_660_670_740_658_6, 0.0, 0.0, 0.000_779_348_168_286_730_9, 0.0,
+    0.0, 0.000_437_115_573_010_737_9, 0.0, 1.104_172_642_665_734_6, 0.000_662_848_341_292_71, 0.000_152_316_327_837_187_52,
+    0.0, 0.001_640_643_745_659_975_4, 0.0, 1.842_245_552_053_929_8, 11.441_172_603_757_666, 0.0,
+    0.000_798_910_943_601_516_3, 0.000_176_816_438_078_653, 0.0, 1.878_759_497_954_638_7, 10.949_069_906_051_42, 0.0,
+    0.000_728_934_699_150_807_2, 0.967_793_708_062_683_3, 0.0, 0.000_140_034_242_854_358_84, 0.998_176_697_785_496_7, 0.000_319_497_559_344_350_53,
+    0.000_455_099_211_379_206_3, 0.0, 0.0, 0.001_364_876_616_324_339_8, 0.0, 0.0,
+    // Y
+    0.0, 0.0, 0.0, 7.466_890_328_078_848, 0.0, 17.445_833_984_131_262,
+    0.000_623_560_163_404_146_6, 0.0, 0.0, 6.683_678_146_179_332, 0.000_377_244_079_796_112_96, 1.027_889_937_768_264,
+    225.205_153_008_492_74, 0.0, 0.0, 19.213_238_186_143_016, 0.001_140_152_458_661_836_1, 0.001_237_755_635_509_985,
+    176.393_175_984_506_94, 0.0, 0.0, 24.433_009_998_704_76, 0.285_208_026_121_177_57, 0.000_448_543_692_476_037_24,
+    0.0, 0.0, 0.0, 34.779_063_444_837_72, 44.835_625_328_877_896, 0.0,
+    0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+    // B
+    0.0, 0.000_868_055_657_329_169_8, 0.0, 0.0, 0.0, 0.0,
+    0.0, 0.000_531_319_187_435_874_7, 0.0, 0.000_165_338_141_613_791_12, 0.0, 0.0,
+    0.0, 0.0, 0.0, 0.000_417_917_180_325_133_6, 0.001_729_082_823_472_283_3, 0.0,
+    0.002_082_700_584_663_643_7, 0.0, 0.0, 8.826_982_764_996_862, 23.192_433_439_989_26, 0.0,
+    95.108_049_881_108_6, 0.986_397_803_440_068_2, 0.983_438_279_246_535_3, 0.001_228_640_504_827_849_3, 171.266_725_589_730_7, 0.980_785_887_243_537_9,
+    0.0, 0.0, 0.0, 0.000_513_006_458_899_067_9, 0.0, 0.000_108_540_578_584_115_37,
+];
+
+/// Opsin absorbance matrix of the XYB color space
+#[rustfmt::skip]
+const OPSIN_ABSORBANCE: [[f32; 3]; 3] = [
+    [0.30, 0.622, 0.078],
+    [0.23, 0.692, 0.078],
+    [0.243_422_69, 0.204_767_44, 0.551_809_87],
+];
+
+const OPSIN_BIAS: f32 = 0.003_793_073_3;
+
+/// Image with three planes of `width * height` samples
+#[derive(Debug, Clone)]
+pub(crate) struct Image {
+    pub width:  usize,
+    pub height: usize,
+    pub planes: [Vec<f32>; 3],
+}
+
+impl Image {
+    /// Converts limited range BT.709 YCbCr with 4:4:4 planes normalized to
+    /// `0.0..=1.0` of the code values of `bit_depth` bits to linear RGB
+    pub(crate) fn from_yuv(
+        width: usize,
+        height: usize,
+        yuv: [&[f32]; 3],
+        bit_depth: usize,
+    ) -> Self {
+        // Normalized samples to 8-bit code values
+        let scale = ((1u32 << bit_depth) - 1) as f32 / (1u32 << (bit_depth - 8)) as f32;
+        let to_linear = |value: f32| {
+            let value = value.clamp(0.0, 1.0);
+            if value < 0.081 {
+                value / 4.5
+            } else {
+                ((value + 0.099) / 1.099).powf(1.0 / 0.45)
+            }
+        };
+
+        let mut planes = [
+            Vec::with_capacity(width * height),
+            Vec::with_capacity(width * height),
+            Vec::with_capacity(width * height),
+        ];
+        for ((&y, &u), &v) in yuv[0].iter().zip(yuv[1]).zip(yuv[2]) {
+            let y = y.mul_add(scale, -16.0) / 219.0;
+            let cb = u.mul_add(scale, -128.0) / 224.0;
+            let cr = v.mul_add(scale, -128.0) / 224.0;
+            planes[0].push(to_linear(1.5748f32.mul_add(cr, y)));
+            planes[1].push(to_linear(0.4681f32.mul_add(-cr, 0.1873f32.mul_add(-cb, y))));
+            planes[2].push(to_linear(1.8556f32.mul_add(cb, y)));
+        }
+
+        Self {
+            width,
+            height,
+            planes,
+        }
+    }
+
+    /// Halves the resolution by averaging 2x2 blocks, rounding up
+    fn downsample(&self) -> Self {
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let planes = self.planes.each_ref().map(|plane| {
+            let mut downsampled = Vec::with_capacity(width * height);
+            for y in 0..height {
+                for x in 0..width {
+                    let (mut sum, mut count) = (0.0, 0.0);
+                    for source_y in 2 * y..(2 * y + 2).min(self.height) {
+                        for source_x in 2 * x..(2 * x + 2).min(self.width) {
+                            sum += plane[source_y * self.width + source_x];
+                            count += 1.0;
+                        }
+                    }
+                    downsampled.push(sum / count);
+                }
+            }
+            downsampled
+        });
+
+        Self {
+            width,
+            height,
+            planes,
+        }
+    }
+
+    /// Converts linear RGB to XYB, offset so that every channel is positive
+    fn to_positive_xyb(&self) -> Self {
+        let bias = OPSIN_BIAS.cbrt();
+        let mut planes = [
+            Vec::with_capacity(self.planes[0].len()),
+            Vec::with_capacity(self.planes[0].len()),
+            Vec::with_capacity(self.planes[0].len()),
+        ];
+        for ((&r, &g), &b) in self.planes[0].iter().zip(&self.planes[1]).zip(&self.planes[2]) {
+            let [l, m, s] = OPSIN_ABSORBANCE.map(|row| {
+                let mixed = row[2].mul_add(b, row[1].mul_add(g, row[0].mul_add(r, OPSIN_BIAS)));
+                mixed.max(0.0).cbrt() - bias
+            });
+            let (x, y) = (0.5 * (l - m), 0.5 * (l + m));
+            planes[0].push(x.mul_add(14.0, 0.42));
+            planes[1].push(y + 0.01);
+            planes[2].push(s - y + 0.55);
+        }
+
+        Self {
+            width: self.width,
+            height: self.height,
+            planes,
+        }
+    }
+
+    fn map(&self, other: &Self, f: impl Fn(f32, f32) -> f32) -> Self {
+        Self {
+            width:  self.width,
+            height: self.height,
+            planes: [0, 1, 2].map(|c| {
+                self.planes[c].iter().zip(&other.planes[c]).map(|(&a, &b)| f(a, b)).collect()
+            }),
+        }
+    }
+
+    /// Separable Gaussian blur treating samples outside the image as zero
+    fn blur(&self, kernel: &[f32]) -> Self {
+        let radius = kernel.len() / 2;
+        let (width, height) = (self.width, self.height);
+        let planes = self.planes.each_ref().map(|plane| {
+            let mut horizontal = vec![0.0; plane.len()];
+            for y in 0..height {
+                let row = &plane[y * width..(y + 1) * width];
+                for x in 0..width {
+                    horizontal[y * width + x] = kernel
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(i, weight)| {
+                            (x + i).checked_sub(radius).and_then(|x| row.get(x)).map(|v| v * weight)
+                        })
+                        .sum();
+                }
+            }
+
+            let mut vertical = vec![0.0; plane.len()];
+            for y in 0..height {
+                for (i, weight) in kernel.iter().enumerate() {
+                    let Some(source_y) = (y + i).checked_sub(radius).filter(|&y| y < height) else {
+                        continue;
+                    };
+                    let source = &horizontal[source_y * width..(source_y + 1) * width];
+                    for (out, value) in vertical[y * width..(y + 1) * width].iter_mut().zip(source)
+                    {
+                        *out += value * weight;
+                    }
+                }
+            }
+            vertical
+        });
+
+        Self {
+            width,
+            height,
+            planes,
+        }
+    }
+}
+
+fn gaussian_kernel(sigma: f64) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as i32;
+    let weights: Vec<f64> = (-radius..=radius)
+        .map(|i| (-f64::from(i * i) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    weights.iter().map(|weight| (weight / total) as f32).collect()
+}
+
+/// Means of the SSIM error map of every channel, with norms 1 and 4
+fn ssim_map(
+    mu1: &Image,
+    mu2: &Image,
+    sigma11: &Image,
+    sigma22: &Image,
+    sigma12: &Image,
+) -> [f64; 6] {
+    const C2: f64 = 0.0009;
+
+    let mut averages = [0.0; 6];
+    let pixels = (mu1.width * mu1.height) as f64;
+    for c in 0..3 {
+        let (mut sum_1, mut sum_4) = (0.0, 0.0);
+        for i in 0..mu1.planes[c].len() {
+            let (m1, m2) = (f64::from(mu1.planes[c][i]), f64::from(mu2.planes[c][i]));
+            let num_m = (m1 - m2).mul_add(-(m1 - m2), 1.0);
+            let num_s = 2.0f64.mul_add(m1.mul_add(-m2, f64::from(sigma12.planes[c][i])), C2);
+            let denom_s = m1.mul_add(-m1, f64::from(sigma11.planes[c][i]))
+                + m2.mul_add(-m2, f64::from(sigma22.planes[c][i]))
+                + C2;
+            let d = (1.0 - num_m * num_s / denom_s).max(0.0);
+            sum_1 += d;
+            sum_4 += d.powi(4);
+        }
+        averages[c * 2] = sum_1 / pixels;
+        averages[c * 2 + 1] = (sum_4 / pixels).powf(0.25);
+    }
+
+    averages
+}
+
+/// Means of the added artifacts and the lost details of every channel, with
+/// norms 1 and 4
+fn edge_diff_map(img1: &Image, mu1: &Image, img2: &Image, mu2: &Image) -> [f64; 12] {
+    let mut averages = [0.0; 12];
+    let pixels = (img1.width * img1.height) as f64;
+    for c in 0..3 {
+        let mut sums = [0.0; 4];
+        for i in 0..img1.planes[c].len() {
+            let edge1 = f64::from((img1.planes[c][i] - mu1.planes[c][i]).abs());
+            let edge2 = f64::from((img2.planes[c][i] - mu2.planes[c][i]).abs());
+            let d = (1.0 + edge2) / (1.0 + edge1) - 1.0;
+            let (artifact, detail_lost) = (d.max(0.0), (-d).max(0.0));
+            sums[0] += artifact;
+            sums[1] += artifact.powi(4);
+            sums[2] += detail_lost;
+            sums[3] += detail_lost.powi(4);
+        }
+        averages[c * 4] = sums[0] / pixels;
+        averages[c * 4 + 1] = (sums[1] / pixels).powf(0.25);
+        averages[c * 4 + 2] = sums[2] / pixels;
+        averages[c * 4 + 3] = (sums[3] / pixels).powf(0.25);
+    }
+
+    averages
+}
+
+/// SSIMULACRA2 score of `distorted` against `reference`, both in linear RGB.
+/// 100 means identical, and lower scores mean more visible differences.
+pub(crate) fn ssimulacra2(reference: &Image, distorted: &Image) -> f64 {
+    let kernel = gaussian_kernel(BLUR_SIGMA);
+    let (mut reference, mut distorted) = (reference.clone(), distorted.clone());
+
+    let mut scales = Vec::with_capacity(NUM_SCALES);
+    for scale in 0..NUM_SCALES {
+        if reference.width < 8 || reference.height < 8 {
+            break;
+        }
+        if scale > 0 {
+            reference = reference.downsample();
+            distorted = distorted.downsample();
+        }
+
+        let img1 = reference.to_positive_xyb();
+        let img2 = distorted.to_positive_xyb();
+        let sigma11 = img1.map(&img1, |a, b| a * b).blur(&kernel);
+        let sigma22 = img2.map(&img2, |a, b| a * b).blur(&kernel);
+        let sigma12 = img1.map(&img2, |a, b| a * b).blur(&kernel);
+        let mu1 = img1.blur(&kernel);
+        let mu2 = img2.blur(&kernel);
+
+        scales.push((
+            ssim_map(&mu1, &mu2, &sigma11, &sigma22, &sigma12),
+            edge_diff_map(&img1, &mu1, &img2, &mu2),
+        ));
+    }
+
+    let mut weights = WEIGHTS.iter();
+    let mut score = 0.0;
+    for c in 0..3 {
+        for (ssim, edge_diff) in &scales {
+            for n in 0..2 {
+                for feature in [ssim[c * 2 + n], edge_diff[c * 4 + n], edge_diff[c * 4 + n + 2]] {
+                    score += weights.next().expect("there is a weight for every feature")
+                        * feature.abs();
+                }
+            }
+        }
+    }
+
+    let score = score * 0.956_238_261_683_484_4;
+    let score = (6.248_496_625_763_138e-5 * score * score).mul_add(
+        score,
+        2.326_765_642_916_932f64.mul_add(score, -0.020_884_521_182_843_837 * score * score),
+    );
+    if score > 0.0 {
+        10.0f64.mul_add(-score.powf(0.627_633_646_783_138_7), 100.0)
+    } else {
+        100.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ssimulacra2_of_identical_and_distorted_images() {
+        let (width, height) = (64, 48);
+        let gradient: Vec<f32> = (0..width * height)
+            .map(|i| ((i % width) * 3 + (i / width) * 2) as f32 / 300.0 + 0.1)
+            .collect();
+        let chroma = vec![0.5; width * height];
+        let reference = Image::from_yuv(width, height, [&gradient, &chroma, &chroma], 8);
+        assert!((ssimulacra2(&reference, &reference) - 100.0).abs() < 1e-9);
+
+        let blocky: Vec<f32> = gradient
+            .iter()
+            .enumerate()
+            .map(|(i, value)| gradient[i - i % 8] * 0.5 + value * 0.5)
+            .collect();
+        let distorted = Image::from_yuv(width, height, [&blocky, &chroma, &chroma], 8);
+        let score = ssimulacra2(&reference, &distorted);
+        assert!(score < 100.0, "score {score} should be below 100");
+    }
+}
@@ -1,8 +1,26 @@
 use std::{cmp::Ordering, collections::HashMap};
 
+use serde::{Deserialize, Serialize};
+
+use crate::{ProbingStatistic, ProbingStatisticName, TargetMetric};
+
+/// Summary of the per-frame scores of a probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameStatistics {
+    pub frames:             usize,
+    pub mean:               f64,
+    pub median:             f64,
+    pub minimum:            f64,
+    pub maximum:            f64,
+    pub standard_deviation: f64,
+    pub percentile_1:       f64,
+}
+
 pub struct MetricStatistics {
-    scores: Vec<f64>,
-    cache:  HashMap<String, f64>,
+    scores:     Vec<f64>,
+    cache:      HashMap<String, f64>,
+    /// Scores per second of video, used to size the pooling windows
+    frame_rate: Option<f64>,
 }
 
 impl MetricStatistics {
@@ -10,9 +28,17 @@ impl MetricStatistics {
         MetricStatistics {
             scores,
             cache: HashMap::new(),
+            frame_rate: None,
         }
     }
 
+    /// Sets the number of scores per second of video, which is lower than the
+    /// frame rate when only every n-th frame is scored
+    pub fn with_frame_rate(mut self, frame_rate: f64) -> Self {
+        self.frame_rate = Some(frame_rate);
+        self
+    }
+
     fn get_or_compute(&mut self, key: &str, compute: impl FnOnce(&[f64]) -> f64) -> f64 {
         *self.cache.entry(key.to_string()).or_insert_with(|| compute(&self.scores))
     }
@@ -105,6 +131,112 @@ impl MetricStatistics {
         })
     }
 
+    pub fn summary(&mut self) -> FrameStatistics {
+        FrameStatistics {
+            frames:             self.scores.len(),
+            mean:               self.mean(),
+            median:             self.median(),
+            minimum:            self.minimum(),
+            maximum:            self.maximum(),
+            standard_deviation: self.standard_deviation(),
+            percentile_1:       self.percentile(1),
+        }
+    }
+
+    /// Means of every window of `window` consecutive scores, sliding by one
+    /// score. A window longer than the scores covers all of them.
+    pub fn window_means(&self, window: usize) -> Vec<f64> {
+        if self.scores.is_empty() {
+            return Vec::new();
+        }
+        let window = window.clamp(1, self.scores.len());
+
+        let mut sum: f64 = self.scores[..window].iter().sum();
+        let mut means = Vec::with_capacity(self.scores.len() - window + 1);
+        means.push(sum / window as f64);
+        for (entering, leaving) in self.scores[window..].iter().zip(&self.scores) {
+            sum += entering - leaving;
+            means.push(sum / window as f64);
+        }
+        means
+    }
+
+    /// Number of scores in a window of `seconds`
+    fn window_length(&self, seconds: f64) -> anyhow::Result<usize> {
+        let frame_rate = self
+            .frame_rate
+            .ok_or_else(|| anyhow::anyhow!("Window statistics require the frame rate"))?;
+        Ok((seconds * frame_rate).round().max(1.0) as usize)
+    }
+
+    /// Lowest mean score of any window of `seconds`, so that sustained drops
+    /// in quality weigh more than drops in single frames
+    pub fn window_minimum(&mut self, seconds: f64) -> anyhow::Result<f64> {
+        let window = self.window_length(seconds)?;
+        let means = self.window_means(window);
+        Ok(
+            self.get_or_compute(&format!("window_minimum_{window}"), |_| {
+                means.iter().copied().fold(f64::INFINITY, f64::min)
+            }),
+        )
+    }
+
+    /// Percentile of the mean scores of the windows of `seconds`
+    pub fn window_percentile(&mut self, seconds: f64, percentile: usize) -> anyhow::Result<f64> {
+        let window = self.window_length(seconds)?;
+        let means = self.window_means(window);
+        Ok(
+            self.get_or_compute(&format!("window_percentile_{window}_{percentile}"), |_| {
+                MetricStatistics::new(means).percentile(percentile)
+            }),
+        )
+    }
+
+    /// Aggregates the scores of `metric` with `statistic`. The automatic
+    /// statistic of target quality depends on the quantizer of the probe, so
+    /// without one it is the 1st percentile for VMAF and the mean otherwise.
+    pub fn aggregate(
+        &mut self,
+        statistic: &ProbingStatistic,
+        metric: TargetMetric,
+    ) -> anyhow::Result<f64> {
+        Ok(match statistic.name {
+            ProbingStatisticName::Automatic if metric == TargetMetric::VMAF => self.percentile(1),
+            ProbingStatisticName::Automatic | ProbingStatisticName::Mean => self.mean(),
+            ProbingStatisticName::RootMeanSquare => self.root_mean_square(),
+            ProbingStatisticName::Median => self.median(),
+            ProbingStatisticName::Harmonic => self.harmonic_mean(),
+            ProbingStatisticName::Percentile => {
+                let value = statistic
+                    .value
+                    .ok_or_else(|| anyhow::anyhow!("Percentile statistic requires a value"))?;
+                self.percentile(value as usize)
+            },
+            ProbingStatisticName::StandardDeviation => {
+                let value = statistic.value.ok_or_else(|| {
+                    anyhow::anyhow!("Standard deviation statistic requires a value")
+                })?;
+                let sigma_distance = value * self.standard_deviation();
+                let statistic = self.mean() + sigma_distance;
+                statistic.clamp(self.minimum(), self.maximum())
+            },
+            ProbingStatisticName::Mode => self.mode(),
+            ProbingStatisticName::Minimum => self.minimum(),
+            ProbingStatisticName::Maximum => self.maximum(),
+            ProbingStatisticName::WindowMinimum => {
+                let seconds = statistic
+                    .value
+                    .ok_or_else(|| anyhow::anyhow!("Window minimum statistic requires a value"))?;
+                self.window_minimum(seconds)?
+            },
+            ProbingStatisticName::Expression => statistic
+                .expression
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("Expression statistic requires an expression"))?
+                .evaluate(self)?,
+        })
+    }
+
     pub fn root_mean_square(&mut self) -> f64 {
         self.get_or_compute("root_mean_square", |scores| {
             let sum_of_squares: f64 = scores.iter().map(|&x| x * x).sum();
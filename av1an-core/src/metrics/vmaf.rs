@@ -8,12 +8,12 @@ use std::{
 use anyhow::{anyhow, Context};
 use plotters::prelude::*;
 use serde::{Deserialize, Serialize};
-use smallvec::SmallVec;
 
 use crate::{
     broker::EncoderCrash,
     ffmpeg,
-    ref_smallvec,
+    metrics::analysis::reference_pipe,
+    sampling::FrameSampling,
     util::printable_base10_digits,
     Input,
     VmafFeature,
@@ -139,35 +139,9 @@ pub fn plot(
 ) -> anyhow::Result<()> {
     let json_file = encoded.with_extension("json");
     let plot_file = encoded.with_extension("svg");
-    let vspipe_args;
-
     println!(":: VMAF Run");
 
-    let pipe_cmd: SmallVec<[&OsStr; 8]> = match reference {
-        Input::Video {
-            ref path, ..
-        } => {
-            vspipe_args = vec![];
-            ref_smallvec!(OsStr, 8, [
-                "ffmpeg",
-                "-i",
-                path,
-                "-strict",
-                "-1",
-                "-f",
-                "yuv4mpegpipe",
-                "-"
-            ])
-        },
-        Input::VapourSynth {
-            ref path,
-            vspipe_args: args,
-            ..
-        } => {
-            vspipe_args = args.to_owned();
-            ref_smallvec!(OsStr, 8, ["vspipe", "-c", "y4m", path, "-"])
-        },
-    };
+    let (pipe_cmd, vspipe_args) = reference_pipe(reference);
 
     run_vmaf(
         encoded,
@@ -177,7 +151,7 @@ pub fn plot(
         model,
         res,
         scaler,
-        sample_rate,
+        &FrameSampling::Every(sample_rate),
         filter,
         threads,
         60.0,
@@ -210,22 +184,14 @@ pub fn run_vmaf(
     model: Option<impl AsRef<Path>>,
     res: &str,
     scaler: &str,
-    sample_rate: usize,
+    sampling: &FrameSampling,
     vmaf_filter: Option<&str>,
     threads: usize,
     framerate: f64,
     disable_motion: bool,
     probing_vmaf_features: &[VmafFeature],
 ) -> anyhow::Result<()> {
-    let mut filter = if sample_rate > 1 {
-        format!(
-            "select=not(mod(n\\,{})),setpts={:.4}*PTS,",
-            sample_rate,
-            1.0 / sample_rate as f64,
-        )
-    } else {
-        String::new()
-    };
+    let mut filter = sampling.ffmpeg_reference_filter();
 
     if let Some(vmaf_filter) = vmaf_filter {
         filter.reserve(1 + vmaf_filter.len());
@@ -9,7 +9,7 @@ use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use strum::{Display, EnumString, IntoStaticStr};
 
-use crate::{broker::EncoderCrash, ffmpeg};
+use crate::{broker::EncoderCrash, ffmpeg, sampling::FrameSampling};
 
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display, EnumString, IntoStaticStr,
@@ -48,18 +48,10 @@ pub fn run_xpsnr(
     stat_file: impl AsRef<Path>,
     res: &str,
     scaler: &str,
-    sample_rate: usize,
+    sampling: &FrameSampling,
     framerate: f64,
 ) -> anyhow::Result<()> {
-    let filter = if sample_rate > 1 {
-        format!(
-            "select=not(mod(n\\,{})),setpts={:.4}*PTS,",
-            sample_rate,
-            1.0 / sample_rate as f64,
-        )
-    } else {
-        String::new()
-    };
+    let filter = sampling.ffmpeg_reference_filter();
 
     let xpsnr = format!(
         "[distorted][ref]xpsnr=stats_file={}:eof_action=endall",
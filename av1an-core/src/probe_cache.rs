@@ -0,0 +1,310 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    encoder::Encoder,
+    ffmpeg::FFPixelFormat,
+    metrics::statistics::FrameStatistics,
+    sampling::ProbingWindows,
+    search_param::SearchParameter,
+    util::stable_hash,
+    ProbingStatistic,
+    TargetMetric,
+    VmafFeature,
+};
+
+/// Name of the directory inside the temporary directory holding the probe
+/// caches. It is kept when a new encode reuses the temporary directory.
+pub(crate) const PROBE_CACHE_DIR: &str = "probes";
+
+/// Result of a single target quality probe
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct CachedProbe {
+    pub quantizer:   f32,
+    /// Aggregated score as returned by the metric, before any inversion
+    pub score:       f64,
+    /// Size of the probe in bytes
+    pub size:        u64,
+    pub metric:      TargetMetric,
+    /// [`ProbeParams::hash`] of the settings of the probe
+    pub params_hash: u64,
+    /// Summary of the per-frame scores, if the metric is calculated per frame
+    #[serde(default)]
+    pub frames:      Option<FrameStatistics>,
+    /// Time taken to encode the probe in seconds
+    #[serde(default)]
+    pub encode_time: f64,
+}
+
+/// Every setting that affects the score of a probe of a chunk. The settings
+/// are stored next to the probes made with them, so a cached probe is only
+/// reused with the same settings even if their hashes collide.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ProbeParams {
+    /// Fingerprint of the content of the input, as the temporary directory
+    /// is named after its path
+    pub input_fingerprint:     u64,
+    pub proxy_fingerprint:     Option<u64>,
+    pub start_frame:           usize,
+    pub end_frame:             usize,
+    pub source_cmd:            Vec<String>,
+    pub proxy_cmd:             Option<Vec<String>>,
+    pub encoder:               Encoder,
+    pub pix_format:            FFPixelFormat,
+    pub video_params:          Option<Vec<String>>,
+    pub vspipe_args:           Vec<String>,
+    pub probe_res:             Option<(u32, u32)>,
+    pub vmaf_res:              String,
+    pub vmaf_scaler:           String,
+    pub vmaf_filter:           Option<String>,
+    pub model:                 Option<PathBuf>,
+    pub probing_vmaf_features: Vec<VmafFeature>,
+    pub probing_statistic:     ProbingStatistic,
+    pub probing_rate:          usize,
+    pub probing_windows:       Option<ProbingWindows>,
+    pub search_parameter:      Option<SearchParameter>,
+    pub fixed_quantizer:       Option<f32>,
+    pub resize_denominator:    Option<u8>,
+    /// Implementation scoring SSIMULACRA2, if it is one of the metrics
+    pub ssimulacra2_backend:   Option<String>,
+}
+
+impl ProbeParams {
+    /// 64-bit FNV-1a hash of the JSON serialization of the settings, which
+    /// is the same for every build of Av1an
+    pub(crate) fn hash(&self) -> u64 {
+        stable_hash(self)
+    }
+}
+
+/// Contents of a probe cache file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    /// Settings of the probes by their hash
+    params: BTreeMap<u64, ProbeParams>,
+    probes: Vec<CachedProbe>,
+}
+
+/// Probe results of a single chunk, persisted to
+/// `<temp>/probes/<chunk name>.json` after every probe
+#[derive(Debug)]
+pub(crate) struct ProbeCache {
+    path:                         PathBuf,
+    params:                       BTreeMap<u64, ProbeParams>,
+    probes:                       Vec<CachedProbe>,
+    /// Whether the frame alignment of the probes was checked, which is not
+    /// persisted
+    pub(crate) alignment_checked: bool,
+}
+
+impl ProbeCache {
+    /// Loads the probe cache of a chunk. A missing or unreadable cache is
+    /// treated as empty.
+    pub(crate) fn load(temp: &str, chunk_name: &str) -> Self {
+        let path = Path::new(temp).join(PROBE_CACHE_DIR).join(format!("{chunk_name}.json"));
+        let CacheFile {
+            params,
+            probes,
+        } = fs::read_to_string(&path).map_or_else(
+            |_| CacheFile::default(),
+            |contents| {
+                serde_json::from_str(&contents).unwrap_or_else(|e| {
+                    warn!("Ignoring invalid probe cache {}: {e}", path.display());
+                    CacheFile::default()
+                })
+            },
+        );
+
+        Self {
+            path,
+            params,
+            probes,
+            alignment_checked: false,
+        }
+    }
+
+    /// Hash of `params` to look up and insert probes with. Probes stored
+    /// under the same hash with different settings are dropped.
+    pub(crate) fn register(&mut self, params: ProbeParams) -> u64 {
+        let params_hash = params.hash();
+        // Not every setting implements PartialEq, so they are compared as JSON
+        if self.params.get(&params_hash).is_some_and(|stored| {
+            serde_json::to_value(stored).ok() != serde_json::to_value(&params).ok()
+        }) {
+            debug!(
+                "Dropping cached probes of {} made with other settings",
+                self.path.display()
+            );
+            self.probes.retain(|probe| probe.params_hash != params_hash);
+        }
+        self.params.insert(params_hash, params);
+
+        params_hash
+    }
+
+    pub(crate) fn get(
+        &self,
+        quantizer: f32,
+        metric: TargetMetric,
+        params_hash: u64,
+    ) -> Option<CachedProbe> {
+        self.probes
+            .iter()
+            .find(|probe| {
+                probe.quantizer == quantizer
+                    && probe.metric == metric
+                    && probe.params_hash == params_hash
+            })
+            .copied()
+    }
+
+    /// Probes of `metric` made with the settings of `params_hash`
+    pub(crate) fn probes(
+        &self,
+        metric: TargetMetric,
+        params_hash: u64,
+    ) -> impl Iterator<Item = &CachedProbe> {
+        self.probes
+            .iter()
+            .filter(move |probe| probe.metric == metric && probe.params_hash == params_hash)
+    }
+
+    /// Adds a probe made with settings passed to [`Self::register`] to the
+    /// cache and writes the cache to disk
+    pub(crate) fn insert(&mut self, probe: CachedProbe) -> anyhow::Result<()> {
+        debug_assert!(self.params.contains_key(&probe.params_hash));
+        self.probes.retain(|cached| {
+            !(cached.quantizer == probe.quantizer
+                && cached.metric == probe.metric
+                && cached.params_hash == probe.params_hash)
+        });
+        self.probes.push(probe);
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        // Write to a temporary file first so an interruption cannot corrupt the cache
+        let tmp_path = self.path.with_extension("json.tmp");
+        // Only the settings of cached probes are kept
+        let file = CacheFile {
+            params: self
+                .params
+                .iter()
+                .filter(|(hash, _)| self.probes.iter().any(|probe| probe.params_hash == **hash))
+                .map(|(&hash, params)| (hash, params.clone()))
+                .collect(),
+            probes: self.probes.clone(),
+        };
+        fs::write(&tmp_path, serde_json::to_string(&file)?)
+            .with_context(|| format!("Failed to write probe cache {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to write probe cache {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProbingStatisticName;
+
+    fn params(video_params: &[&str]) -> ProbeParams {
+        ProbeParams {
+            input_fingerprint:     1,
+            proxy_fingerprint:     None,
+            start_frame:           0,
+            end_frame:             48,
+            source_cmd:            vec!["ffmpeg".to_owned()],
+            proxy_cmd:             None,
+            encoder:               Encoder::aom,
+            pix_format:            FFPixelFormat::YUV420P10LE,
+            video_params:          Some(video_params.iter().map(|&p| p.to_owned()).collect()),
+            vspipe_args:           vec![],
+            probe_res:             None,
+            vmaf_res:              "1920x1080".to_owned(),
+            vmaf_scaler:           "bicubic".to_owned(),
+            vmaf_filter:           None,
+            model:                 None,
+            probing_vmaf_features: vec![],
+            probing_statistic:     ProbingStatistic {
+                name:       ProbingStatisticName::Mean,
+                value:      None,
+                expression: None,
+            },
+            probing_rate:          1,
+            probing_windows:       None,
+            search_parameter:      None,
+            fixed_quantizer:       None,
+            resize_denominator:    None,
+            ssimulacra2_backend:   None,
+        }
+    }
+
+    #[test]
+    fn probe_cache_round_trip() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let temp_str = temp.path().to_string_lossy().to_string();
+
+        let mut cache = ProbeCache::load(&temp_str, "00001");
+        let params_hash = cache.register(params(&["--cpu-used=6"]));
+        assert_eq!(params_hash, params(&["--cpu-used=6"]).hash());
+        assert_ne!(params_hash, params(&["--cpu-used=4"]).hash());
+        // A different file written to the same input path
+        assert_ne!(
+            params_hash,
+            ProbeParams {
+                input_fingerprint: 2,
+                ..params(&["--cpu-used=6"])
+            }
+            .hash()
+        );
+        let probe = CachedProbe {
+            quantizer: 30.0,
+            score: 93.5,
+            size: 12345,
+            metric: TargetMetric::VMAF,
+            params_hash,
+            frames: None,
+            encode_time: 1.5,
+        };
+
+        assert_eq!(cache.get(30.0, TargetMetric::VMAF, params_hash), None);
+        cache.insert(probe).expect("probe cache should be written");
+        cache
+            .insert(CachedProbe {
+                score: 94.0,
+                ..probe
+            })
+            .expect("probe cache should be written");
+
+        let mut cache = ProbeCache::load(&temp_str, "00001");
+        assert_eq!(cache.probes.len(), 1);
+        assert!(cache.params.contains_key(&params_hash));
+        assert_eq!(
+            cache.get(30.0, TargetMetric::VMAF, params_hash).map(|p| p.score),
+            Some(94.0)
+        );
+        assert_eq!(cache.get(30.0, TargetMetric::VMAF, params_hash + 1), None);
+        assert_eq!(
+            cache.get(30.0, TargetMetric::SSIMULACRA2, params_hash),
+            None
+        );
+        assert_eq!(cache.get(31.0, TargetMetric::VMAF, params_hash), None);
+
+        // Probes stored under the same hash with other settings are not reused
+        cache.params.insert(params_hash, params(&["--cpu-used=4"]));
+        assert_eq!(cache.register(params(&["--cpu-used=6"])), params_hash);
+        assert_eq!(cache.get(30.0, TargetMetric::VMAF, params_hash), None);
+
+        Ok(())
+    }
+}
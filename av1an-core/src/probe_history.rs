@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+/// Maximum distance in chunk indices between a chunk and the finished chunks
+/// used to seed its target quality search
+const MAX_SEED_DISTANCE: usize = 8;
+
+/// Probe history of a chunk that finished its target quality search
+#[derive(Debug, Clone)]
+struct FinishedSearch {
+    /// Hash of the settings that decide which quantizer a search ends with
+    settings_hash: u64,
+    /// Quantizer-score pairs with the scores normalized so higher is better
+    history:       Vec<(f32, f64)>,
+    quantizer:     f32,
+}
+
+/// Target quality probe histories of finished chunks, shared between the
+/// workers so that a new chunk can start its search near the quantizer its
+/// neighbours ended with
+#[derive(Debug, Default)]
+pub struct ProbeHistories {
+    searches: Mutex<BTreeMap<usize, FinishedSearch>>,
+}
+
+/// Starting point of a target quality search predicted from neighbouring
+/// chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct QuantizerSeed {
+    /// Mean final quantizer of the neighbouring chunks
+    pub quantizer: f32,
+    /// Mean change in quantizer per unit of normalized score of the
+    /// neighbouring chunks, if they probed enough to estimate it
+    pub slope:     Option<f64>,
+}
+
+impl ProbeHistories {
+    pub(crate) fn insert(
+        &self,
+        index: usize,
+        settings_hash: u64,
+        history: &[(f32, f64)],
+        quantizer: f32,
+    ) {
+        self.searches
+            .lock()
+            .expect("mutex should acquire lock")
+            .insert(index, FinishedSearch {
+                settings_hash,
+                history: history.to_vec(),
+                quantizer,
+            });
+    }
+
+    /// Predicts the starting point of the search of chunk `index` from the
+    /// closest finished chunk on each side that used the same settings
+    pub(crate) fn seed(&self, index: usize, settings_hash: u64) -> Option<QuantizerSeed> {
+        let searches = self.searches.lock().expect("mutex should acquire lock");
+        let compatible =
+            |(_, search): &(&usize, &FinishedSearch)| search.settings_hash == settings_hash;
+        let previous = searches
+            .range(index.saturating_sub(MAX_SEED_DISTANCE)..index)
+            .rev()
+            .find(compatible);
+        let next = searches
+            .range(index + 1..=index.saturating_add(MAX_SEED_DISTANCE))
+            .find(compatible);
+        let neighbours: Vec<&FinishedSearch> =
+            previous.into_iter().chain(next).map(|(_, search)| search).collect();
+        if neighbours.is_empty() {
+            return None;
+        }
+
+        let quantizer =
+            neighbours.iter().map(|search| search.quantizer).sum::<f32>() / neighbours.len() as f32;
+        let slopes: Vec<f64> = neighbours
+            .iter()
+            .filter_map(|search| quantizer_slope(&search.history))
+            .collect();
+        let slope = (!slopes.is_empty()).then(|| slopes.iter().sum::<f64>() / slopes.len() as f64);
+
+        Some(QuantizerSeed {
+            quantizer,
+            slope,
+        })
+    }
+}
+
+/// Least squares slope of the quantizer against the normalized score. Only
+/// decreasing quality with increasing quantizer is accepted.
+fn quantizer_slope(history: &[(f32, f64)]) -> Option<f64> {
+    if history.len() < 2 {
+        return None;
+    }
+
+    let n = history.len() as f64;
+    let mean_score = history.iter().map(|(_, score)| score).sum::<f64>() / n;
+    let mean_quantizer = history.iter().map(|(quantizer, _)| *quantizer as f64).sum::<f64>() / n;
+    let (covariance, variance) =
+        history.iter().fold((0.0, 0.0), |(covariance, variance), (quantizer, score)| {
+            let score_deviation = score - mean_score;
+            (
+                score_deviation.mul_add(*quantizer as f64 - mean_quantizer, covariance),
+                score_deviation.mul_add(score_deviation, variance),
+            )
+        });
+
+    let slope = covariance / variance;
+    (variance > 0.0 && slope < 0.0).then_some(slope)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seed_from_neighbours() {
+        let histories = ProbeHistories::default();
+        assert_eq!(histories.seed(5, 1), None);
+
+        histories.insert(3, 1, &[(20.0, 96.0), (30.0, 92.0)], 28.0);
+        histories.insert(7, 1, &[(30.0, 94.0)], 32.0);
+        // Different settings, e.g. another zone
+        histories.insert(4, 2, &[(10.0, 99.0), (50.0, 80.0)], 10.0);
+        // Too far away
+        histories.insert(20, 1, &[(60.0, 70.0)], 60.0);
+
+        let seed = histories.seed(5, 1).expect("chunk 5 should have neighbours");
+        assert!((seed.quantizer - 30.0).abs() < f32::EPSILON);
+        assert_eq!(seed.slope, Some(-2.5));
+
+        assert_eq!(histories.seed(30, 1), None);
+    }
+}
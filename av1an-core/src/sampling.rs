@@ -0,0 +1,175 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Contiguous windows of frames probed instead of the whole chunk, spread
+/// evenly from the start to the end of the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProbingWindows {
+    pub count:  usize,
+    /// Length of each window in frames
+    pub length: usize,
+}
+
+impl ProbingWindows {
+    /// Frame ranges of the windows relative to the start of a chunk of
+    /// `frames` frames, with exclusive ends. Chunks that are not longer than
+    /// the windows combined are probed whole.
+    #[inline]
+    pub fn ranges(&self, frames: usize) -> Vec<(usize, usize)> {
+        if self.count * self.length >= frames {
+            return vec![(0, frames)];
+        }
+
+        let last_start = frames - self.length;
+        (0..self.count)
+            .map(|i| {
+                let start = if self.count == 1 {
+                    last_start / 2
+                } else {
+                    last_start * i / (self.count - 1)
+                };
+                (start, start + self.length)
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ProbingWindows {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, length) = s
+            .split_once('x')
+            .ok_or_else(|| format!("Invalid probing windows: {s}. Use <count>x<frames>"))?;
+        let count = count
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid probing window count: {count}"))?;
+        let length = length
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format!("Invalid probing window length: {length}"))?;
+        if count == 0 || length == 0 {
+            return Err("Probing window count and length must be greater than 0".to_string());
+        }
+
+        Ok(Self {
+            count,
+            length,
+        })
+    }
+}
+
+impl Display for ProbingWindows {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.count, self.length)
+    }
+}
+
+/// Frames of a chunk that are encoded and measured when probing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FrameSampling {
+    /// One of every n frames
+    Every(usize),
+    /// Contiguous frame ranges relative to the start of the chunk, with
+    /// exclusive ends
+    Windows(Vec<(usize, usize)>),
+}
+
+impl FrameSampling {
+    /// Whether only a part of the frames is used
+    #[inline]
+    pub fn is_sampled(&self) -> bool {
+        match self {
+            Self::Every(n) => *n > 1,
+            Self::Windows(_) => true,
+        }
+    }
+
+    /// Number of frames used out of `frames`
+    #[inline]
+    pub fn frame_count(&self, frames: usize) -> usize {
+        match self {
+            Self::Every(n) => frames.div_ceil(*n),
+            Self::Windows(windows) => windows.iter().map(|(start, end)| end - start).sum(),
+        }
+    }
+
+    /// Number of sampled frames per second of video. Windows are contiguous, so
+    /// their frames keep the frame rate.
+    #[inline]
+    pub fn score_rate(&self, frame_rate: f64) -> f64 {
+        match self {
+            Self::Every(n) => frame_rate / *n as f64,
+            Self::Windows(_) => frame_rate,
+        }
+    }
+
+    /// FFmpeg `select` filter keeping the sampled frames
+    #[inline]
+    pub fn ffmpeg_select(&self) -> Option<String> {
+        match self {
+            Self::Every(n) if *n > 1 => Some(format!("select=not(mod(n\\,{n}))")),
+            Self::Every(_) => None,
+            Self::Windows(windows) => Some(format!(
+                "select={}",
+                windows
+                    .iter()
+                    .map(|(start, end)| format!("between(n\\,{start}\\,{last})", last = end - 1))
+                    .collect::<Vec<_>>()
+                    .join("+")
+            )),
+        }
+    }
+
+    /// FFmpeg filters keeping the sampled frames of a reference with
+    /// contiguous timestamps, followed by a comma. Empty if every frame is
+    /// used.
+    #[inline]
+    pub fn ffmpeg_reference_filter(&self) -> String {
+        match self {
+            Self::Every(n) if *n > 1 => format!(
+                "{select},setpts={:.4}*PTS,",
+                1.0 / *n as f64,
+                select = self.ffmpeg_select().expect("frames are sampled"),
+            ),
+            Self::Every(_) => String::new(),
+            Self::Windows(_) => format!(
+                "{select},setpts=N/FRAME_RATE/TB,",
+                select = self.ffmpeg_select().expect("frames are sampled"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probing_window_ranges() {
+        let windows: ProbingWindows = "3x24".parse().unwrap();
+        assert_eq!(windows.ranges(240), [(0, 24), (108, 132), (216, 240)]);
+        assert_eq!(windows.ranges(72), [(0, 72)]);
+        assert_eq!("1x10".parse::<ProbingWindows>().unwrap().ranges(100), [(
+            45, 55
+        )]);
+        assert!("3x0".parse::<ProbingWindows>().is_err());
+        assert!("24".parse::<ProbingWindows>().is_err());
+
+        let sampling = FrameSampling::Windows(windows.ranges(240));
+        assert_eq!(sampling.frame_count(240), 72);
+        assert_eq!(
+            sampling.ffmpeg_select().unwrap(),
+            "select=between(n\\,0\\,23)+between(n\\,108\\,131)+between(n\\,216\\,239)"
+        );
+        assert_eq!(FrameSampling::Every(1).ffmpeg_reference_filter(), "");
+        assert_eq!(
+            FrameSampling::Every(2).ffmpeg_reference_filter(),
+            "select=not(mod(n\\,2)),setpts=0.5000*PTS,"
+        );
+    }
+}
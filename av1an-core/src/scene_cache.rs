@@ -0,0 +1,481 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use av_scenechange::ScenecutResult;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    scenes::Scene,
+    util::{stable_hash, StableHasher},
+    EncodeArgs,
+    Input,
+};
+
+/// Bytes read from the start, the middle and the end of a video file to
+/// fingerprint its content
+const FINGERPRINT_SAMPLE_SIZE: u64 = 1 << 20;
+
+/// Scene detection results of an input, as returned by
+/// [`av_scenechange_detect`](crate::scene_detect::av_scenechange_detect)
+pub(crate) type DetectedScenes = (Vec<Scene>, usize, BTreeMap<usize, ScenecutResult>);
+
+/// Scenecut scores of a single frame, mirroring [`ScenecutResult`] which
+/// cannot be deserialized
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct CachedScore {
+    inter_cost:             f64,
+    imp_block_cost:         f64,
+    backward_adjusted_cost: f64,
+    forward_adjusted_cost:  f64,
+    threshold:              f64,
+}
+
+impl From<ScenecutResult> for CachedScore {
+    fn from(score: ScenecutResult) -> Self {
+        Self {
+            inter_cost:             score.inter_cost,
+            imp_block_cost:         score.imp_block_cost,
+            backward_adjusted_cost: score.backward_adjusted_cost,
+            forward_adjusted_cost:  score.forward_adjusted_cost,
+            threshold:              score.threshold,
+        }
+    }
+}
+
+impl From<CachedScore> for ScenecutResult {
+    #[inline]
+    fn from(score: CachedScore) -> Self {
+        Self {
+            inter_cost:             score.inter_cost,
+            imp_block_cost:         score.imp_block_cost,
+            backward_adjusted_cost: score.backward_adjusted_cost,
+            forward_adjusted_cost:  score.forward_adjusted_cost,
+            threshold:              score.threshold,
+        }
+    }
+}
+
+/// Content of the input and scene detection options that the scenes were
+/// detected with, stored with the scenes so that an entry is only used for
+/// the same key even if the hashes of two keys collide
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SceneCacheKey {
+    input:               u64,
+    frames:              usize,
+    encoder:             String,
+    scaler:              String,
+    sc_method:           String,
+    sc_downscale_height: Option<usize>,
+    sc_pix_format:       Option<String>,
+    min_scene_len:       usize,
+    zones:               Vec<ZoneKey>,
+}
+
+/// Frames of a zone and the zone overrides that scene detection reads. Other
+/// overrides such as the encoder settings are applied to the cached scenes
+/// when they are loaded, so changing them does not invalidate the cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ZoneKey {
+    start_frame:      usize,
+    end_frame:        usize,
+    min_scene_len:    Option<usize>,
+    extra_splits_len: Option<usize>,
+}
+
+impl From<&Scene> for ZoneKey {
+    fn from(zone: &Scene) -> Self {
+        Self {
+            start_frame:      zone.start_frame,
+            end_frame:        zone.end_frame,
+            min_scene_len:    zone.zone_overrides.as_ref().map(|zone| zone.min_scene_len),
+            extra_splits_len: zone.zone_overrides.as_ref().and_then(|zone| zone.extra_splits_len),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedScenes {
+    key:    SceneCacheKey,
+    frames: usize,
+    scenes: Vec<Scene>,
+    scores: BTreeMap<usize, CachedScore>,
+}
+
+/// Scene detection results of a single input and set of detection options,
+/// persisted to `<cache dir>/<hash>.json` so that later encodes of the same
+/// source can skip scene detection. The hash is the 64-bit FNV-1a hash of the
+/// JSON serialization of the key, which is the same for every build of Av1an.
+#[derive(Debug)]
+pub(crate) struct SceneCache {
+    path:  PathBuf,
+    key:   SceneCacheKey,
+    /// Zones whose overrides are given to the cached scenes
+    zones: Vec<Scene>,
+}
+
+impl SceneCache {
+    /// Scene cache of the input of `args`, or of its proxy if scene detection
+    /// runs on it. The key covers the content of the input, the scene
+    /// detection options and the frames of `zones`.
+    pub(crate) fn new(
+        dir: &Path,
+        args: &EncodeArgs,
+        frames: usize,
+        zones: &[Scene],
+    ) -> anyhow::Result<Self> {
+        let input = args.proxy.as_ref().unwrap_or(&args.input);
+
+        let key = SceneCacheKey {
+            input: input_fingerprint(input)?,
+            frames,
+            encoder: <&str>::from(args.encoder).to_owned(),
+            scaler: args.scaler.clone(),
+            sc_method: args.sc_method.to_string(),
+            sc_downscale_height: args.sc_downscale_height,
+            sc_pix_format: args.sc_pix_format.map(|format| format!("{format:?}")),
+            min_scene_len: args.min_scene_len,
+            zones: zones.iter().map(ZoneKey::from).collect(),
+        };
+
+        Ok(Self {
+            path: dir.join(format!("{:016x}.json", stable_hash(&key))),
+            key,
+            zones: zones.to_vec(),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the cached scenes with the overrides of the zones they are in. A
+    /// missing or unreadable cache entry is treated as a miss.
+    pub(crate) fn load(&self) -> Option<DetectedScenes> {
+        let contents = fs::read_to_string(&self.path).ok()?;
+        let cached: CachedScenes = serde_json::from_str(&contents)
+            .inspect_err(|e| warn!("Ignoring invalid scene cache {}: {e}", self.path.display()))
+            .ok()?;
+        if cached.key != self.key {
+            debug!(
+                "Ignoring scene cache {} of another input or options",
+                self.path.display()
+            );
+            return None;
+        }
+
+        let scenes = cached
+            .scenes
+            .into_iter()
+            .map(|scene| Scene {
+                zone_overrides: self
+                    .zones
+                    .iter()
+                    .find(|zone| {
+                        zone.start_frame <= scene.start_frame && scene.end_frame <= zone.end_frame
+                    })
+                    .and_then(|zone| zone.zone_overrides.clone()),
+                ..scene
+            })
+            .collect();
+
+        Some((
+            scenes,
+            cached.frames,
+            cached.scores.into_iter().map(|(frame, score)| (frame, score.into())).collect(),
+        ))
+    }
+
+    /// Writes the scenes to the cache
+    pub(crate) fn store(&self, (scenes, frames, scores): &DetectedScenes) -> anyhow::Result<()> {
+        let cached = CachedScenes {
+            key:    self.key.clone(),
+            frames: *frames,
+            // The overrides come from the zones of the encode loading the scenes
+            scenes: scenes
+                .iter()
+                .map(|scene| Scene {
+                    zone_overrides: None,
+                    ..scene.clone()
+                })
+                .collect(),
+            scores: scores.iter().map(|(&frame, &score)| (frame, score.into())).collect(),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        // Write to a temporary file first so that concurrent encodes never read a
+        // partial entry
+        let tmp_path = self.path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&tmp_path, serde_json::to_string(&cached)?)
+            .with_context(|| format!("Failed to write scene cache {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Failed to write scene cache {}", self.path.display()))?;
+
+        Ok(())
+    }
+}
+
+/// Default directory of the scene cache, in the cache directory of the user
+#[inline]
+pub fn default_scene_cache_dir() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| Path::new(&home).join("Library").join("Caches"))
+    } else {
+        env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+
+    base.map(|base| base.join("av1an").join("scenes"))
+}
+
+/// Hash of the content of `input`. VapourSynth scripts are identified by
+/// their text and arguments, and by the files they load, which are the
+/// string literals and argument values that name an existing file.
+pub(crate) fn input_fingerprint(input: &Input) -> anyhow::Result<u64> {
+    let mut hasher = StableHasher::new();
+    let files = match input {
+        Input::VapourSynth {
+            path,
+            script_text,
+            vspipe_args,
+            ..
+        } => {
+            hasher.write(script_text.as_bytes());
+            for arg in vspipe_args {
+                hasher.write(&(arg.len() as u64).to_le_bytes());
+                hasher.write(arg.as_bytes());
+            }
+            script_sources(
+                path.parent().unwrap_or_else(|| Path::new("")),
+                script_text,
+                vspipe_args,
+            )
+        },
+        Input::Video {
+            path, ..
+        } => vec![path.clone()],
+    };
+    for file in files {
+        file_fingerprint(&file, &mut hasher)
+            .with_context(|| format!("Failed to fingerprint {}", file.display()))?;
+    }
+
+    Ok(hasher.finish())
+}
+
+/// Files a VapourSynth script loads, found as the string literals of the
+/// script and the values of its arguments that name an existing file,
+/// relative to the directory of the script
+fn script_sources(script_dir: &Path, script_text: &str, vspipe_args: &[String]) -> Vec<PathBuf> {
+    let literals = script_text.split(['"', '\'']).skip(1).step_by(2);
+    let values = vspipe_args.iter().filter_map(|arg| arg.split_once('=')).map(|(_, value)| value);
+
+    let mut sources: Vec<PathBuf> = literals
+        .chain(values)
+        .filter(|literal| !literal.is_empty())
+        .map(|literal| script_dir.join(literal))
+        .filter(|path| path.is_file())
+        .collect();
+    sources.sort();
+    sources.dedup();
+    sources
+}
+
+/// Fingerprints the file at `path` by its size and samples of
+/// [`FINGERPRINT_SAMPLE_SIZE`] bytes at the start, the middle and the end,
+/// rather than reading it whole. Changes that keep the size of a file larger
+/// than three samples and only touch bytes between the samples are not
+/// detected.
+fn file_fingerprint(path: &Path, hasher: &mut StableHasher) -> anyhow::Result<()> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    hasher.write(&len.to_le_bytes());
+
+    let mut sample = Vec::new();
+    for offset in [
+        0,
+        len.saturating_sub(FINGERPRINT_SAMPLE_SIZE) / 2,
+        len.saturating_sub(FINGERPRINT_SAMPLE_SIZE),
+    ] {
+        sample.clear();
+        file.seek(SeekFrom::Start(offset))?;
+        file.by_ref().take(FINGERPRINT_SAMPLE_SIZE).read_to_end(&mut sample)?;
+        hasher.write(&sample);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{encoder::Encoder, scenes::ZoneOptions};
+
+    fn key(frames: usize) -> SceneCacheKey {
+        SceneCacheKey {
+            input: 1,
+            frames,
+            encoder: "aom".to_owned(),
+            scaler: "bicubic".to_owned(),
+            sc_method: "standard".to_owned(),
+            sc_downscale_height: None,
+            sc_pix_format: None,
+            min_scene_len: 24,
+            zones: vec![],
+        }
+    }
+
+    #[test]
+    fn scene_cache_round_trip_and_fingerprint() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let temp = temp.path();
+
+        let cache = SceneCache {
+            path:  temp.join("scenes").join("0000000000000000.json"),
+            key:   key(60),
+            zones: vec![],
+        };
+        assert!(cache.load().is_none());
+        let scenes = vec![
+            Scene {
+                start_frame:    0,
+                end_frame:      24,
+                zone_overrides: None,
+            },
+            Scene {
+                start_frame:    24,
+                end_frame:      60,
+                zone_overrides: None,
+            },
+        ];
+        let scores = BTreeMap::from([(24, ScenecutResult {
+            inter_cost:             10.0,
+            imp_block_cost:         2.0,
+            backward_adjusted_cost: 3.0,
+            forward_adjusted_cost:  4.0,
+            threshold:              5.0,
+        })]);
+        cache.store(&(scenes, 60, scores)).expect("scenes should be cached");
+
+        let (cached_scenes, frames, cached_scores) = cache.load().expect("scenes should be loaded");
+        assert_eq!(frames, 60);
+        assert_eq!(
+            cached_scenes.iter().map(|s| (s.start_frame, s.end_frame)).collect::<Vec<_>>(),
+            [(0, 24), (24, 60)]
+        );
+        assert!((cached_scores[&24].threshold - 5.0).abs() < f64::EPSILON);
+        // An entry stored under the same hash for another key is a miss
+        let other = SceneCache {
+            path:  cache.path,
+            key:   key(61),
+            zones: vec![],
+        };
+        assert!(other.load().is_none());
+
+        let video = temp.join("video.mkv");
+        let fingerprint = |contents: &[u8]| -> anyhow::Result<u64> {
+            fs::write(&video, contents)?;
+            let mut hasher = StableHasher::new();
+            file_fingerprint(&video, &mut hasher)?;
+            Ok(hasher.finish())
+        };
+        assert_eq!(fingerprint(b"frames")?, fingerprint(b"frames")?);
+        assert_ne!(fingerprint(b"frames")?, fingerprint(b"framez")?);
+        // Only the size and three samples of larger files are compared, so a change
+        // between the samples goes unnoticed
+        let sample = FINGERPRINT_SAMPLE_SIZE as usize;
+        let mut large = vec![0; 4 * sample];
+        let unchanged = fingerprint(&large)?;
+        large[sample + sample / 4] = 1;
+        assert_eq!(fingerprint(&large)?, unchanged);
+        large[2 * sample] = 1;
+        assert_ne!(fingerprint(&large)?, unchanged);
+        large.push(0);
+        assert_ne!(fingerprint(&large)?, unchanged);
+
+        Ok(())
+    }
+
+    #[test]
+    fn zone_encoder_settings_do_not_invalidate_the_cache() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let zone = |video_params: &[&str], min_scene_len: usize| Scene {
+            start_frame:    24,
+            end_frame:      60,
+            zone_overrides: Some(ZoneOptions {
+                encoder: Encoder::aom,
+                passes: 1,
+                video_params: video_params.iter().map(|&param| param.to_owned()).collect(),
+                photon_noise: None,
+                photon_noise_height: None,
+                photon_noise_width: None,
+                chroma_noise: false,
+                extra_splits_len: None,
+                min_scene_len,
+                target_quality: None,
+            }),
+        };
+        let cache = |zones: Vec<Scene>| SceneCache {
+            path: temp.path().join("scenes.json"),
+            key: SceneCacheKey {
+                zones: zones.iter().map(ZoneKey::from).collect(),
+                ..key(60)
+            },
+            zones,
+        };
+
+        let scenes = vec![
+            Scene {
+                start_frame:    0,
+                end_frame:      24,
+                zone_overrides: None,
+            },
+            zone(&["--cq-level=30"], 24),
+        ];
+        cache(vec![zone(&["--cq-level=30"], 24)]).store(&(scenes, 60, BTreeMap::new()))?;
+
+        // The scenes get the overrides of the current zones
+        let (scenes, ..) = cache(vec![zone(&["--cq-level=20"], 24)])
+            .load()
+            .expect("scenes should be loaded");
+        assert!(scenes[0].zone_overrides.is_none());
+        assert_eq!(
+            scenes[1].zone_overrides.as_ref().map(|zone| zone.video_params.clone()),
+            Some(vec!["--cq-level=20".to_owned()])
+        );
+        // Scene detection reads the minimum scene length of zones
+        assert!(cache(vec![zone(&["--cq-level=20"], 12)]).load().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn vapoursynth_script_sources() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let temp = temp.path();
+        fs::write(temp.join("video.mkv"), b"frames")?;
+        fs::write(temp.join("proxy.mkv"), b"frames")?;
+
+        let script = "clip = core.lsmas.LWLibavSource(r\"video.mkv\", cachefile='missing.lwi')";
+        assert_eq!(
+            script_sources(temp, script, &["proxy=proxy.mkv".to_owned()]),
+            [temp.join("proxy.mkv"), temp.join("video.mkv")]
+        );
+        assert_eq!(script_sources(temp, script, &[]), [temp.join("video.mkv")]);
+
+        Ok(())
+    }
+}
@@ -357,3 +357,154 @@ fn build_decoder(
 
     Ok((decoder, bit_depth))
 }
+
+/// Cost relative to the scenecut threshold above which a frame is considered
+/// part of a gradual transition
+const TRANSITION_COST: f64 = 0.25;
+
+/// Minimum number of consecutive frames around a scenecut with a transition
+/// cost for the scenecut to be considered inside a fade or dissolve
+const MIN_TRANSITION_LEN: usize = 4;
+
+/// Maximum length in frames of a camera flash
+const MAX_FLASH_LEN: usize = 3;
+
+/// Scenecuts changed by [`refine_transitions`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct TransitionChanges {
+    /// Scenecuts moved to the end of a fade or dissolve
+    pub moved:   usize,
+    /// Scenecuts removed because they were caused by a flash
+    pub removed: usize,
+}
+
+/// Moves scenecuts found inside fades and dissolves to the frame after the
+/// transition, and removes scenecuts caused by flashes, using the scores of
+/// scene detection. Scenecuts at the boundaries of `zones` are kept, and moved
+/// scenecuts stay `min_scene_len` frames away from their neighbours.
+pub(crate) fn refine_transitions(
+    scenes: &[Scene],
+    scores: &BTreeMap<usize, ScenecutResult>,
+    min_scene_len: usize,
+    zones: &[Scene],
+) -> (Vec<Scene>, TransitionChanges) {
+    let relative_cost = |frame: usize| {
+        scores
+            .get(&frame)
+            .filter(|score| score.threshold > 0.0)
+            .map_or(0.0, |score| score.inter_cost / score.threshold)
+    };
+    let is_zone_boundary = |frame: usize| {
+        zones.iter().any(|zone| zone.start_frame == frame || zone.end_frame == frame)
+    };
+
+    let mut changes = TransitionChanges::default();
+    let Some(frames) = scenes.last().map(|scene| scene.end_frame) else {
+        return (Vec::new(), changes);
+    };
+    let scenecuts: Vec<usize> = scenes.iter().skip(1).map(|scene| scene.start_frame).collect();
+    let mut boundaries = vec![0];
+    for (index, &scenecut) in scenecuts.iter().enumerate() {
+        let previous = *boundaries.last().expect("boundaries start with 0");
+        let next = scenecuts.get(index + 1).copied().unwrap_or(frames);
+        if is_zone_boundary(scenecut) {
+            boundaries.push(scenecut);
+            continue;
+        }
+
+        // A flash jumps away from the scene and back within a few frames, while a shot
+        // change only jumps once
+        let flash_end = (scenecut + 1..=scenecut + MAX_FLASH_LEN)
+            .find(|&frame| frame < next && relative_cost(frame) >= 1.0);
+        if relative_cost(scenecut) >= 1.0
+            && flash_end.is_some_and(|end| relative_cost(end + 1) < TRANSITION_COST)
+        {
+            changes.removed += 1;
+            continue;
+        }
+
+        // Frames of a fade or dissolve all differ a little from the previous one
+        let run_start = (previous + 1..scenecut)
+            .rev()
+            .take_while(|&frame| relative_cost(frame) >= TRANSITION_COST)
+            .last()
+            .unwrap_or(scenecut);
+        let run_end = (scenecut + 1..next)
+            .find(|&frame| relative_cost(frame) < TRANSITION_COST)
+            .unwrap_or(next);
+        if run_end - run_start > MIN_TRANSITION_LEN
+            && run_end > scenecut + 1
+            && run_end >= previous + min_scene_len
+            && next >= run_end + min_scene_len
+        {
+            changes.moved += 1;
+            boundaries.push(run_end);
+        } else {
+            boundaries.push(scenecut);
+        }
+    }
+    boundaries.push(frames);
+
+    let refined = boundaries
+        .into_iter()
+        .tuple_windows()
+        .map(|(start_frame, end_frame)| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: scenes
+                .iter()
+                .find(|scene| (scene.start_frame..scene.end_frame).contains(&start_frame))
+                .and_then(|scene| scene.zone_overrides.clone()),
+        })
+        .collect();
+
+    (refined, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refine_transitions_moves_fades_and_removes_flashes() {
+        let score = |relative_cost: f64| ScenecutResult {
+            inter_cost:             relative_cost * 10.0,
+            imp_block_cost:         0.0,
+            backward_adjusted_cost: 0.0,
+            forward_adjusted_cost:  0.0,
+            threshold:              10.0,
+        };
+        let mut scores: BTreeMap<usize, ScenecutResult> =
+            (1..300).map(|frame| (frame, score(0.05))).collect();
+        // A dissolve from frame 96 to 105, cut by scene detection at frame 100
+        for frame in 96..106 {
+            scores.insert(frame, score(0.5));
+        }
+        scores.insert(100, score(1.2));
+        // A flash at frame 150
+        scores.insert(150, score(3.0));
+        scores.insert(151, score(3.0));
+        // A shot change at frame 200
+        scores.insert(200, score(2.0));
+        let scene = |start_frame, end_frame| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        };
+        let scenes = [scene(0, 100), scene(100, 150), scene(150, 200), scene(200, 300)];
+
+        let (refined, changes) = refine_transitions(&scenes, &scores, 24, &[]);
+        assert_eq!(
+            refined.iter().map(|s| (s.start_frame, s.end_frame)).collect::<Vec<_>>(),
+            [(0, 106), (106, 200), (200, 300)]
+        );
+        assert_eq!(changes, TransitionChanges {
+            moved:   1,
+            removed: 1,
+        });
+
+        // Zone boundaries are never moved
+        let (refined, _) = refine_transitions(&scenes, &scores, 24, &[scene(100, 300)]);
+        assert_eq!(refined[1].start_frame, 100);
+    }
+}
@@ -0,0 +1,177 @@
+use std::{fmt::Write, fs, path::PathBuf, str::FromStr};
+
+use anyhow::Context;
+
+use crate::{scenes::Scene, SceneExportFormat};
+
+/// Scene list to write after scene detection, parsed from `<format>=<path>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SceneExport {
+    pub format: SceneExportFormat,
+    pub path:   PathBuf,
+}
+
+impl FromStr for SceneExport {
+    type Err = String;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected <format>=<path>, found {s:?}"))?;
+        let format = SceneExportFormat::from_str(format.trim()).map_err(|_| {
+            format!(
+                "Unknown scene export format {format:?}, expected chapters, qpfile, edl or \
+                 timecodes"
+            )
+        })?;
+        if path.is_empty() {
+            return Err(format!("Missing the path of the {format} scene export"));
+        }
+
+        Ok(Self {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl SceneExport {
+    /// Writes `scenes` of the video `source_name` in the format of the export
+    pub(crate) fn write(
+        &self,
+        scenes: &[Scene],
+        frame_rate: f64,
+        source_name: &str,
+    ) -> anyhow::Result<()> {
+        fs::write(
+            &self.path,
+            render_scenes(scenes, self.format, frame_rate, source_name),
+        )
+        .with_context(|| {
+            format!(
+                "Failed to write {} scene export {}",
+                self.format,
+                self.path.display()
+            )
+        })
+    }
+}
+
+pub(crate) fn render_scenes(
+    scenes: &[Scene],
+    format: SceneExportFormat,
+    frame_rate: f64,
+    source_name: &str,
+) -> String {
+    let mut out = String::new();
+    match format {
+        SceneExportFormat::Chapters => {
+            out.push_str(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE Chapters SYSTEM \
+                 \"matroskachapters.dtd\">\n<Chapters>\n  <EditionEntry>\n",
+            );
+            for (index, scene) in scenes.iter().enumerate() {
+                let name = zone_description(scene).map_or_else(
+                    || format!("Scene {}", index + 1),
+                    |zone| format!("Scene {} ({zone})", index + 1),
+                );
+                writeln!(
+                    out,
+                    "    <ChapterAtom>\n      <ChapterTimeStart>{start}</ChapterTimeStart>\n      \
+                     <ChapterTimeEnd>{end}</ChapterTimeEnd>\n      <ChapterDisplay>\n        \
+                     <ChapterString>{name}</ChapterString>\n        \
+                     <ChapterLanguage>und</ChapterLanguage>\n      </ChapterDisplay>\n    \
+                     </ChapterAtom>",
+                    start = clock_time(scene.start_frame, frame_rate, 9),
+                    end = clock_time(scene.end_frame, frame_rate, 9),
+                    name = escape_xml(&name),
+                )
+                .expect("writing to a string should not fail");
+            }
+            out.push_str("  </EditionEntry>\n</Chapters>\n");
+        },
+        SceneExportFormat::Qpfile => {
+            for scene in scenes {
+                // K lets the encoder pick between an IDR and a recovery point
+                writeln!(out, "{} K", scene.start_frame)
+                    .expect("writing to a string should not fail");
+            }
+        },
+        SceneExportFormat::Edl => {
+            // Timecodes of CMX 3600 count whole frames at the nominal frame rate
+            let timebase = (frame_rate.round() as usize).max(1);
+            writeln!(out, "TITLE: {source_name}\nFCM: NON-DROP FRAME\n")
+                .expect("writing to a string should not fail");
+            for (index, scene) in scenes.iter().enumerate() {
+                let (start, end) = (
+                    smpte_time(scene.start_frame, timebase),
+                    smpte_time(scene.end_frame, timebase),
+                );
+                writeln!(
+                    out,
+                    "{event:03}  AX       V     C        {start} {end} {start} {end}\n* FROM CLIP \
+                     NAME: {source_name}",
+                    event = index + 1
+                )
+                .expect("writing to a string should not fail");
+                if let Some(zone) = zone_description(scene) {
+                    writeln!(out, "* COMMENT: ZONE {zone}")
+                        .expect("writing to a string should not fail");
+                }
+                out.push('\n');
+            }
+        },
+        SceneExportFormat::Timecodes => {
+            for scene in scenes {
+                writeln!(out, "{}", clock_time(scene.start_frame, frame_rate, 3))
+                    .expect("writing to a string should not fail");
+            }
+        },
+    }
+
+    out
+}
+
+/// Encoder and parameters of the zone `scene` belongs to, if any
+fn zone_description(scene: &Scene) -> Option<String> {
+    let zone = scene.zone_overrides.as_ref()?;
+    Some(if zone.video_params.is_empty() {
+        zone.encoder.to_string()
+    } else {
+        format!("{} {}", zone.encoder, zone.video_params.join(" "))
+    })
+}
+
+/// Time of `frame` as `HH:MM:SS.fff` with `decimals` digits of fractional
+/// seconds
+fn clock_time(frame: usize, frame_rate: f64, decimals: u32) -> String {
+    let scale = 10u64.pow(decimals);
+    let units = (frame as f64 / frame_rate * scale as f64).round() as u64;
+    let seconds = units / scale;
+    format!(
+        "{:02}:{:02}:{:02}.{:0width$}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        units % scale,
+        width = decimals as usize
+    )
+}
+
+/// SMPTE timecode `HH:MM:SS:FF` of `frame` counting `timebase` frames per
+/// second
+fn smpte_time(frame: usize, timebase: usize) -> String {
+    let seconds = frame / timebase;
+    format!(
+        "{:02}:{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        frame % timebase
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
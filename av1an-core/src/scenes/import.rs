@@ -0,0 +1,179 @@
+//! Readers of scene lists written by other tools, which only provide the
+//! frames that start a new scene
+
+use std::{fs, path::Path};
+
+use anyhow::{bail, ensure, Context};
+
+use crate::SceneFormat;
+
+/// Reads the frames starting a scene from the scene list at `path`, converting
+/// timestamps to frames with `frame_rate`. The frames are sorted and
+/// deduplicated but not checked against the length of the video.
+pub(crate) fn read_scenecuts(
+    path: &Path,
+    format: SceneFormat,
+    frame_rate: f64,
+) -> anyhow::Result<Vec<usize>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read scene list {}", path.display()))?;
+
+    parse_scenecuts(&contents, format, frame_rate)
+        .with_context(|| format!("Failed to parse {format} scene list {}", path.display()))
+}
+
+/// Guesses the format of a scene list from its extension and contents
+pub(crate) fn detect_format(path: &Path) -> anyhow::Result<SceneFormat> {
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")) {
+        return Ok(SceneFormat::Av1an);
+    }
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read scene list {}", path.display()))?;
+
+    Ok(detect_contents(&contents))
+}
+
+fn detect_contents(contents: &str) -> SceneFormat {
+    let lines = || contents.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    if contents.trim_start().starts_with('{') {
+        SceneFormat::Av1an
+    } else if lines().take(2).any(|line| line.starts_with("Scene Number")) {
+        SceneFormat::PySceneDetect
+    } else if contents.contains("lavfi.scd.time") || contents.contains("pts_time:") {
+        SceneFormat::FFmpeg
+    } else if contents.contains("<Chapters") || lines().any(|line| line.starts_with("CHAPTER")) {
+        SceneFormat::Chapters
+    } else if lines().all(|line| {
+        let mut fields = line.split_whitespace();
+        fields.next().is_some_and(|frame| frame.parse::<usize>().is_ok())
+            && fields.next().is_some_and(|kind| "IiKPBb".contains(kind))
+    }) {
+        SceneFormat::Qpfile
+    } else if contents.contains(':') {
+        SceneFormat::Timecodes
+    } else {
+        SceneFormat::Frames
+    }
+}
+
+pub(crate) fn parse_scenecuts(
+    contents: &str,
+    format: SceneFormat,
+    frame_rate: f64,
+) -> anyhow::Result<Vec<usize>> {
+    let to_frame = |seconds: f64| (seconds * frame_rate).round() as usize;
+    let lines = contents.lines().map(str::trim).filter(|line| !line.is_empty());
+
+    let mut scenecuts = match format {
+        SceneFormat::Auto => {
+            return parse_scenecuts(contents, detect_contents(contents), frame_rate);
+        },
+        SceneFormat::Av1an => bail!("av1an scene files are not a list of scenecuts"),
+        SceneFormat::PySceneDetect => {
+            // An optional "Timecode List:" line comes before the header
+            let mut lines = lines.skip_while(|line| !line.starts_with("Scene Number"));
+            let header = lines.next().context("Missing the \"Scene Number\" header")?;
+            let column = header
+                .split(',')
+                .position(|name| name.trim() == "Start Time (seconds)")
+                .context("Missing the \"Start Time (seconds)\" column")?;
+            lines
+                .map(|line| {
+                    let field = line.split(',').nth(column).unwrap_or_default().trim();
+                    let seconds: f64 =
+                        field.parse().with_context(|| format!("Invalid start time {field:?}"))?;
+                    Ok(to_frame(seconds))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        },
+        SceneFormat::FFmpeg => lines
+            .filter_map(|line| {
+                // scdet logs the time of every scene change, showinfo of every frame it is
+                // given, which is usually the output of a scene selection filter
+                let (_, rest) =
+                    line.split_once("lavfi.scd.time:").or_else(|| line.split_once("pts_time:"))?;
+                let seconds = rest.split_whitespace().next()?.trim_end_matches(',');
+                Some(
+                    seconds
+                        .parse::<f64>()
+                        .map(to_frame)
+                        .with_context(|| format!("Invalid time {seconds:?}")),
+                )
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        SceneFormat::Qpfile => lines
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let frame = fields.next()?;
+                // Only keyframes start a scene
+                fields.next().filter(|kind| matches!(*kind, "I" | "K"))?;
+                Some(frame.parse::<usize>().with_context(|| format!("Invalid frame {frame:?}")))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        SceneFormat::Chapters => {
+            if contents.contains("<Chapters") {
+                contents
+                    .split("<ChapterTimeStart>")
+                    .skip(1)
+                    .map(|chapter| {
+                        let timestamp = chapter
+                            .split_once("</ChapterTimeStart>")
+                            .context("Unterminated ChapterTimeStart")?
+                            .0;
+                        Ok(to_frame(parse_timestamp(timestamp.trim(), frame_rate)?))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                // Simple chapters, with a name line following every time line
+                lines
+                    .filter_map(|line| {
+                        let (key, timestamp) = line.split_once('=')?;
+                        (!key.ends_with("NAME"))
+                            .then(|| Ok(to_frame(parse_timestamp(timestamp, frame_rate)?)))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            }
+        },
+        SceneFormat::Frames => contents
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|frame| !frame.is_empty())
+            .map(|frame| frame.parse::<usize>().with_context(|| format!("Invalid frame {frame:?}")))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        SceneFormat::Timecodes => contents
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|timestamp| !timestamp.is_empty())
+            .map(|timestamp| Ok(to_frame(parse_timestamp(timestamp, frame_rate)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+    };
+
+    scenecuts.sort_unstable();
+    scenecuts.dedup();
+    Ok(scenecuts)
+}
+
+/// Parses a timestamp in seconds: `[[HH:]MM:]SS[.sss]`, or SMPTE
+/// `HH:MM:SS:FF` with the frames counted at `frame_rate`
+fn parse_timestamp(timestamp: &str, frame_rate: f64) -> anyhow::Result<f64> {
+    let fields = timestamp
+        .split(':')
+        .map(|field| {
+            field
+                .trim()
+                .parse::<f64>()
+                .with_context(|| format!("Invalid timestamp {timestamp:?}"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    ensure!(
+        (1..=4).contains(&fields.len()) && fields.iter().all(|&field| field >= 0.0),
+        "Invalid timestamp {timestamp:?}"
+    );
+
+    Ok(match fields[..] {
+        [hours, minutes, seconds, frames] => {
+            hours.mul_add(3600.0, minutes.mul_add(60.0, seconds)) + frames / frame_rate
+        },
+        _ => fields.iter().fold(0.0, |total, &field| total.mul_add(60.0, field)),
+    })
+}
@@ -1,3 +1,5 @@
+pub(crate) mod export;
+pub(crate) mod import;
 #[cfg(test)]
 mod tests;
 
@@ -11,7 +13,8 @@ use std::{
     sync::atomic,
 };
 
-use anyhow::{anyhow, bail, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use av_scenechange::ScenecutResult;
 use itertools::Itertools;
 use nom::{
     branch::alt,
@@ -22,18 +25,22 @@ use nom::{
     sequence::preceded,
     Parser,
 };
+use num_traits::cast::ToPrimitive;
 use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
 use crate::{
     get_done,
     parse::valid_params,
-    scene_detect::av_scenechange_detect,
+    scene_cache::SceneCache,
+    scene_detect::{av_scenechange_detect, refine_transitions},
     settings::{invalid_params, suggest_fix},
     split::extra_splits,
     EncodeArgs,
     Encoder,
+    SceneFormat,
     SplitMethod,
+    TargetFallback,
     TargetMetric,
     TargetQuality,
 };
@@ -201,6 +208,14 @@ impl Scene {
             let parsed = TargetQuality::parse_target_qp_range(zone_target_quality)
                 .map_err(|e| anyhow!("Invalid --target-quality: {}", e))?;
             target_quality.target = Some(parsed);
+            target_quality.constraints.clear();
+        }
+        if let Some(Some(zone_target_constraints)) = zone_args.remove("--target-constraints") {
+            target_quality.constraints = zone_target_constraints
+                .split(',')
+                .map(TargetQuality::parse_constraint)
+                .collect::<Result<_, _>>()
+                .map_err(|e| anyhow!("Invalid --target-constraints: {}", e))?;
         }
         if let Some(Some(zone_target_metric)) = zone_args.remove("--target-metric") {
             let parsed = TargetMetric::from_str(zone_target_metric)
@@ -213,6 +228,16 @@ impl Scene {
             target_quality.min_q = min;
             target_quality.max_q = max;
         }
+        if let Some(Some(zone_target_fallback)) = zone_args.remove("--target-fallback") {
+            let parsed = TargetFallback::from_str(zone_target_fallback)
+                .map_err(|_| anyhow!("Invalid --target-fallback: {}", zone_target_fallback))?;
+            target_quality.fallback = parsed;
+        }
+        if let Some(Some(zone_frame_floor)) = zone_args.remove("--frame-floor") {
+            let parsed = TargetQuality::parse_frame_floor(zone_frame_floor)
+                .map_err(|e| anyhow!("Invalid --frame-floor: {}", e))?;
+            target_quality.frame_floor = Some(parsed);
+        }
         if let Some(Some(zone_probes)) = zone_args.remove("--probes") {
             let parsed =
                 zone_probes.parse().map_err(|_| anyhow!("Invalid --probes: {}", zone_probes))?;
@@ -233,6 +258,25 @@ impl Scene {
                 warn!("{}", warning);
             }
             target_quality.probing_rate = probing_rate;
+            target_quality.probing_windows = None;
+        }
+        if let Some(Some(zone_probing_windows)) = zone_args.remove("--probing-windows") {
+            let parsed = zone_probing_windows
+                .parse()
+                .map_err(|e| anyhow!("Invalid --probing-windows: {}", e))?;
+            target_quality.probing_windows = Some(parsed);
+        }
+        if let Some(Some(zone_search_param)) = zone_args.remove("--search-param") {
+            let parsed = zone_search_param
+                .parse()
+                .map_err(|e| anyhow!("Invalid --search-param: {}", e))?;
+            if args.target_size.is_some() {
+                bail!(
+                    "--search-param cannot be used with --target-size, which only sets the \
+                     quantizer"
+                );
+            }
+            target_quality.search_parameter = Some(parsed);
         }
         if let Some(Some(zone_probe_res)) = zone_args.remove("--probe-res") {
             let (width, height) = TargetQuality::parse_probe_res(zone_probe_res)
@@ -249,6 +293,12 @@ impl Scene {
                 .map_err(|e| anyhow!("Invalid --interp-method: {}", e))?;
             target_quality.interp_method = Some((method4, method5));
         }
+        // The zone may have changed the metric or target the floor was checked with
+        target_quality.validate_frame_floor()?;
+        ensure!(
+            target_quality.hull_denominators.is_empty() || encoder.resize_args(16).is_some(),
+            "--hull-denominators requires aom or svt-av1, but the zone uses {encoder}"
+        );
 
         let raw_zone_args = if [Encoder::aom, Encoder::vpx].contains(&encoder) {
             zone_args
@@ -325,6 +375,11 @@ impl Scene {
             video_params.push(arg);
         }
 
+        // A zone may search a parameter without a quantizer to keep fixed
+        if target_quality.is_enabled() {
+            target_quality.fixed_quantizer(&video_params)?;
+        }
+
         Ok(Self {
             start_frame:    start,
             end_frame:      end,
@@ -443,19 +498,52 @@ impl SceneFactory {
 
         let frames = args.input.clip_info()?.num_frames;
 
-        let (mut scenes, frames, scores) = match args.split_method {
-            SplitMethod::AvScenechange => av_scenechange_detect(
-                args.proxy.as_ref().unwrap_or(&args.input),
-                args.encoder,
-                frames,
-                args.min_scene_len,
-                args.verbosity,
-                args.scaler.as_str(),
-                args.sc_pix_format,
-                args.sc_method,
-                args.sc_downscale_height,
-                zones,
-            )?,
+        let (scenes, frames, scores) = match args.split_method {
+            SplitMethod::AvScenechange => {
+                let cache = args.scene_cache.as_deref().and_then(|dir| {
+                    SceneCache::new(dir, args, frames, zones)
+                        .inspect_err(|e| warn!("Not using the scene cache: {e:#}"))
+                        .ok()
+                });
+                let (scenes, frames, scores) =
+                    if let Some(cached) = cache.as_ref().and_then(SceneCache::load) {
+                        info!(
+                            "scenecut: using cached scenes from {}",
+                            cache.as_ref().expect("cache exists").path().display()
+                        );
+                        cached
+                    } else {
+                        let detected = av_scenechange_detect(
+                            args.proxy.as_ref().unwrap_or(&args.input),
+                            args.encoder,
+                            frames,
+                            args.min_scene_len,
+                            args.verbosity,
+                            args.scaler.as_str(),
+                            args.sc_pix_format,
+                            args.sc_method,
+                            args.sc_downscale_height,
+                            zones,
+                        )?;
+                        if let Some(cache) = &cache {
+                            if let Err(e) = cache.store(&detected) {
+                                warn!("Failed to cache the scenes: {e:#}");
+                            }
+                        }
+                        detected
+                    };
+                if args.sc_refine {
+                    let (refined, changes) =
+                        refine_transitions(&scenes, &scores, args.min_scene_len, zones);
+                    info!(
+                        "scenecut: moved {} scenecut(s) out of fades, removed {} flash scenecut(s)",
+                        changes.moved, changes.removed
+                    );
+                    (refined, frames, scores)
+                } else {
+                    (scenes, frames, scores)
+                }
+            },
             SplitMethod::None => {
                 let mut scenes = Vec::with_capacity(2 * zones.len() + 1);
                 let mut frames_processed = 0;
@@ -487,6 +575,76 @@ impl SceneFactory {
             },
         };
 
+        self.finish_scenes(args, scenes, frames, &scores);
+
+        Ok(())
+    }
+
+    /// Reads the scenecuts of a scene list written by another tool and
+    /// populates the factory with the scenes between them, split at the
+    /// boundaries of `zones`. Like [`Self::compute_scenes`], this must be
+    /// called before getting the list of scenes.
+    pub fn import_scenes(
+        &mut self,
+        path: &Path,
+        format: SceneFormat,
+        args: &EncodeArgs,
+        zones: &[Scene],
+    ) -> anyhow::Result<()> {
+        debug_assert!(self.data.scenes.is_none());
+
+        let clip_info = args.input.clip_info()?;
+        let frames = clip_info.num_frames;
+        let frame_rate = clip_info.frame_rate.to_f64().expect("frame rate should not be NaN");
+        let scenecuts = import::read_scenecuts(path, format, frame_rate)?;
+        if let Some(&last) = scenecuts.last().filter(|&&last| last >= frames) {
+            warn!(
+                "scene list {} has a scenecut at frame {last} but video has {frames} frames, \
+                 ignoring the scenecuts past the end",
+                path.display()
+            );
+        }
+        info!(
+            "scenecut: imported {} scenecut(s) from {}",
+            scenecuts.len(),
+            path.display()
+        );
+
+        let mut boundaries: Vec<usize> = scenecuts
+            .into_iter()
+            .chain(zones.iter().flat_map(|zone| [zone.start_frame, zone.end_frame]))
+            .filter(|&frame| frame > 0 && frame < frames)
+            .chain([0, frames])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        let scenes = boundaries
+            .into_iter()
+            .tuple_windows()
+            .map(|(start_frame, end_frame)| Scene {
+                start_frame,
+                end_frame,
+                zone_overrides: zones
+                    .iter()
+                    .find(|zone| (zone.start_frame..zone.end_frame).contains(&start_frame))
+                    .and_then(|zone| zone.zone_overrides.clone()),
+            })
+            .collect();
+
+        self.finish_scenes(args, scenes, frames, &BTreeMap::new());
+
+        Ok(())
+    }
+
+    /// Adds the forced keyframes and the extra splits to the detected
+    /// `scenes` and stores them in the factory
+    fn finish_scenes(
+        &mut self,
+        args: &EncodeArgs,
+        mut scenes: Vec<Scene>,
+        frames: usize,
+        scores: &BTreeMap<usize, ScenecutResult>,
+    ) {
         self.data.frames = frames;
         get_done().frames.store(frames, atomic::Ordering::SeqCst);
 
@@ -528,7 +686,7 @@ impl SceneFactory {
             self.data.split_scenes = Some(extra_splits(
                 self.data.scenes.as_deref().expect("scenes is set"),
                 split_len,
-                &scores,
+                scores,
             ));
             let scenes_after = self.data.split_scenes.as_ref().expect("split_scenes is set").len();
             info!(
@@ -539,7 +697,5 @@ impl SceneFactory {
             self.data.split_scenes = self.data.scenes.clone();
             info!("scenecut: found {scenes_before} scene(s)");
         }
-
-        Ok(())
     }
 }
@@ -21,6 +21,7 @@ fn get_test_args() -> Av1anContext {
         ChunkMethod,
         ChunkOrdering,
         Input,
+        SceneFormat,
         ScenecutMethod,
         SplitMethod,
         Verbosity,
@@ -63,13 +64,18 @@ fn get_test_args() -> Av1anContext {
         },
         resume:                false,
         scenes:                None,
+        scenes_format:         SceneFormat::Auto,
+        scene_cache:           None,
         split_method:          SplitMethod::AvScenechange,
         sc_method:             ScenecutMethod::Standard,
         sc_only:               false,
+        sc_export:             vec![],
         sc_downscale_height:   None,
+        sc_refine:             false,
         force_keyframes:       Vec::new(),
         target_quality:        TargetQuality::default("", Encoder::aom),
         vmaf:                  false,
+        analysis_metrics:      vec![],
         verbosity:             Verbosity::Normal,
         workers:               1,
         tiles:                 (1, 1),
@@ -82,6 +88,8 @@ fn get_test_args() -> Av1anContext {
         vmaf_res:              "1920x1080".to_string(),
         vmaf_threads:          None,
         vmaf_filter:           None,
+        target_size:           None,
+        tq_report:             false,
         probe_res:             None,
         vapoursynth_plugins:   None,
     };
@@ -247,8 +255,9 @@ fn validate_zones_target_quality() {
     let desired_qp_max = 35;
     let desired_probe_res = (1280, 720);
     let desired_probing_stat = ProbingStatistic {
-        name:  crate::ProbingStatisticName::Mode,
-        value: None,
+        name:       crate::ProbingStatisticName::Mode,
+        value:      None,
+        expression: None,
     };
     let desired_interpolation_method4 = "natural";
     let desired_interpolation_method5 = "cubic";
@@ -301,3 +310,88 @@ fn validate_zones_target_quality() {
         ))
     );
 }
+
+#[test]
+fn import_scenecuts_from_external_formats() {
+    use crate::{scenes::import::parse_scenecuts, SceneFormat};
+
+    let cases =
+        [
+            (
+                SceneFormat::PySceneDetect,
+                "Timecode List:,00:00:02.000\nScene Number,Start Frame,Start Timecode,Start Time \
+                 (seconds),End Frame\n1,1,00:00:00.000,0.000,48\n2,49,00:00:02.000,2.000,120\n",
+            ),
+            (
+                SceneFormat::FFmpeg,
+                "[scdet @ 0x1] lavfi.scd.score: 42.1, lavfi.scd.time: 2\n[Parsed_showinfo_1 @ \
+                 0x2] n:   1 pts:  5000 pts_time:5 duration: 1\n",
+            ),
+            (SceneFormat::Qpfile, "0 I\n12 P\n48 K\n120 I -1\n"),
+            (
+                SceneFormat::Chapters,
+                "<Chapters><EditionEntry><ChapterAtom><ChapterTimeStart>00:00:02.000000000</\
+                 ChapterTimeStart></ChapterAtom></EditionEntry></Chapters>",
+            ),
+            (
+                SceneFormat::Chapters,
+                "CHAPTER01=00:00:00.000\nCHAPTER01NAME=Intro\nCHAPTER02=00:00:05.000\n",
+            ),
+            (SceneFormat::Frames, "120, 48\n48"),
+            (SceneFormat::Timecodes, "00:00:02.000 00:05 00:00:05:00"),
+        ];
+    let expected = [
+        vec![0, 48],
+        vec![48, 120],
+        vec![0, 48, 120],
+        vec![48],
+        vec![0, 120],
+        vec![48, 120],
+        vec![48, 120],
+    ];
+    for ((format, contents), expected) in cases.into_iter().zip(expected) {
+        assert_eq!(
+            parse_scenecuts(contents, format, 24.0).expect("scene list should parse"),
+            expected,
+            "{format}"
+        );
+        assert_eq!(
+            parse_scenecuts(contents, SceneFormat::Auto, 24.0).expect("scene list should parse"),
+            expected,
+            "auto {format}"
+        );
+    }
+}
+
+#[test]
+fn exported_scenes_import_back() {
+    use crate::{
+        scenes::{export::render_scenes, import::parse_scenecuts},
+        SceneExportFormat,
+        SceneFormat,
+    };
+
+    let scenes: Vec<Scene> = [(0, 48), (48, 101), (101, 240)]
+        .into_iter()
+        .map(|(start_frame, end_frame)| Scene {
+            start_frame,
+            end_frame,
+            zone_overrides: None,
+        })
+        .collect();
+    for (export, import) in [
+        (SceneExportFormat::Chapters, SceneFormat::Chapters),
+        (SceneExportFormat::Qpfile, SceneFormat::Qpfile),
+        (SceneExportFormat::Timecodes, SceneFormat::Timecodes),
+    ] {
+        let contents = render_scenes(&scenes, export, 23.976, "input.mkv");
+        assert_eq!(
+            parse_scenecuts(&contents, import, 23.976).expect("export should import"),
+            [0, 48, 101],
+            "{export}"
+        );
+    }
+
+    let edl = render_scenes(&scenes, SceneExportFormat::Edl, 24.0, "input.mkv");
+    assert!(edl.contains("002  AX       V     C        00:00:02:00 00:00:04:05"));
+}
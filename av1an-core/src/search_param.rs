@@ -0,0 +1,216 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::encoder::format_q;
+
+/// How the value of a search parameter is passed to the encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ParameterSyntax {
+    /// `--name value`
+    Separate,
+    /// `--name=value`
+    Equals,
+}
+
+/// How the quality changes as the value of a search parameter increases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchDirection {
+    /// Higher values lower the quality, like a quantizer or a film grain
+    /// strength
+    Decreasing,
+    /// Higher values raise the quality
+    Increasing,
+}
+
+/// A numeric encoder parameter searched by target quality instead of the
+/// quantizer, which stays fixed at the value in the video parameters
+///
+/// The search itself always works on positions in `min..=max` where higher
+/// positions give lower quality, like a quantizer. For parameters where
+/// higher values raise the quality, positions are mirrored, so the position
+/// `min` is the value `max`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchParameter {
+    pub name:      String,
+    pub syntax:    ParameterSyntax,
+    pub min:       f32,
+    pub max:       f32,
+    pub step:      f32,
+    pub direction: SearchDirection,
+}
+
+impl SearchParameter {
+    /// Value of the parameter at search position `position`
+    #[inline]
+    pub fn value(&self, position: f32) -> f32 {
+        match self.direction {
+            SearchDirection::Decreasing => position,
+            SearchDirection::Increasing => self.min + self.max - position,
+        }
+    }
+
+    /// Sets the parameter to `value` in `params`, replacing any value already
+    /// set or appending the parameter otherwise
+    #[inline]
+    pub fn apply(&self, mut params: Vec<String>, value: f32) -> Vec<String> {
+        let value = format_q(value);
+        match self.syntax {
+            ParameterSyntax::Separate => {
+                if let Some(index) = params.iter().position(|param| *param == self.name) {
+                    if index + 1 < params.len() {
+                        params[index + 1] = value;
+                    } else {
+                        params.push(value);
+                    }
+                } else {
+                    params.push(self.name.clone());
+                    params.push(value);
+                }
+            },
+            ParameterSyntax::Equals => {
+                let prefix = format!("{}=", self.name);
+                let param = format!("{prefix}{value}");
+                if let Some(index) = params.iter().position(|p| p.starts_with(&prefix)) {
+                    params[index] = param;
+                } else {
+                    params.push(param);
+                }
+            },
+        }
+
+        params
+    }
+}
+
+impl FromStr for SearchParameter {
+    type Err = String;
+
+    /// Parses `<name>[=]:<min>..<max>[:<step>][:<direction>]`, where a
+    /// trailing `=` on the name selects `--name=value` syntax and the
+    /// direction is either `decreasing` (default) or `increasing`
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let name = parts.next().unwrap_or_default().trim();
+        let (name, syntax) =
+            name.strip_suffix('=').map_or((name, ParameterSyntax::Separate), |name| {
+                (name, ParameterSyntax::Equals)
+            });
+        if name.is_empty() {
+            return Err(format!(
+                "Invalid search parameter: {s}. Use <name>[=]:<min>..<max>[:<step>][:<direction>]"
+            ));
+        }
+
+        let range = parts
+            .next()
+            .ok_or_else(|| format!("Search parameter {name} requires a range, e.g. 0..50"))?;
+        let (min, max) = range
+            .split_once("..")
+            .ok_or_else(|| format!("Invalid search parameter range: {range}. Use <min>..<max>"))?;
+        let parse_number = |value: &str| {
+            value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .ok_or_else(|| format!("Invalid search parameter value: {value}"))
+        };
+        let (min, max) = (parse_number(min)?, parse_number(max)?);
+        if min >= max {
+            return Err(format!(
+                "Search parameter minimum ({min}) must be less than maximum ({max})"
+            ));
+        }
+
+        let step = parts.next().map_or(Ok(1.0), parse_number)?;
+        if step <= 0.0 {
+            return Err("Search parameter step must be greater than 0".to_string());
+        }
+
+        let direction = match parts.next().map(str::trim) {
+            None | Some("decreasing") => SearchDirection::Decreasing,
+            Some("increasing") => SearchDirection::Increasing,
+            Some(direction) => {
+                return Err(format!(
+                    "Invalid search parameter direction: {direction}. Use decreasing or increasing"
+                ))
+            },
+        };
+        if parts.next().is_some() {
+            return Err(format!("Invalid search parameter: {s}. Too many fields"));
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            syntax,
+            min,
+            max,
+            step,
+            direction,
+        })
+    }
+}
+
+impl Display for SearchParameter {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let equals = if self.syntax == ParameterSyntax::Equals {
+            "="
+        } else {
+            ""
+        };
+        let direction = match self.direction {
+            SearchDirection::Decreasing => "decreasing",
+            SearchDirection::Increasing => "increasing",
+        };
+        write!(
+            f,
+            "{}{equals}:{}..{}:{}:{direction}",
+            self.name, self.min, self.max, self.step
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply_search_parameter() {
+        let grain: SearchParameter = "--film-grain:0..50".parse().unwrap();
+        assert_eq!(grain.syntax, ParameterSyntax::Separate);
+        assert_eq!(grain.direction, SearchDirection::Decreasing);
+        assert!((grain.step - 1.0).abs() < f32::EPSILON);
+        let params = vec!["--crf".to_string(), "30".to_string()];
+        assert_eq!(grain.apply(params.clone(), 8.0), [
+            "--crf",
+            "30",
+            "--film-grain",
+            "8"
+        ]);
+        assert_eq!(grain.apply(grain.apply(params, 8.0), 12.0), [
+            "--crf",
+            "30",
+            "--film-grain",
+            "12"
+        ]);
+
+        let sharpness: SearchParameter = "--sharpness=:-7..7:1:increasing".parse().unwrap();
+        assert_eq!(sharpness.syntax, ParameterSyntax::Equals);
+        assert!((sharpness.value(-7.0) - 7.0).abs() < f32::EPSILON);
+        assert_eq!(sharpness.apply(vec!["--sharpness=0".to_string()], 3.0), [
+            "--sharpness=3"
+        ]);
+        assert_eq!(
+            sharpness.to_string().parse::<SearchParameter>().unwrap(),
+            sharpness
+        );
+
+        assert!("--film-grain".parse::<SearchParameter>().is_err());
+        assert!("--film-grain:50..0".parse::<SearchParameter>().is_err());
+        assert!("--film-grain:0..50:0".parse::<SearchParameter>().is_err());
+        assert!("--film-grain:0..50:1:up".parse::<SearchParameter>().is_err());
+    }
+}
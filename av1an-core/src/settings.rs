@@ -17,11 +17,13 @@ use crate::{
     ffmpeg::FFPixelFormat,
     metrics::{vmaf::validate_libvmaf, xpsnr::validate_libxpsnr},
     parse::valid_params,
+    scenes::export::SceneExport,
     target_quality::TargetQuality,
     vapoursynth::{VSZipVersion, VapoursynthPlugins},
     ChunkMethod,
     ChunkOrdering,
     Input,
+    SceneFormat,
     ScenecutMethod,
     SplitMethod,
     TargetMetric,
@@ -78,11 +80,20 @@ pub struct EncodeArgs {
     pub chunk_order:           ChunkOrdering,
     pub scaler:                String,
     pub scenes:                Option<PathBuf>,
+    pub scenes_format:         SceneFormat,
+    /// Directory of the scene detection results reused across encodes, or
+    /// None to always run scene detection
+    pub scene_cache:           Option<PathBuf>,
     pub split_method:          SplitMethod,
     pub sc_pix_format:         Option<FFPixelFormat>,
     pub sc_method:             ScenecutMethod,
     pub sc_only:               bool,
+    /// Scene lists written after scene detection
+    pub sc_export:             Vec<SceneExport>,
     pub sc_downscale_height:   Option<usize>,
+    /// Move scenecuts out of fades and dissolves and remove those caused by
+    /// flashes
+    pub sc_refine:             bool,
     pub extra_splits_len:      Option<usize>,
     pub min_scene_len:         usize,
     pub force_keyframes:       Vec<usize>,
@@ -115,14 +126,18 @@ pub struct EncodeArgs {
     pub no_defaults: bool,
     pub tile_auto:   bool,
 
-    pub concat:         ConcatMethod,
-    pub target_quality: TargetQuality,
-    pub vmaf:           bool,
-    pub vmaf_path:      Option<PathBuf>,
-    pub vmaf_res:       String,
-    pub probe_res:      Option<String>,
-    pub vmaf_threads:   Option<usize>,
-    pub vmaf_filter:    Option<String>,
+    pub concat:           ConcatMethod,
+    pub target_quality:   TargetQuality,
+    pub vmaf:             bool,
+    /// Metrics calculated on the whole encode once it is finished
+    pub analysis_metrics: Vec<TargetMetric>,
+    pub vmaf_path:        Option<PathBuf>,
+    pub vmaf_res:         String,
+    pub probe_res:        Option<String>,
+    pub vmaf_threads:     Option<usize>,
+    pub vmaf_filter:      Option<String>,
+    pub target_size:      Option<u64>,
+    pub tq_report:        bool,
 
     pub vapoursynth_plugins: Option<VapoursynthPlugins>,
 }
@@ -165,7 +180,7 @@ impl EncodeArgs {
             );
         }
 
-        if self.target_quality.target.is_some() && self.input.is_vapoursynth() {
+        if self.target_quality.is_enabled() && self.input.is_vapoursynth() {
             let input_absolute_path = absolute(self.input.as_path())?;
             if !input_absolute_path.starts_with(std::env::current_dir()?) {
                 warn!(
@@ -174,16 +189,27 @@ impl EncodeArgs {
                 );
             }
         }
-        if self.target_quality.target.is_some() {
-            match self.target_quality.metric {
-                TargetMetric::VMAF => validate_libvmaf()?,
-                TargetMetric::SSIMULACRA2 => self.validate_ssimulacra2()?,
-                TargetMetric::ButteraugliINF => self.validate_butteraugli_inf()?,
-                TargetMetric::Butteraugli3 => self.validate_butteraugli_3()?,
-                TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => self
-                    .validate_xpsnr(self.target_quality.metric, self.target_quality.probing_rate)?,
+        ensure!(
+            self.target_size.is_none() || !self.target_quality.is_enabled(),
+            "--target-size cannot be used with --target-quality or --target-constraints, as both \
+             choose the quantizers"
+        );
+        ensure!(
+            self.target_size.is_none() || self.target_quality.search_parameter.is_none(),
+            "--search-param cannot be used with --target-size, which only sets the quantizer"
+        );
+        if self.target_quality.is_enabled() || self.target_size.is_some() {
+            for metric in self.target_quality.metrics() {
+                self.validate_metric(metric, self.target_quality.samples_frames())?;
             }
         }
+        for &metric in &self.analysis_metrics {
+            ensure!(
+                metric != TargetMetric::Bitrate,
+                "Bitrate cannot be analyzed as it has no per-frame scores"
+            );
+            self.validate_metric(metric, false)?;
+        }
 
         if which::which("ffmpeg").is_err() {
             bail!("FFmpeg not found. Is it installed in system path?");
@@ -307,6 +333,14 @@ impl EncodeArgs {
             }
         }
 
+        self.target_quality.validate_hull_denominators()?;
+
+        self.target_quality.validate_frame_floor()?;
+
+        if self.target_quality.is_enabled() {
+            self.target_quality.fixed_quantizer(&self.video_params)?;
+        }
+
         if let Some(strength) = self.photon_noise {
             if strength > 64 {
                 bail!("Valid strength values for photon noise are 0-64");
@@ -426,18 +460,41 @@ impl EncodeArgs {
         }
     }
 
+    /// Ensures the requirements of calculating `metric` are met
+    fn validate_metric(&self, metric: TargetMetric, samples_frames: bool) -> anyhow::Result<()> {
+        match metric {
+            TargetMetric::VMAF => validate_libvmaf()?,
+            TargetMetric::SSIMULACRA2 => self.validate_ssimulacra2()?,
+            TargetMetric::ButteraugliINF => self.validate_butteraugli_inf()?,
+            TargetMetric::Butteraugli3 => self.validate_butteraugli_3()?,
+            TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => {
+                self.validate_xpsnr(metric, samples_frames)?;
+            },
+            // Bitrate needs no metric and the native metrics are computed in Rust from
+            // frames decoded by the ffmpeg executable, which is always required
+            TargetMetric::Bitrate
+            | TargetMetric::PSNR
+            | TargetMetric::SSIM
+            | TargetMetric::MSSSIM => {},
+        }
+
+        Ok(())
+    }
+
     #[inline]
     pub fn validate_ssimulacra2(&self) -> anyhow::Result<()> {
-        ensure!(
-            self.vapoursynth_plugins.is_some_and(|p| p.vship)
-                || self.vapoursynth_plugins.is_some_and(|p| p.vszip != VSZipVersion::None),
-            "SSIMULACRA2 metric requires either Vapoursynth-HIP or VapourSynth Zig Image Process \
-             to be installed"
-        );
-        self.ensure_chunk_method(
-            "Chunk method must be lsmash, ffms2, bestsource, or dgdecnv for SSIMULACRA2"
-                .to_string(),
-        )?;
+        // The plugins load the source with VapourSynth, so any other chunk method
+        // uses the native implementation
+        let plugins = self.vapoursynth_plugins.is_some_and(|p| p.vship)
+            || self.vapoursynth_plugins.is_some_and(|p| p.vszip != VSZipVersion::None);
+        if !plugins || !self.input.is_vapoursynth_script() {
+            warn!(
+                "SSIMULACRA2 is calculated natively as neither Vapoursynth-HIP nor VapourSynth \
+                 Zig Image Process can be used with this input and chunk method. Its scores \
+                 differ slightly from those of the plugins, so the same target can give other \
+                 quantizers than with the plugins."
+            );
+        }
 
         Ok(())
     }
@@ -447,8 +504,8 @@ impl EncodeArgs {
         ensure!(
             self.vapoursynth_plugins.is_some_and(|p| p.vship)
                 || self.vapoursynth_plugins.is_some_and(|p| p.julek),
-            "Butteraugli metric requires either Vapoursynth-HIP or vapoursynth-julek-plugin to be \
-             installed"
+            "Butteraugli metric has no native implementation and requires either Vapoursynth-HIP \
+             or vapoursynth-julek-plugin to be installed"
         );
         self.ensure_chunk_method(
             "Chunk method must be lsmash, ffms2, bestsource, or dgdecnv for butteraugli"
@@ -462,7 +519,8 @@ impl EncodeArgs {
     pub fn validate_butteraugli_3(&self) -> anyhow::Result<()> {
         ensure!(
             self.vapoursynth_plugins.is_some_and(|p| p.vship),
-            "Butteraugli 3 Norm metric requires Vapoursynth-HIP plugin to be installed"
+            "Butteraugli 3 Norm metric has no native implementation and requires Vapoursynth-HIP \
+             plugin to be installed"
         );
         self.ensure_chunk_method(
             "Chunk method must be lsmash, ffms2, bestsource, or dgdecnv for butteraugli 3-Norm"
@@ -473,23 +531,23 @@ impl EncodeArgs {
     }
 
     #[inline]
-    pub fn validate_xpsnr(&self, metric: TargetMetric, probing_rate: usize) -> anyhow::Result<()> {
+    pub fn validate_xpsnr(&self, metric: TargetMetric, samples_frames: bool) -> anyhow::Result<()> {
         let metric_name = if metric == TargetMetric::XPSNRWeighted {
             "Weighted XPSNR"
         } else {
             "XPSNR"
         };
-        if probing_rate > 1 {
+        if samples_frames {
             ensure!(
                 self.vapoursynth_plugins.is_some_and(|p| p.vszip == VSZipVersion::New),
                 format!(
-                    "{metric_name} metric with probing rate greater than 1 requires \
-                     VapourSynth-Zig Image Process R7 or newer to be installed"
+                    "{metric_name} metric with probing rate greater than 1 or probing windows \
+                     requires VapourSynth-Zig Image Process R7 or newer to be installed"
                 )
             );
             self.ensure_chunk_method(format!(
                 "Chunk method must be lsmash, ffms2, bestsource, or dgdecnv for {metric_name} \
-                 metric with probing rate greater than 1"
+                 metric with probing rate greater than 1 or probing windows"
             ))?;
         } else {
             validate_libxpsnr()?;
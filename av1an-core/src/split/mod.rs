@@ -47,6 +47,149 @@ pub fn segment(
     Ok(())
 }
 
+/// Changes made by [`snap_to_keyframes`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyframeSnap {
+    /// Scenecuts that were moved or removed
+    pub moved: usize,
+    /// Extra splits added to keep scenes within the extra split length
+    pub added: usize,
+}
+
+/// Moves the start of every scene to the nearest of the sorted `keyframes`,
+/// since a stream copy can only be cut at keyframes. The boundaries of
+/// `zones` are never moved, so they have to be keyframes, and scenecuts are
+/// only snapped to keyframes inside their zone. The minimum scene length and
+/// the extra split length, of the zone or the given defaults, are applied to
+/// the snapped scenes.
+pub fn snap_to_keyframes(
+    scenes: &[Scene],
+    zones: &[Scene],
+    keyframes: &[usize],
+    min_scene_len: usize,
+    extra_splits_len: Option<usize>,
+) -> anyhow::Result<(Vec<Scene>, KeyframeSnap)> {
+    let Some(frames) = scenes.last().map(|scene| scene.end_frame) else {
+        return Ok((Vec::new(), KeyframeSnap::default()));
+    };
+    let is_keyframe = |frame: usize| keyframes.binary_search(&frame).is_ok();
+
+    let mut boundaries: Vec<usize> = zones
+        .iter()
+        .flat_map(|zone| [zone.start_frame, zone.end_frame])
+        .filter(|&frame| frame > 0 && frame < frames)
+        .collect();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+    let misplaced: Vec<String> = boundaries
+        .iter()
+        .filter(|&&frame| !is_keyframe(frame))
+        .map(ToString::to_string)
+        .collect();
+    anyhow::ensure!(
+        misplaced.is_empty(),
+        "Zone boundaries at frame(s) {} are not keyframes of the source, and the segment chunk \
+         method can only split at keyframes. Move the zones to keyframes or use another chunk \
+         method",
+        misplaced.join(", ")
+    );
+    boundaries.insert(0, 0);
+    boundaries.push(frames);
+
+    let mut changes = KeyframeSnap::default();
+    let mut snapped: Vec<Scene> = Vec::with_capacity(scenes.len());
+    for range in boundaries.windows(2) {
+        let (start, end) = (range[0], range[1]);
+        let zone_overrides = scenes
+            .iter()
+            .find(|scene| scene.start_frame <= start && start < scene.end_frame)
+            .and_then(|scene| scene.zone_overrides.clone());
+        let min_len = zone_overrides.as_ref().map_or(min_scene_len, |zone| zone.min_scene_len);
+        let split_len = zone_overrides
+            .as_ref()
+            .map_or(extra_splits_len, |zone| zone.extra_splits_len)
+            .filter(|&len| len > 0);
+
+        let inner = &keyframes[keyframes.partition_point(|&keyframe| keyframe <= start)
+            ..keyframes.partition_point(|&keyframe| keyframe < end)];
+        let mut cuts: Vec<usize> = scenes
+            .iter()
+            .map(|scene| scene.start_frame)
+            .filter(|&frame| frame > start && frame < end)
+            .filter_map(|frame| {
+                let next = inner.partition_point(|&keyframe| keyframe < frame);
+                let after = inner.get(next).copied();
+                let before = next.checked_sub(1).map(|previous| inner[previous]);
+                match (before, after) {
+                    (Some(before), Some(after)) => Some(if frame - before <= after - frame {
+                        before
+                    } else {
+                        after
+                    }),
+                    (keyframe, None) | (None, keyframe) => keyframe,
+                }
+            })
+            .collect();
+        cuts.dedup();
+
+        // Snapping can shorten scenes below the minimum scene length
+        let mut kept: Vec<usize> = Vec::with_capacity(cuts.len());
+        for cut in cuts {
+            if cut - kept.last().copied().unwrap_or(start) >= min_len && end - cut >= min_len {
+                kept.push(cut);
+            }
+        }
+
+        // and merging them can make scenes longer than the extra split length
+        let mut starts = vec![start];
+        for &scene_end in kept.iter().chain([&end]) {
+            if let Some(split_len) = split_len {
+                let mut scene_start = *starts.last().expect("starts is not empty");
+                while scene_end - scene_start > split_len {
+                    let candidates: Vec<usize> = inner
+                        .iter()
+                        .copied()
+                        .filter(|&keyframe| {
+                            keyframe > scene_start
+                                && keyframe - scene_start >= min_len
+                                && keyframe < scene_end
+                                && scene_end - keyframe >= min_len
+                        })
+                        .collect();
+                    let Some(split) = candidates
+                        .iter()
+                        .rev()
+                        .find(|&&keyframe| keyframe - scene_start <= split_len)
+                        .or_else(|| candidates.first())
+                        .copied()
+                    else {
+                        break;
+                    };
+                    starts.push(split);
+                    changes.added += 1;
+                    scene_start = split;
+                }
+            }
+            if scene_end < end {
+                starts.push(scene_end);
+            }
+        }
+
+        changes.moved += scenes
+            .iter()
+            .filter(|scene| scene.start_frame > start && scene.start_frame < end)
+            .filter(|scene| starts.binary_search(&scene.start_frame).is_err())
+            .count();
+        snapped.extend(starts.iter().enumerate().map(|(i, &start_frame)| Scene {
+            start_frame,
+            end_frame: starts.get(i + 1).copied().unwrap_or(end),
+            zone_overrides: zone_overrides.clone(),
+        }));
+    }
+
+    Ok((snapped, changes))
+}
+
 pub fn extra_splits(
     scenes: &[Scene],
     split_size: usize,
@@ -202,3 +202,126 @@ fn extra_split_preserves_zone_overrides() {
         }
     }
 }
+
+fn zone_options(min_scene_len: usize, extra_splits_len: Option<usize>) -> ZoneOptions {
+    ZoneOptions {
+        encoder: Encoder::aom,
+        passes: 1,
+        video_params: into_vec!["--cpu-used=8"],
+        photon_noise: None,
+        photon_noise_height: None,
+        photon_noise_width: None,
+        chroma_noise: false,
+        extra_splits_len,
+        min_scene_len,
+        target_quality: None,
+    }
+}
+
+fn frames(scenes: &[Scene]) -> Vec<(usize, usize)> {
+    scenes.iter().map(|scene| (scene.start_frame, scene.end_frame)).collect()
+}
+
+#[test]
+fn snap_scenes_to_keyframes() -> anyhow::Result<()> {
+    let scene = |start_frame, end_frame, zone_overrides| Scene {
+        start_frame,
+        end_frame,
+        zone_overrides,
+    };
+    let zone = zone_options(24, None);
+    let scenes = [
+        scene(0, 90, None),
+        scene(90, 130, None),
+        scene(130, 200, Some(zone.clone())),
+        scene(200, 240, Some(zone.clone())),
+        scene(240, 300, None),
+    ];
+    let zones = [scene(130, 240, Some(zone))];
+
+    // The zone boundaries are kept and its scenecut only snaps inside the zone
+    let (snapped, changes) =
+        snap_to_keyframes(&scenes, &zones, &[0, 100, 130, 230, 240, 360], 24, None)?;
+    assert_eq!(changes, KeyframeSnap {
+        moved: 2, added: 0
+    });
+    assert_eq!(frames(&snapped), [
+        (0, 100),
+        (100, 130),
+        (130, 240),
+        (240, 300)
+    ]);
+    assert!(snapped[1].zone_overrides.is_none());
+    assert!(snapped[2].zone_overrides.is_some());
+    assert!(snapped[3].zone_overrides.is_none());
+
+    let (unchanged, changes) =
+        snap_to_keyframes(&scenes, &zones, &[0, 90, 130, 200, 240], 24, None)?;
+    assert_eq!(changes, KeyframeSnap::default());
+    assert_eq!(frames(&unchanged), frames(&scenes));
+
+    Ok(())
+}
+
+#[test]
+fn snapping_never_moves_zone_boundaries() {
+    let zone = Scene {
+        start_frame:    130,
+        end_frame:      240,
+        zone_overrides: Some(zone_options(24, None)),
+    };
+    let scenes = [
+        Scene {
+            start_frame:    0,
+            end_frame:      130,
+            zone_overrides: None,
+        },
+        zone.clone(),
+        Scene {
+            start_frame:    240,
+            end_frame:      300,
+            zone_overrides: None,
+        },
+    ];
+
+    let error = snap_to_keyframes(&scenes, &[zone], &[0, 120, 240], 24, None)
+        .expect_err("130 is not a keyframe");
+    assert!(error.to_string().contains("frame(s) 130 are not keyframes"));
+}
+
+#[test]
+fn snapping_applies_scene_lengths() -> anyhow::Result<()> {
+    let scenes = [
+        Scene {
+            start_frame:    0,
+            end_frame:      50,
+            zone_overrides: None,
+        },
+        Scene {
+            start_frame:    50,
+            end_frame:      400,
+            zone_overrides: None,
+        },
+    ];
+    let keyframes = [0, 10, 45, 100, 150, 200, 250, 290, 390];
+
+    // 50 snaps to 45 and 10 would be too short, then the 355 frames from 45
+    // are split at the last keyframes within 100 frames
+    let (snapped, changes) = snap_to_keyframes(&scenes, &[], &keyframes, 24, Some(100))?;
+    assert_eq!(frames(&snapped), [
+        (0, 45),
+        (45, 100),
+        (100, 200),
+        (200, 290),
+        (290, 400)
+    ]);
+    assert_eq!(changes, KeyframeSnap {
+        moved: 1, added: 3
+    });
+
+    // 390 is too close to the end for the minimum scene length
+    let (snapped, _) = snap_to_keyframes(&scenes, &[], &[0, 390], 24, Some(100))?;
+    assert_eq!(frames(&snapped), [(0, 400)]);
+
+    Ok(())
+}
@@ -1,39 +1,59 @@
 use std::{
     borrow::Cow,
-    cmp::{self, Ordering},
+    cmp,
     collections::HashSet,
+    ffi::OsString,
+    fmt::Display,
     io::Read,
     path::{Path, PathBuf},
     process::{Child, Stdio},
     str::FromStr,
     thread::{self, available_parallelism},
+    time::Instant,
 };
 
-use anyhow::{anyhow, bail};
+use anyhow::{anyhow, bail, ensure};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 use crate::{
     broker::EncoderCrash,
     chunk::Chunk,
+    convex_hull::{cheapest_point_meeting, point_at_slope, upper_convex_hull, HullPoint},
     ffmpeg::FFPixelFormat,
     interpol::{
         akima_interpolate,
         catmull_rom_interpolate,
         cubic_polynomial_interpolate,
         linear_interpolate,
+        monotone_fit,
         natural_cubic_spline,
         pchip_interpolate,
         quadratic_interpolate,
     },
     metrics::{
         butteraugli::ButteraugliSubMetric,
-        statistics::MetricStatistics,
+        native::{check_alignment, measure_native, NativeMetric},
+        statistics::{FrameStatistics, MetricStatistics},
         vmaf::{get_vmaf_model_version, read_vmaf_file, run_vmaf, run_vmaf_weighted},
         xpsnr::{read_xpsnr_file, run_xpsnr, XPSNRSubMetric},
     },
+    probe_cache::{CachedProbe, ProbeCache, ProbeParams},
+    probe_history::{ProbeHistories, QuantizerSeed},
     progress_bar::update_mp_msg,
-    vapoursynth::{measure_butteraugli, measure_ssimulacra2, measure_xpsnr, VapoursynthPlugins},
+    sampling::{FrameSampling, ProbingWindows},
+    scene_cache::input_fingerprint,
+    search_param::SearchParameter,
+    tq_report::ChunkReport,
+    util::stable_hash,
+    vapoursynth::{
+        measure_butteraugli,
+        measure_ssimulacra2,
+        measure_xpsnr,
+        VSZipVersion,
+        VapoursynthPlugins,
+    },
     Encoder,
     ProbingStatistic,
     ProbingStatisticName,
@@ -50,6 +70,8 @@ pub enum InterpolationMethod {
     Catmull,
     Akima,
     CubicPolynomial,
+    /// Monotone log-linear fit of every probe, usable with any number of probes
+    Monotone,
 }
 
 impl FromStr for InterpolationMethod {
@@ -65,11 +87,202 @@ impl FromStr for InterpolationMethod {
             "catmull" => Ok(Self::Catmull),
             "akima" => Ok(Self::Akima),
             "cubicpolynomial" | "cubic" => Ok(Self::CubicPolynomial),
+            "monotone" | "isotonic" => Ok(Self::Monotone),
             _ => Err(()),
         }
     }
 }
 
+/// What target quality does when no probe of a chunk scores within the
+/// target range
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+    strum::IntoStaticStr,
+)]
+pub enum TargetFallback {
+    /// Use the probe with the score closest to the middle of the range
+    #[default]
+    #[strum(serialize = "nearest")]
+    Nearest,
+    /// Fail the encode
+    #[strum(serialize = "fail")]
+    Fail,
+    /// Use the lowest quantizer of the range
+    #[strum(serialize = "clamp-min")]
+    ClampMin,
+    /// Use the highest quantizer of the range
+    #[strum(serialize = "clamp-max")]
+    ClampMax,
+    /// Use the highest quantizer probed that scores above the range, or the
+    /// lowest quantizer probed if none do
+    #[strum(serialize = "quality-safe")]
+    QualitySafe,
+    /// Use the lowest quantizer probed that scores below the range, or the
+    /// highest quantizer probed if none do
+    #[strum(serialize = "size-safe")]
+    SizeSafe,
+    /// Widen the quantizer range past the side where the target was missed
+    /// and search again, then use the nearest probe if it is still missed
+    #[strum(serialize = "widen-retry")]
+    WidenRetry,
+}
+
+/// What to do when the frames of a probe are not aligned with the frames of the
+/// reference, which makes every metric score collapse
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    strum::Display,
+    strum::EnumString,
+    strum::IntoStaticStr,
+)]
+pub enum AlignmentCheck {
+    /// Do not check the alignment
+    #[strum(serialize = "off")]
+    Off,
+    /// Log a warning and keep probing
+    #[default]
+    #[strum(serialize = "warn")]
+    Warn,
+    /// Fail the encode
+    #[strum(serialize = "fail")]
+    Fail,
+}
+
+/// A bound that the score of a metric must satisfy, used when targeting
+/// several metrics at once
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct QualityConstraint {
+    pub metric: TargetMetric,
+    /// Minimum score, or maximum score for inverse metrics such as butteraugli
+    pub bound:  f64,
+}
+
+impl QualityConstraint {
+    /// Whether `score` satisfies the constraint
+    #[inline]
+    pub fn is_satisfied(&self, score: f64) -> bool {
+        if is_inverse_metric(self.metric) {
+            score <= self.bound
+        } else {
+            score >= self.bound
+        }
+    }
+
+    /// `score` negated for inverse metrics so that higher is always better
+    fn normalize(&self, score: f64) -> f64 {
+        if is_inverse_metric(self.metric) {
+            -score
+        } else {
+            score
+        }
+    }
+
+    /// Normalized range of scores the search aims for, slightly above the
+    /// bound like a single value --target-quality
+    fn target_range(&self) -> (f64, f64) {
+        let bound = self.normalize(self.bound);
+        (bound, bound + (bound.abs() * 0.01).max(f64::EPSILON))
+    }
+
+    /// Normalized distance of `score` from the bound relative to the bound,
+    /// negative if the constraint is not satisfied. The margins of different
+    /// metrics are comparable, and the search aims for [`MARGIN_RANGE`].
+    fn margin(&self, score: f64) -> f64 {
+        (self.normalize(score) - self.normalize(self.bound)) / self.bound.abs().max(f64::EPSILON)
+    }
+}
+
+impl Display for QualityConstraint {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operator = if is_inverse_metric(self.metric) {
+            "<="
+        } else {
+            ">="
+        };
+        write!(f, "{}{operator}{}", self.metric, self.bound)
+    }
+}
+
+/// Range of [`QualityConstraint::margin`] the constrained search aims for,
+/// matching [`QualityConstraint::target_range`]
+const MARGIN_RANGE: (f64, f64) = (0.0, 0.01);
+
+/// Statistic of the per-frame scores of a probe that a [`FrameFloor`] bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FloorStatistic {
+    /// Lowest score of any frame
+    Minimum,
+    /// Highest score of any frame, the worst frame of inverse metrics
+    Maximum,
+    /// 1st percentile of the frame scores
+    Percentile1,
+}
+
+/// A bound on the worst frames of a probe, checked alongside the aggregate
+/// score so that a chunk scoring well on average cannot hide a few bad frames
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameFloor {
+    pub statistic: FloorStatistic,
+    pub bound:     f64,
+}
+
+impl FrameFloor {
+    /// Value of the bounded statistic in `frames`, negated for inverse metrics
+    /// so that higher is always better
+    #[inline]
+    pub fn normalized_score(&self, frames: &FrameStatistics) -> f64 {
+        match self.statistic {
+            FloorStatistic::Minimum => frames.minimum,
+            FloorStatistic::Maximum => -frames.maximum,
+            FloorStatistic::Percentile1 => frames.percentile_1,
+        }
+    }
+
+    /// The bound, negated for inverse metrics so that higher is always better
+    #[inline]
+    pub fn normalized_bound(&self) -> f64 {
+        if self.statistic == FloorStatistic::Maximum {
+            -self.bound
+        } else {
+            self.bound
+        }
+    }
+
+    /// Whether the worst frames summarized by `frames` satisfy the floor
+    #[inline]
+    pub fn is_satisfied(&self, frames: &FrameStatistics) -> bool {
+        self.normalized_score(frames) >= self.normalized_bound()
+    }
+}
+
+impl Display for FrameFloor {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.statistic {
+            FloorStatistic::Minimum => write!(f, "min>={}", self.bound),
+            FloorStatistic::Maximum => write!(f, "max<={}", self.bound),
+            FloorStatistic::Percentile1 => write!(f, "p1>={}", self.bound),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TargetQuality {
     pub vmaf_res:              String,
@@ -94,6 +307,33 @@ pub struct TargetQuality {
     pub vspipe_args:           Vec<String>,
     pub probing_vmaf_features: Vec<VmafFeature>,
     pub probing_statistic:     ProbingStatistic,
+    pub constraints:           Vec<QualityConstraint>,
+    /// Number of probes made at the same time at the start of a search
+    pub parallel_probes:       usize,
+    /// Probe contiguous windows of frames instead of every nth frame
+    pub probing_windows:       Option<ProbingWindows>,
+    /// Parameter searched instead of the quantizer, which stays fixed
+    pub search_parameter:      Option<SearchParameter>,
+    /// Resize denominators probed in addition to the source resolution to
+    /// build the rate-quality convex hull of each chunk
+    pub hull_denominators:     Vec<u8>,
+    /// Score gained per Mbps at which the point on the convex hull is picked,
+    /// instead of the cheapest point meeting the target
+    pub hull_slope:            Option<f64>,
+    /// Resize denominator the probes are encoded with, or None to encode them
+    /// at the source resolution
+    #[serde(default)]
+    pub resize_denominator:    Option<u8>,
+    /// What to do when no probe scores within the target
+    #[serde(default)]
+    pub fallback:              TargetFallback,
+    /// Bound on the worst frames that the chosen quantizer must satisfy
+    #[serde(default)]
+    pub frame_floor:           Option<FrameFloor>,
+    /// What to do when the first probe of a chunk is not aligned with the
+    /// reference
+    #[serde(default)]
+    pub alignment_check:       AlignmentCheck,
 }
 
 impl TargetQuality {
@@ -124,10 +364,184 @@ impl TargetQuality {
             vspipe_args: vec![],
             probing_vmaf_features: vec![VmafFeature::Default],
             probing_statistic: ProbingStatistic {
-                name:  ProbingStatisticName::Automatic,
-                value: None,
+                name:       ProbingStatisticName::Automatic,
+                value:      None,
+                expression: None,
             },
+            constraints: vec![],
+            parallel_probes: 1,
+            probing_windows: None,
+            search_parameter: None,
+            hull_denominators: vec![],
+            hull_slope: None,
+            resize_denominator: None,
+            fallback: TargetFallback::Nearest,
+            frame_floor: None,
+            alignment_check: AlignmentCheck::Warn,
+        }
+    }
+
+    /// Whether target quality is used, either with a single target or with
+    /// a list of constraints
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.target.is_some() || !self.constraints.is_empty()
+    }
+
+    /// Metrics that have to be calculated for every probe
+    #[inline]
+    pub fn metrics(&self) -> Vec<TargetMetric> {
+        if self.constraints.is_empty() {
+            vec![self.metric]
+        } else {
+            self.constraints.iter().map(|constraint| constraint.metric).unique().collect()
+        }
+    }
+
+    /// Checks that the resolution ladder of the convex hull search can be
+    /// used. The encoder has to scale the frames itself, so that every chunk
+    /// keeps the size of the sequence header and can be concatenated.
+    pub(crate) fn validate_hull_denominators(&self) -> anyhow::Result<()> {
+        if self.hull_denominators.is_empty() {
+            return Ok(());
+        }
+        ensure!(
+            self.target.is_some() && self.metric != TargetMetric::Bitrate,
+            "--hull-denominators requires --target-quality with a quality metric"
+        );
+        ensure!(
+            self.encoder.resize_args(16).is_some(),
+            "--hull-denominators requires aom or svt-av1, as {encoder} cannot scale frames \
+             without changing the size of the stream",
+            encoder = self.encoder
+        );
+
+        Ok(())
+    }
+
+    /// Checks that the frame floor can be used with the target. Only the
+    /// single target search applies it, and its statistic has to bound the
+    /// worst frames of the metric.
+    pub(crate) fn validate_frame_floor(&self) -> anyhow::Result<()> {
+        let Some(floor) = self.frame_floor else {
+            return Ok(());
+        };
+        ensure!(
+            self.target.is_some() && self.metric != TargetMetric::Bitrate,
+            "--frame-floor requires --target-quality with a quality metric"
+        );
+        ensure!(
+            self.constraints.is_empty() && self.hull_denominators.is_empty(),
+            "--frame-floor cannot be used with --target-constraints or --hull-denominators"
+        );
+        ensure!(
+            (floor.statistic == FloorStatistic::Maximum) == is_inverse_metric(self.metric),
+            "--frame-floor {floor} does not match {metric}. Use max<= for butteraugli and min>= \
+             or p1>= for other metrics",
+            metric = self.metric
+        );
+
+        Ok(())
+    }
+
+    /// Short description of the target for progress messages
+    #[inline]
+    pub fn describe_target(&self) -> String {
+        if self.constraints.is_empty() {
+            let (min, max) = self.target.unwrap_or_default();
+            format!("{metric} Quality: {min}-{max}", metric = self.metric)
+        } else {
+            self.constraints.iter().join(", ")
+        }
+    }
+
+    /// Searches for the quantizer of `chunk`, along with the resize
+    /// denominator to encode it with if a resolution ladder is probed
+    pub(crate) fn search(
+        &self,
+        chunk: &Chunk,
+        worker_id: Option<usize>,
+        plugins: Option<VapoursynthPlugins>,
+        probe_histories: Option<&ProbeHistories>,
+    ) -> anyhow::Result<(f32, Option<u8>)> {
+        if self.hull_denominators.is_empty() {
+            self.per_shot_target_quality(chunk, worker_id, plugins, probe_histories)
+                .map(|quantizer| (quantizer, None))
+        } else {
+            self.per_shot_convex_hull(chunk, worker_id, plugins, probe_histories)
+        }
+    }
+
+    /// Searches for the quantizer meeting the target at the source resolution
+    /// and at every resize denominator of the ladder, then picks the point on
+    /// the rate-quality convex hull of all their probes that is the
+    /// cheapest to meet the target, or that matches the hull slope
+    fn per_shot_convex_hull(
+        &self,
+        chunk: &Chunk,
+        worker_id: Option<usize>,
+        plugins: Option<VapoursynthPlugins>,
+        probe_histories: Option<&ProbeHistories>,
+    ) -> anyhow::Result<(f32, Option<u8>)> {
+        let (min, max) = self.target.ok_or_else(|| anyhow!("Target must be some"))?;
+        let normalize = |score: f64| {
+            if is_inverse_metric(self.metric) {
+                -score
+            } else {
+                score
+            }
+        };
+        let minimum_score = if is_inverse_metric(self.metric) {
+            -max
+        } else {
+            min
+        };
+        let probed_frames = self.sampling(chunk).frame_count(chunk.frames());
+
+        let mut points = vec![];
+        for denominator in
+            std::iter::once(None).chain(self.hull_denominators.iter().copied().map(Some))
+        {
+            let search = Self {
+                resize_denominator: denominator,
+                hull_denominators: vec![],
+                ..self.clone()
+            };
+            // Histories are kept per chunk, so only the searches at the source
+            // resolution seed each other
+            search.per_shot_target_quality(
+                chunk,
+                worker_id,
+                plugins,
+                probe_histories.filter(|_| denominator.is_none()),
+            )?;
+
+            let mut cache = ProbeCache::load(&chunk.temp, &chunk.name());
+            let params_hash = cache.register(search.probe_params(chunk, plugins)?);
+            for probe in cache.probes(self.metric, params_hash) {
+                points.push(HullPoint {
+                    denominator,
+                    quantizer: probe.quantizer,
+                    bitrate: probe_bitrate(probe.size, probed_frames, chunk.frame_rate),
+                    score: normalize(probe.score),
+                });
+            }
         }
+
+        let hull = upper_convex_hull(points);
+        let point = self
+            .hull_slope
+            .map_or_else(
+                || cheapest_point_meeting(&hull, minimum_score),
+                |slope| point_at_slope(&hull, slope),
+            )
+            .ok_or_else(|| anyhow!("No probes to build the convex hull from"))?;
+        debug!(
+            "chunk {name}: Convex hull {hull:.2?}, picked {point:.2?}",
+            name = chunk.name()
+        );
+
+        Ok((point.quantizer, point.denominator))
     }
 
     #[inline]
@@ -136,11 +550,29 @@ impl TargetQuality {
         chunk: &Chunk,
         worker_id: Option<usize>,
         plugins: Option<VapoursynthPlugins>,
+        probe_histories: Option<&ProbeHistories>,
     ) -> anyhow::Result<f32> {
+        if !self.constraints.is_empty() {
+            return self.per_shot_constrained_quality(chunk, worker_id, plugins);
+        }
+
         anyhow::ensure!(self.target.is_some(), "Target must be some");
         let target = self.target.expect("target is some");
         // History of probe results as quantizer-score pairs
         let mut quantizer_score_history: Vec<(f32, f64)> = vec![];
+        // Every probe with its raw score, size and timings for the report
+        let mut probes = vec![];
+        // Probes from a previous or interrupted run are reused instead of probed again
+        let mut probe_cache = ProbeCache::load(&chunk.temp, &chunk.name());
+        // Start near the quantizers that neighbouring chunks ended with
+        let settings_hash = self.search_settings_hash();
+        let seed = probe_histories.and_then(|histories| histories.seed(chunk.index, settings_hash));
+        if let Some(seed) = seed {
+            debug!(
+                "chunk {name}: Seeding search with {seed:?}",
+                name = chunk.name()
+            );
+        }
 
         let update_progress_bar = |next_quantizer: f32| {
             if let Some(worker_id) = worker_id {
@@ -151,44 +583,86 @@ impl TargetQuality {
                         metric = self.metric,
                         min = target.0,
                         max = target.1,
-                        quantizer = next_quantizer
+                        quantizer = self.describe_position(next_quantizer)
                     ),
                 );
             }
         };
 
         // Initialize quantizer limits from specified range or encoder defaults
-        let step = match self.encoder {
-            Encoder::x264 | Encoder::x265 => 0.25,
-            Encoder::svt_av1 if crate::encoder::svt_av1_supports_quarter_steps(&self.temp) => 0.25,
-            _ => 1.0,
-        };
-        let mut lower_quantizer_limit = self.min_q as f32;
-        let mut upper_quantizer_limit = self.max_q as f32;
+        let step = self.quantizer_step();
+        let (mut lower_quantizer_limit, mut upper_quantizer_limit) = self.search_range();
+        // The range is widened at most once, with as many probes again
+        let mut widened = false;
+        let mut probe_limit = self.probes as usize;
 
         let skip_reason;
 
+        // Invert for butteraugli
+        let normalized_target = match self.metric {
+            TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
+                let (min, max) = target;
+                (-max, -min)
+            },
+            _ => target,
+        };
+
+        // Neighbouring chunks already predict a good starting point
+        let mut pending_quantizers = if seed.is_none() {
+            self.prefetch_initial_probes(
+                chunk,
+                worker_id,
+                &[self.metric],
+                plugins,
+                &mut probe_cache,
+            )?
+        } else {
+            vec![]
+        };
+
         loop {
-            let next_quantizer = predict_quantizer(
+            let seeded_quantizer = seed.and_then(|seed| {
+                seeded_quantizer(
+                    seed,
+                    lower_quantizer_limit,
+                    upper_quantizer_limit,
+                    &quantizer_score_history,
+                    normalized_target,
+                    step,
+                )
+            });
+            let next_quantizer = match next_pending_quantizer(
+                &mut pending_quantizers,
                 lower_quantizer_limit,
                 upper_quantizer_limit,
-                &quantizer_score_history,
-                // Invert for butteraugli
-                match self.metric {
-                    TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                        let (min, max) = target;
-                        (-max, -min)
-                    },
-                    _ => target,
-                },
-                self.interp_method,
-                step,
-            )?;
+            )
+            .or(seeded_quantizer)
+            {
+                Some(quantizer) => quantizer,
+                None => predict_quantizer(
+                    lower_quantizer_limit,
+                    upper_quantizer_limit,
+                    &quantizer_score_history,
+                    normalized_target,
+                    self.interp_method,
+                    step,
+                )?,
+            };
 
             if quantizer_score_history
                 .iter()
                 .any(|(quantizer, _)| *quantizer == next_quantizer)
             {
+                if !widened {
+                    if let Some(limits) =
+                        self.widened_limits(&quantizer_score_history, normalized_target)
+                    {
+                        (lower_quantizer_limit, upper_quantizer_limit) = limits;
+                        widened = true;
+                        probe_limit += self.probes as usize;
+                        continue;
+                    }
+                }
                 // Predicted quantizer has already been probed
                 skip_reason = SkipProbingReason::None;
                 break;
@@ -197,9 +671,13 @@ impl TargetQuality {
             update_progress_bar(next_quantizer);
 
             let score = {
-                let value = self.probe(chunk, next_quantizer, plugins)?;
+                let probe = self.cached_probe(chunk, next_quantizer, plugins, &mut probe_cache)?;
+                probes.push(probe);
+                let value = probe.score;
 
-                // Butteraugli is an inverse metric, invert score for comparisons
+                // Butteraugli is an inverse metric, invert score for comparisons. A higher
+                // quantizer lowers the bitrate like it lowers the VMAF score, so bitrate is
+                // not.
                 match self.metric {
                     TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => -value,
                     _ => value,
@@ -215,7 +693,17 @@ impl TargetQuality {
 
             quantizer_score_history.push((next_quantizer, score));
 
-            if score_within_range || quantizer_score_history.len() >= self.probes as usize {
+            if score_within_range || quantizer_score_history.len() >= probe_limit {
+                if !score_within_range && !widened {
+                    if let Some(limits) =
+                        self.widened_limits(&quantizer_score_history, normalized_target)
+                    {
+                        (lower_quantizer_limit, upper_quantizer_limit) = limits;
+                        widened = true;
+                        probe_limit += self.probes as usize;
+                        continue;
+                    }
+                }
                 skip_reason = if score_within_range {
                     SkipProbingReason::WithinTolerance
                 } else {
@@ -224,16 +712,28 @@ impl TargetQuality {
                 break;
             }
 
+            if confident_fit(
+                &quantizer_score_history,
+                normalized_target,
+                self.interp_method,
+                step,
+            ) {
+                skip_reason = SkipProbingReason::ConfidentFit;
+                break;
+            }
+
             let target_range = match self.metric {
                 TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => (-target.1, -target.0),
                 _ => target,
             };
 
-            if score > target_range.1 {
-                lower_quantizer_limit = ((next_quantizer) + step).min(upper_quantizer_limit);
-            } else if score < target_range.0 {
-                upper_quantizer_limit = ((next_quantizer) - step).max(lower_quantizer_limit);
-            }
+            (lower_quantizer_limit, upper_quantizer_limit) = update_quantizer_limits(
+                (lower_quantizer_limit, upper_quantizer_limit),
+                next_quantizer,
+                score,
+                target_range,
+                step,
+            );
 
             // Ensure quantizer limits are valid
             if lower_quantizer_limit > upper_quantizer_limit {
@@ -259,33 +759,56 @@ impl TargetQuality {
                 )
             })
             .max_by(|(q1, _), (q2, _)| q1.partial_cmp(q2).unwrap_or(std::cmp::Ordering::Equal))
-            .map_or_else(
-                || {
-                    // No quantizers within tolerance, choose the quantizer closest to target
-                    let target_midpoint = f64::midpoint(target.0, target.1);
-                    quantizer_score_history
-                        .iter()
-                        .min_by(|(_, score1), (_, score2)| {
-                            let score_1 = match self.metric {
-                                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                                    -score1
-                                },
-                                _ => *score1,
-                            };
-                            let score_2 = match self.metric {
-                                TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
-                                    -score2
-                                },
-                                _ => *score2,
-                            };
-                            let difference1 = (score_1 - target_midpoint).abs();
-                            let difference2 = (score_2 - target_midpoint).abs();
-                            difference1.partial_cmp(&difference2).unwrap_or(Ordering::Equal)
-                        })
-                        .expect("quantizer_score_history is not empty")
-                },
-                |highest_quantizer_score_within_range| highest_quantizer_score_within_range,
+            .copied();
+        // No quantizers within tolerance, the fallback policy decides
+        let fallback = final_quantizer_score.is_none().then_some(self.fallback);
+        if fallback == Some(TargetFallback::Fail) {
+            // Record the quantizer of the nearest probe so the report shows how close it
+            // got
+            let nearest = fallback_probe(
+                &quantizer_score_history,
+                normalized_target,
+                TargetFallback::Nearest,
+                self.search_range(),
+            );
+            self.write_report(chunk, probes, skip_reason, nearest.0, fallback);
+            bail!(
+                "chunk {name}: No probe scored within the target {metric} {min}-{max}",
+                name = chunk.name(),
+                metric = self.metric,
+                min = target.0,
+                max = target.1
+            );
+        }
+        let final_quantizer_score = final_quantizer_score.unwrap_or_else(|| {
+            fallback_probe(
+                &quantizer_score_history,
+                normalized_target,
+                self.fallback,
+                self.search_range(),
+            )
+        });
+        if let Some(fallback) = fallback {
+            warn!(
+                "chunk {name}: Target {metric} {min}-{max} not met, using Q={quantizer} from the \
+                 {fallback} fallback",
+                name = chunk.name(),
+                metric = self.metric,
+                min = target.0,
+                max = target.1,
+                quantizer = self.describe_position(final_quantizer_score.0)
             );
+        }
+
+        let final_quantizer_score = self.apply_frame_floor(
+            chunk,
+            worker_id,
+            plugins,
+            &mut probe_cache,
+            &mut probes,
+            &mut quantizer_score_history,
+            final_quantizer_score,
+        )?;
 
         log_probes(
             &quantizer_score_history,
@@ -306,75 +829,888 @@ impl TargetQuality {
             skip_reason,
         );
 
-        Ok(final_quantizer_score.0)
+        if let Some(histories) = probe_histories {
+            histories.insert(
+                chunk.index,
+                settings_hash,
+                &quantizer_score_history,
+                final_quantizer_score.0,
+            );
+        }
+        self.write_report(
+            chunk,
+            probes,
+            skip_reason,
+            final_quantizer_score.0,
+            fallback,
+        );
+
+        Ok(final_quantizer_score.0)
+    }
+
+    /// Lowers the quantizer chosen by the search until the worst frames of its
+    /// probe satisfy the frame floor, with at most as many probes again as the
+    /// search. The lowest quantizer probed is used if none satisfies it.
+    #[expect(clippy::too_many_arguments)]
+    fn apply_frame_floor(
+        &self,
+        chunk: &Chunk,
+        worker_id: Option<usize>,
+        plugins: Option<VapoursynthPlugins>,
+        probe_cache: &mut ProbeCache,
+        probes: &mut Vec<CachedProbe>,
+        quantizer_score_history: &mut Vec<(f32, f64)>,
+        chosen: (f32, f64),
+    ) -> anyhow::Result<(f32, f64)> {
+        let Some(floor) = self.frame_floor else {
+            return Ok(chosen);
+        };
+        let floor_score = |probe: &CachedProbe| probe.frames.map(|f| floor.normalized_score(&f));
+        // History of probe results as quantizer-floor statistic pairs
+        let mut floor_history: Vec<(f32, f64)> = probes
+            .iter()
+            .filter(|probe| probe.metric == self.metric)
+            .filter_map(|probe| floor_score(probe).map(|score| (probe.quantizer, score)))
+            .collect();
+        let bound = floor.normalized_bound();
+        // Quantizers clamped by a fallback policy may not have been probed
+        if floor_history
+            .iter()
+            .all(|&(quantizer, score)| quantizer != chosen.0 || score >= bound)
+        {
+            return Ok(chosen);
+        }
+        debug!(
+            "chunk {name}: Frame floor {floor} not met at Q={quantizer}, lowering the quantizer",
+            name = chunk.name(),
+            quantizer = self.describe_position(chosen.0)
+        );
+
+        // Highest quantizer below the chosen one that satisfies the floor
+        let satisfying = |history: &[(f32, f64)]| {
+            history
+                .iter()
+                .filter(|&&(quantizer, score)| quantizer < chosen.0 && score >= bound)
+                .map(|(quantizer, _)| *quantizer)
+                .max_by(f32::total_cmp)
+        };
+        let target_range = (bound, bound + (bound.abs() * 0.01).max(f64::EPSILON));
+        let step = self.quantizer_step();
+        let minimum_quantizer = self.search_range().0;
+        for _ in 0..self.probes {
+            let lower_quantizer_limit =
+                satisfying(&floor_history).map_or(minimum_quantizer, |q| q + step);
+            let upper_quantizer_limit = floor_history
+                .iter()
+                .filter(|(_, score)| *score < bound)
+                .map(|(quantizer, _)| *quantizer)
+                .min_by(f32::total_cmp)
+                .map_or(chosen.0, |quantizer| quantizer - step);
+            if lower_quantizer_limit > upper_quantizer_limit {
+                break;
+            }
+
+            let next_quantizer = predict_quantizer(
+                lower_quantizer_limit,
+                upper_quantizer_limit,
+                &floor_history,
+                target_range,
+                self.interp_method,
+                step,
+            )?;
+            if floor_history.iter().any(|(quantizer, _)| *quantizer == next_quantizer) {
+                break;
+            }
+
+            if let Some(worker_id) = worker_id {
+                update_mp_msg(
+                    worker_id,
+                    format!(
+                        "Targeting frame floor {floor} - Testing {quantizer}",
+                        quantizer = self.describe_position(next_quantizer)
+                    ),
+                );
+            }
+            let probe = self.cached_probe(chunk, next_quantizer, plugins, probe_cache)?;
+            probes.push(probe);
+            let Some(score) = floor_score(&probe) else {
+                break;
+            };
+            let aggregate = if is_inverse_metric(self.metric) {
+                -probe.score
+            } else {
+                probe.score
+            };
+            quantizer_score_history.push((next_quantizer, aggregate));
+            floor_history.push((next_quantizer, score));
+        }
+
+        let quantizer = satisfying(&floor_history).unwrap_or_else(|| {
+            let lowest = floor_history
+                .iter()
+                .map(|(quantizer, _)| *quantizer)
+                .min_by(f32::total_cmp)
+                .expect("the chosen quantizer was probed");
+            warn!(
+                "chunk {name}: Frame floor {floor} not met, using the lowest Q={quantizer} probed",
+                name = chunk.name(),
+                quantizer = self.describe_position(lowest)
+            );
+            lowest
+        });
+        let score = quantizer_score_history
+            .iter()
+            .find(|(q, _)| *q == quantizer)
+            .map_or(chosen.1, |(_, score)| *score);
+
+        Ok((quantizer, score))
+    }
+
+    /// Searches for the highest quantizer at which every constraint is
+    /// satisfied
+    fn per_shot_constrained_quality(
+        &self,
+        chunk: &Chunk,
+        worker_id: Option<usize>,
+        plugins: Option<VapoursynthPlugins>,
+    ) -> anyhow::Result<f32> {
+        // History of probe results as quantizer-scores pairs, with one score per
+        // constraint in the same order as the constraints
+        let mut quantizer_scores_history: Vec<(f32, Vec<f64>)> = vec![];
+        let mut probe_cache = ProbeCache::load(&chunk.temp, &chunk.name());
+        let metrics = self.metrics();
+        let mut probes = vec![];
+
+        let satisfies_all =
+            |scores: &[f64]| self.constraints.iter().zip(scores).all(|(c, &s)| c.is_satisfied(s));
+        let target_ranges: Vec<(f64, f64)> =
+            self.constraints.iter().map(QualityConstraint::target_range).collect();
+
+        // Margin of the tightest constraint of each probe, which the fallback policy
+        // treats like the score of a single target
+        let margin_history = |history: &[(f32, Vec<f64>)]| -> Vec<(f32, f64)> {
+            history
+                .iter()
+                .map(|(quantizer, scores)| {
+                    let margin = self
+                        .constraints
+                        .iter()
+                        .zip(scores)
+                        .map(|(constraint, &score)| constraint.margin(score))
+                        .fold(f64::INFINITY, f64::min);
+                    (*quantizer, margin)
+                })
+                .collect()
+        };
+
+        let step = self.quantizer_step();
+        let (mut lower_quantizer_limit, mut upper_quantizer_limit) = self.search_range();
+        // The range is widened at most once, with as many probes again
+        let mut widened = false;
+        let mut probe_limit = self.probes as usize;
+        // Only a search that satisfied no constraint set is widened
+        let widened_limits = |history: &[(f32, Vec<f64>)]| {
+            if history.iter().any(|(_, scores)| satisfies_all(scores)) {
+                return None;
+            }
+            self.widened_limits(&margin_history(history), MARGIN_RANGE)
+        };
+
+        let skip_reason;
+        let mut pending_quantizers =
+            self.prefetch_initial_probes(chunk, worker_id, &metrics, plugins, &mut probe_cache)?;
+
+        loop {
+            let pending_quantizer = next_pending_quantizer(
+                &mut pending_quantizers,
+                lower_quantizer_limit,
+                upper_quantizer_limit,
+            );
+            let next_quantizer = if let Some(quantizer) = pending_quantizer {
+                quantizer
+            } else {
+                // The lowest quantizer predicted for any constraint is needed to satisfy all
+                let mut next_quantizer = upper_quantizer_limit;
+                for (i, constraint) in self.constraints.iter().enumerate() {
+                    let history: Vec<(f32, f64)> = quantizer_scores_history
+                        .iter()
+                        .map(|(quantizer, scores)| (*quantizer, constraint.normalize(scores[i])))
+                        .collect();
+                    next_quantizer = next_quantizer.min(predict_quantizer(
+                        lower_quantizer_limit,
+                        upper_quantizer_limit,
+                        &history,
+                        target_ranges[i],
+                        self.interp_method,
+                        step,
+                    )?);
+                }
+                next_quantizer
+            };
+
+            if quantizer_scores_history
+                .iter()
+                .any(|(quantizer, _)| *quantizer == next_quantizer)
+            {
+                if !widened {
+                    if let Some(limits) = widened_limits(&quantizer_scores_history) {
+                        (lower_quantizer_limit, upper_quantizer_limit) = limits;
+                        widened = true;
+                        probe_limit += self.probes as usize;
+                        continue;
+                    }
+                }
+                // Predicted quantizer has already been probed
+                skip_reason = SkipProbingReason::None;
+                break;
+            }
+
+            if let Some(worker_id) = worker_id {
+                update_mp_msg(
+                    worker_id,
+                    format!(
+                        "Targeting {target} - Testing {quantizer}",
+                        target = self.describe_target(),
+                        quantizer = self.describe_position(next_quantizer)
+                    ),
+                );
+            }
+
+            let quantizer_probes = self.cached_probe_metrics(
+                chunk,
+                next_quantizer,
+                &metrics,
+                plugins,
+                &mut probe_cache,
+            )?;
+            let scores: Vec<f64> = self
+                .constraints
+                .iter()
+                .map(|constraint| {
+                    quantizer_probes
+                        .iter()
+                        .find(|probe| probe.metric == constraint.metric)
+                        .expect("every constraint metric was probed")
+                        .score
+                })
+                .collect();
+            probes.extend(quantizer_probes);
+            let satisfied = satisfies_all(&scores);
+            // The tightest constraint is close to its bound
+            let within_tolerance = satisfied
+                && self.constraints.iter().zip(&scores).zip(&target_ranges).any(
+                    |((constraint, &score), &range)| {
+                        within_range(constraint.normalize(score), range)
+                    },
+                );
+
+            quantizer_scores_history.push((next_quantizer, scores));
+
+            if within_tolerance || quantizer_scores_history.len() >= probe_limit {
+                if !within_tolerance && !widened {
+                    if let Some(limits) = widened_limits(&quantizer_scores_history) {
+                        (lower_quantizer_limit, upper_quantizer_limit) = limits;
+                        widened = true;
+                        probe_limit += self.probes as usize;
+                        continue;
+                    }
+                }
+                skip_reason = if within_tolerance {
+                    SkipProbingReason::WithinTolerance
+                } else {
+                    SkipProbingReason::ProbeLimitReached
+                };
+                break;
+            }
+
+            if satisfied {
+                lower_quantizer_limit = (next_quantizer + step).min(upper_quantizer_limit);
+            } else {
+                upper_quantizer_limit = (next_quantizer - step).max(lower_quantizer_limit);
+            }
+
+            if lower_quantizer_limit > upper_quantizer_limit
+                || (satisfied && next_quantizer >= upper_quantizer_limit)
+                || (!satisfied && next_quantizer <= lower_quantizer_limit)
+            {
+                if !satisfied && !widened {
+                    if let Some(limits) = widened_limits(&quantizer_scores_history) {
+                        (lower_quantizer_limit, upper_quantizer_limit) = limits;
+                        widened = true;
+                        probe_limit += self.probes as usize;
+                        continue;
+                    }
+                }
+                skip_reason = if satisfied {
+                    SkipProbingReason::QuantizerTooHigh
+                } else {
+                    SkipProbingReason::QuantizerTooLow
+                };
+                break;
+            }
+        }
+
+        // Highest quantizer satisfying all constraints
+        let final_quantizer = quantizer_scores_history
+            .iter()
+            .filter(|(_, scores)| satisfies_all(scores))
+            .map(|(quantizer, _)| *quantizer)
+            .max_by(f32::total_cmp);
+        // No quantizer satisfies every constraint, the fallback policy decides
+        let fallback = final_quantizer.is_none().then_some(self.fallback);
+        let margins = margin_history(&quantizer_scores_history);
+        if fallback == Some(TargetFallback::Fail) {
+            let nearest = fallback_probe(
+                &margins,
+                MARGIN_RANGE,
+                TargetFallback::Nearest,
+                self.search_range(),
+            );
+            self.write_report(chunk, probes, skip_reason, nearest.0, fallback);
+            bail!(
+                "chunk {name}: No probe satisfied the constraints {constraints}",
+                name = chunk.name(),
+                constraints = self.describe_target()
+            );
+        }
+        let final_quantizer = final_quantizer.unwrap_or_else(|| {
+            fallback_probe(&margins, MARGIN_RANGE, self.fallback, self.search_range()).0
+        });
+        if let Some(fallback) = fallback {
+            warn!(
+                "chunk {name}: Constraints {constraints} not met, using Q={quantizer} from the \
+                 {fallback} fallback",
+                name = chunk.name(),
+                constraints = self.describe_target(),
+                quantizer = self.describe_position(final_quantizer)
+            );
+        }
+
+        let mut sorted_history = quantizer_scores_history.clone();
+        sorted_history.sort_by(|(q1, _), (q2, _)| q1.total_cmp(q2));
+        debug!(
+            "chunk {name}: Constraints={constraints}, P-Rate={rate}, {frame_count} frames
+       TQ-Probes: {history:.2?}{suffix}
+       Final Q={final_quantizer:.2}",
+            name = chunk.name(),
+            constraints = self.describe_target(),
+            rate = self.probing_rate,
+            frame_count = chunk.frames(),
+            history = sorted_history,
+            suffix = skip_reason.suffix(),
+        );
+        self.write_report(chunk, probes, skip_reason, final_quantizer, fallback);
+
+        Ok(final_quantizer)
+    }
+
+    /// Writes the machine-readable record of the search of `chunk` to the
+    /// temporary directory. A failure only loses the report, so it is not
+    /// fatal.
+    fn write_report(
+        &self,
+        chunk: &Chunk,
+        probes: Vec<CachedProbe>,
+        skip_reason: SkipProbingReason,
+        final_quantizer: f32,
+        fallback: Option<TargetFallback>,
+    ) {
+        let report = ChunkReport {
+            chunk: chunk.name(),
+            frames: chunk.frames(),
+            metric: self.metric,
+            target: self.target,
+            constraints: self.constraints.clone(),
+            probing_statistic: self.probing_statistic.clone(),
+            probing_rate: self.probing_rate,
+            probing_windows: self.probing_windows,
+            search_parameter: self.search_parameter.clone(),
+            resize_denominator: self.resize_denominator,
+            probes,
+            skip_reason,
+            final_quantizer,
+            fallback,
+            frame_floor: self.frame_floor,
+        };
+        if let Err(e) = report.write(&chunk.temp) {
+            warn!("chunk {name}: {e:#}", name = chunk.name());
+        }
+    }
+
+    /// Hash of the settings that decide which quantizer a search ends with,
+    /// apart from the chunk itself
+    fn search_settings_hash(&self) -> u64 {
+        stable_hash(&(
+            (self.metric, self.target, self.min_q, self.max_q),
+            (self.encoder, &self.video_params, self.probe_res),
+            (
+                &self.probing_statistic,
+                self.probing_rate,
+                self.probing_windows,
+            ),
+            (
+                &self.search_parameter,
+                self.resize_denominator,
+                self.frame_floor,
+            ),
+        ))
+    }
+
+    /// Every setting that affects the score of a probe of `chunk`, including
+    /// which implementation scores SSIMULACRA2 with `plugins`
+    pub(crate) fn probe_params(
+        &self,
+        chunk: &Chunk,
+        plugins: Option<VapoursynthPlugins>,
+    ) -> anyhow::Result<ProbeParams> {
+        let to_strings =
+            |cmd: &[OsString]| cmd.iter().map(|arg| arg.to_string_lossy().into_owned()).collect();
+        Ok(ProbeParams {
+            input_fingerprint:     input_fingerprint(&chunk.input)?,
+            proxy_fingerprint:     chunk.proxy.as_ref().map(input_fingerprint).transpose()?,
+            start_frame:           chunk.start_frame,
+            end_frame:             chunk.end_frame,
+            source_cmd:            to_strings(&chunk.source_cmd),
+            proxy_cmd:             chunk.proxy_cmd.as_deref().map(to_strings),
+            encoder:               self.encoder,
+            pix_format:            self.pix_format,
+            video_params:          self.video_params.clone(),
+            vspipe_args:           self.vspipe_args.clone(),
+            probe_res:             self.probe_res,
+            vmaf_res:              self.vmaf_res.clone(),
+            vmaf_scaler:           self.vmaf_scaler.clone(),
+            vmaf_filter:           self.vmaf_filter.clone(),
+            model:                 self.model.clone(),
+            probing_vmaf_features: self.probing_vmaf_features.clone(),
+            probing_statistic:     self.probing_statistic.clone(),
+            probing_rate:          self.probing_rate,
+            probing_windows:       self.probing_windows,
+            search_parameter:      self.search_parameter.clone(),
+            fixed_quantizer:       self.fixed_quantizer(&chunk.video_params)?,
+            resize_denominator:    self.resize_denominator,
+            ssimulacra2_backend:   self
+                .metrics()
+                .contains(&TargetMetric::SSIMULACRA2)
+                .then(|| ssimulacra2_backend(chunk, plugins).to_owned()),
+        })
+    }
+
+    /// Probes `chunk` at `quantizer`, reusing the result stored in `cache` if
+    /// the same probe has already been done with the same settings
+    pub(crate) fn cached_probe(
+        &self,
+        chunk: &Chunk,
+        quantizer: f32,
+        plugins: Option<VapoursynthPlugins>,
+        cache: &mut ProbeCache,
+    ) -> anyhow::Result<CachedProbe> {
+        Ok(self.cached_probe_metrics(chunk, quantizer, &[self.metric], plugins, cache)?[0])
+    }
+
+    /// Probes `chunk` at `quantizer` and scores the probe with every metric in
+    /// `metrics`. The probe is encoded at most once, and only if any of the
+    /// scores is missing from `cache`.
+    fn cached_probe_metrics(
+        &self,
+        chunk: &Chunk,
+        quantizer: f32,
+        metrics: &[TargetMetric],
+        plugins: Option<VapoursynthPlugins>,
+        cache: &mut ProbeCache,
+    ) -> anyhow::Result<Vec<CachedProbe>> {
+        let params_hash = cache.register(self.probe_params(chunk, plugins)?);
+        let missing_metrics = missing_metrics(cache, quantizer, metrics, params_hash);
+
+        if missing_metrics.is_empty() {
+            trace!(
+                "chunk {name}: using cached probes for quantizer {quantizer}",
+                name = chunk.name()
+            );
+        } else {
+            for probe in self.run_probe(chunk, quantizer, &missing_metrics, plugins, params_hash)? {
+                cache.insert(probe)?;
+            }
+            self.ensure_alignment(chunk, quantizer, cache)?;
+        }
+
+        Ok(metrics
+            .iter()
+            .map(|&metric| cache.get(quantizer, metric, params_hash).expect("probe is cached"))
+            .collect())
+    }
+
+    /// Encodes and scores the probes of `quantizers` that are missing from
+    /// `cache` concurrently, splitting the threads of the worker between them
+    fn prefetch_probes(
+        &self,
+        chunk: &Chunk,
+        quantizers: &[f32],
+        metrics: &[TargetMetric],
+        plugins: Option<VapoursynthPlugins>,
+        cache: &mut ProbeCache,
+    ) -> anyhow::Result<()> {
+        let params_hash = cache.register(self.probe_params(chunk, plugins)?);
+        let missing: Vec<(f32, Vec<TargetMetric>)> = quantizers
+            .iter()
+            .map(|&quantizer| {
+                (
+                    quantizer,
+                    missing_metrics(cache, quantizer, metrics, params_hash),
+                )
+            })
+            .filter(|(_, metrics)| !metrics.is_empty())
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let concurrent = Self {
+            vmaf_threads: self.concurrent_probe_threads(missing.len()),
+            ..self.clone()
+        };
+        let results = crossbeam_utils::thread::scope(|s| {
+            // Every probe is started before any of them is joined
+            let mut handles = Vec::with_capacity(missing.len());
+            for (quantizer, metrics) in &missing {
+                let concurrent = &concurrent;
+                handles.push(s.spawn(move |_| {
+                    concurrent.run_probe(chunk, *quantizer, metrics, plugins, params_hash)
+                }));
+            }
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("probe thread should join successfully"))
+                .collect::<Vec<_>>()
+        })
+        .expect("thread should spawn successfully");
+
+        for result in results {
+            for probe in result? {
+                cache.insert(probe)?;
+            }
+        }
+        self.ensure_alignment(chunk, missing[0].0, cache)?;
+
+        Ok(())
+    }
+
+    /// Checks once per chunk that the frames of the probe encoded at
+    /// `quantizer` line up with the frames of the reference, since filters,
+    /// proxies and variable frame rate sources can shift them
+    fn ensure_alignment(
+        &self,
+        chunk: &Chunk,
+        quantizer: f32,
+        cache: &mut ProbeCache,
+    ) -> anyhow::Result<()> {
+        if cache.alignment_checked
+            || self.alignment_check == AlignmentCheck::Off
+            || self.metrics().iter().all(|&metric| metric == TargetMetric::Bitrate)
+        {
+            return Ok(());
+        }
+        cache.alignment_checked = true;
+
+        let reference_pipe_cmd =
+            chunk.proxy_cmd.as_ref().map_or(chunk.source_cmd.as_slice(), |proxy_cmd| {
+                proxy_cmd.as_slice()
+            });
+        let alignment = match check_alignment(
+            &Self::probe_path(chunk, self.encoder, self.resize_denominator, quantizer),
+            reference_pipe_cmd,
+            self.vspipe_args.clone(),
+            &self.sampling(chunk),
+        ) {
+            Ok(alignment) => alignment,
+            Err(e) => {
+                debug!(
+                    "chunk {name}: Failed to check the frame alignment: {e:#}",
+                    name = chunk.name()
+                );
+                return Ok(());
+            },
+        };
+        if alignment.offset == 0 {
+            return Ok(());
+        }
+
+        let message = format!(
+            "chunk {name}: Probe frames are offset by {offset} from the reference ({best:.2} dB \
+             PSNR at the offset, {aligned:.2} dB without), check ffmpeg filters, the proxy or a \
+             variable frame rate source",
+            name = chunk.name(),
+            offset = alignment.offset,
+            best = alignment.best_psnr,
+            aligned = alignment.aligned_psnr
+        );
+        if self.alignment_check == AlignmentCheck::Fail {
+            bail!(message);
+        }
+        warn!("{message}");
+
+        Ok(())
+    }
+
+    /// Threads for each of `probes` probes running at the same time in a
+    /// single worker
+    fn concurrent_probe_threads(&self, probes: usize) -> usize {
+        if self.vmaf_threads == 0 {
+            vmaf_auto_threads(self.workers * probes)
+        } else {
+            (self.vmaf_threads / probes).max(1)
+        }
+    }
+
+    /// Encodes a probe of `chunk` at `quantizer` and scores it with every
+    /// metric in `metrics`
+    fn run_probe(
+        &self,
+        chunk: &Chunk,
+        quantizer: f32,
+        metrics: &[TargetMetric],
+        plugins: Option<VapoursynthPlugins>,
+        params_hash: u64,
+    ) -> anyhow::Result<Vec<CachedProbe>> {
+        let encode_start = Instant::now();
+        let probe_name =
+            self.encode_probe(chunk, quantizer, self.probe_quantizer(chunk, quantizer)?)?;
+        let encode_time = encode_start.elapsed().as_secs_f64();
+        let size = probe_name.metadata()?.len();
+
+        metrics
+            .iter()
+            .map(|&metric| {
+                let (score, frames) =
+                    self.score_probe(chunk, &probe_name, quantizer, metric, plugins)?;
+                Ok(CachedProbe {
+                    quantizer,
+                    score,
+                    size,
+                    metric,
+                    params_hash,
+                    frames,
+                    encode_time,
+                })
+            })
+            .collect()
+    }
+
+    /// Probes the initial quantizers of a search concurrently and returns them
+    /// so the search can use their results first
+    fn prefetch_initial_probes(
+        &self,
+        chunk: &Chunk,
+        worker_id: Option<usize>,
+        metrics: &[TargetMetric],
+        plugins: Option<VapoursynthPlugins>,
+        cache: &mut ProbeCache,
+    ) -> anyhow::Result<Vec<f32>> {
+        let quantizers = self.initial_quantizers(self.quantizer_step());
+        if quantizers.is_empty() {
+            return Ok(quantizers);
+        }
+
+        if let Some(worker_id) = worker_id {
+            update_mp_msg(
+                worker_id,
+                format!(
+                    "Targeting {target} - Testing {quantizers}",
+                    target = self.describe_target(),
+                    quantizers = quantizers.iter().map(|&q| self.describe_position(q)).join(", ")
+                ),
+            );
+        }
+        self.prefetch_probes(chunk, &quantizers, metrics, plugins, cache)?;
+
+        Ok(quantizers)
+    }
+
+    /// Quantizers probed at the same time before the search starts, evenly
+    /// spread across the quantizer range
+    fn initial_quantizers(&self, step: f32) -> Vec<f32> {
+        let count = self.parallel_probes.min(self.probes as usize);
+        if count <= 1 {
+            return vec![];
+        }
+
+        let (min_q, max_q) = self.search_range();
+        (1..=count)
+            .map(|i| {
+                let quantizer = min_q + (max_q - min_q) * i as f32 / (count + 1) as f32;
+                ((quantizer / step).round() * step).clamp(min_q, max_q)
+            })
+            .dedup()
+            .collect()
+    }
+
+    /// Whether probes only use some of the frames of a chunk
+    #[inline]
+    pub fn samples_frames(&self) -> bool {
+        self.probing_rate > 1 || self.probing_windows.is_some()
+    }
+
+    /// Frames of `chunk` that are encoded and measured when probing
+    pub(crate) fn sampling(&self, chunk: &Chunk) -> FrameSampling {
+        match self.probing_windows {
+            Some(windows) if windows.count * windows.length < chunk.frames() => {
+                FrameSampling::Windows(windows.ranges(chunk.frames()))
+            },
+            _ => FrameSampling::Every(self.probing_rate),
+        }
+    }
+
+    /// Range searched, either the quantizer range or the positions of the
+    /// search parameter
+    pub(crate) fn search_range(&self) -> (f32, f32) {
+        self.search_parameter.as_ref().map_or_else(
+            || (self.min_q as f32, self.max_q as f32),
+            |parameter| (parameter.min, parameter.max),
+        )
+    }
+
+    /// Quantizer of the probes and of the final encode when a parameter is
+    /// searched instead, taken from the probe or the given video parameters.
+    /// None if no parameter is searched, and an error if the quantizer is not
+    /// set.
+    pub(crate) fn fixed_quantizer(&self, video_params: &[String]) -> anyhow::Result<Option<f32>> {
+        if self.search_parameter.is_none() {
+            return Ok(None);
+        }
+        let quantizer = self
+            .video_params
+            .as_deref()
+            .and_then(|params| self.encoder.get_q(params))
+            .or_else(|| self.encoder.get_q(video_params));
+        ensure!(
+            quantizer.is_some(),
+            "--search-param requires the quantizer to be set in --video-params or \
+             --probe-video-params"
+        );
+
+        Ok(quantizer)
+    }
+
+    /// Quantizer a probe at search `position` is encoded with
+    fn probe_quantizer(&self, chunk: &Chunk, position: f32) -> anyhow::Result<f32> {
+        Ok(self.fixed_quantizer(&chunk.video_params)?.unwrap_or(position))
+    }
+
+    /// Sets the result of a search in the video parameters of the final
+    /// encode
+    pub(crate) fn apply_search_result(&self, params: Vec<String>, result: f32) -> Vec<String> {
+        match &self.search_parameter {
+            Some(parameter) => parameter.apply(params, parameter.value(result)),
+            None => self.encoder.man_command(params, result),
+        }
+    }
+
+    /// Search position for progress messages, as the parameter value when a
+    /// parameter is searched
+    fn describe_position(&self, position: f32) -> String {
+        self.search_parameter.as_ref().map_or_else(
+            || position.to_string(),
+            |parameter| format!("{} {}", parameter.name, parameter.value(position)),
+        )
+    }
+
+    /// Quantizer limits past the edge of the range where every probe missed the
+    /// target on the same side, extended by the width of the range up to the
+    /// full range of the encoder. None unless the fallback widens the range.
+    fn widened_limits(
+        &self,
+        history: &[(f32, f64)],
+        normalized_target: (f64, f64),
+    ) -> Option<(f32, f32)> {
+        if self.fallback != TargetFallback::WidenRetry || self.search_parameter.is_some() {
+            return None;
+        }
+
+        let (min_q, max_q) = self.search_range();
+        let (encoder_min, encoder_max) = self.encoder.get_quantizer_range();
+        let (encoder_min, encoder_max) = (encoder_min as f32, encoder_max as f32);
+        let width = max_q - min_q;
+        let step = self.quantizer_step();
+        let probed = |quantizer: f32| history.iter().any(|(q, _)| *q == quantizer);
+
+        let limits = if history.iter().all(|(_, score)| *score > normalized_target.1) {
+            // Even the highest quantizer scores too high
+            probed(max_q).then(|| (max_q + step, (max_q + width).min(encoder_max)))
+        } else if history.iter().all(|(_, score)| *score < normalized_target.0) {
+            probed(min_q).then(|| ((min_q - width).max(encoder_min), min_q - step))
+        } else {
+            None
+        };
+        let limits = limits.filter(|(lower, upper)| lower <= upper)?;
+        debug!("Widening the quantizer range to {}-{}", limits.0, limits.1);
+
+        Some(limits)
+    }
+
+    /// Smallest quantizer increment supported by the encoder, or the step of
+    /// the search parameter
+    pub(crate) fn quantizer_step(&self) -> f32 {
+        if let Some(parameter) = &self.search_parameter {
+            return parameter.step;
+        }
+        match self.encoder {
+            Encoder::x264 | Encoder::x265 => 0.25,
+            Encoder::svt_av1 if crate::encoder::svt_av1_supports_quarter_steps(&self.temp) => 0.25,
+            _ => 1.0,
+        }
     }
 
-    fn probe(
+    /// Calculates the aggregated `metric` score of an already encoded probe,
+    /// along with a summary of the per-frame scores
+    fn score_probe(
         &self,
         chunk: &Chunk,
+        probe_name: &Path,
         quantizer: f32,
+        metric: TargetMetric,
         plugins: Option<VapoursynthPlugins>,
-    ) -> anyhow::Result<f64> {
-        let probe_name = self.encode_probe(chunk, quantizer)?;
+    ) -> anyhow::Result<(f64, Option<FrameStatistics>)> {
+        let sampling = self.sampling(chunk);
         let reference_pipe_cmd =
             chunk.proxy_cmd.as_ref().map_or(chunk.source_cmd.as_slice(), |proxy_cmd| {
                 proxy_cmd.as_slice()
             });
 
-        let aggregate_frame_scores = |scores: Vec<f64>| -> anyhow::Result<f64> {
-            let mut statistics = MetricStatistics::new(scores);
-
-            let aggregate = match self.probing_statistic.name {
-                ProbingStatisticName::Automatic => {
-                    if self.metric == TargetMetric::VMAF {
-                        // Preserve legacy VMAF aggregation
-                        return Ok(statistics.percentile(1));
-                    }
-
-                    let sigma_1 = {
-                        let sigma_distance = statistics.standard_deviation();
-                        let statistic = statistics.mean() - sigma_distance;
-                        statistic.clamp(statistics.minimum(), statistics.maximum())
-                    };
+        let aggregate_frame_scores =
+            |scores: Vec<f64>| -> anyhow::Result<(f64, Option<FrameStatistics>)> {
+                let mut statistics = MetricStatistics::new(scores)
+                    .with_frame_rate(sampling.score_rate(chunk.frame_rate));
+
+                let aggregate = match self.probing_statistic.name {
+                    ProbingStatisticName::Automatic => {
+                        if metric == TargetMetric::VMAF {
+                            // Preserve legacy VMAF aggregation
+                            return Ok((statistics.percentile(1), Some(statistics.summary())));
+                        }
+
+                        let sigma_1 = {
+                            let sigma_distance = statistics.standard_deviation();
+                            let statistic = statistics.mean() - sigma_distance;
+                            statistic.clamp(statistics.minimum(), statistics.maximum())
+                        };
+
+                        // Based on quantizer - lower quantizer leads to more accurate scores (lower
+                        // variance) (citation needed)
+                        let quantizer = self.probe_quantizer(chunk, quantizer)?;
+                        if self.encoder.get_cq_relative_percentage(quantizer as usize) > 0.25 {
+                            // Liberal: Use mean to determine aggregate
+                            statistics.mean()
+                        } else {
+                            // Less liberal: Use -1 sigma to determine aggregate
+                            sigma_1
+                        }
+                    },
+                    _ => statistics.aggregate(&self.probing_statistic, metric)?,
+                };
 
-                    // Based on quantizer - lower quantizer leads to more accurate scores (lower
-                    // variance) (citation needed)
-                    if self.encoder.get_cq_relative_percentage(quantizer as usize) > 0.25 {
-                        // Liberal: Use mean to determine aggregate
-                        statistics.mean()
-                    } else {
-                        // Less liberal: Use -1 sigma to determine aggregate
-                        sigma_1
-                    }
-                },
-                ProbingStatisticName::Mean => statistics.mean(),
-                ProbingStatisticName::RootMeanSquare => statistics.root_mean_square(),
-                ProbingStatisticName::Median => statistics.median(),
-                ProbingStatisticName::Harmonic => statistics.harmonic_mean(),
-                ProbingStatisticName::Percentile => {
-                    let value = self
-                        .probing_statistic
-                        .value
-                        .ok_or_else(|| anyhow::anyhow!("Percentile statistic requires a value"))?;
-                    statistics.percentile(value as usize)
-                },
-                ProbingStatisticName::StandardDeviation => {
-                    let value = self.probing_statistic.value.ok_or_else(|| {
-                        anyhow::anyhow!("Standard deviation statistic requires a value")
-                    })?;
-                    let sigma_distance = value * statistics.standard_deviation();
-                    let statistic = statistics.mean() + sigma_distance;
-                    statistic.clamp(statistics.minimum(), statistics.maximum())
-                },
-                ProbingStatisticName::Mode => statistics.mode(),
-                ProbingStatisticName::Minimum => statistics.minimum(),
-                ProbingStatisticName::Maximum => statistics.maximum(),
+                Ok((aggregate, Some(statistics.summary())))
             };
 
-            Ok(aggregate)
-        };
-
-        match self.metric {
+        match metric {
             TargetMetric::VMAF => {
                 let features: HashSet<_> = self.probing_vmaf_features.iter().copied().collect();
                 let use_weighted = features.contains(&VmafFeature::Weighted);
@@ -403,7 +1739,7 @@ impl TargetQuality {
 
                 let vmaf_scores = if use_weighted {
                     run_vmaf_weighted(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
                         self.vspipe_args.clone(),
                         model,
@@ -422,12 +1758,15 @@ impl TargetQuality {
                         })
                     })?
                 } else {
-                    let fl_path = std::path::Path::new(&chunk.temp)
-                        .join("split")
-                        .join(format!("{index}.json", index = chunk.index));
+                    // Named after the quantizer as probes of a chunk may run concurrently
+                    let fl_path = std::path::Path::new(&chunk.temp).join("split").join(format!(
+                        "{index}_{q}.json",
+                        index = chunk.index,
+                        q = crate::encoder::format_q(quantizer)
+                    ));
 
                     run_vmaf(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
                         self.vspipe_args.clone(),
                         &fl_path,
@@ -437,7 +1776,7 @@ impl TargetQuality {
                             |(width, height)| format!("{width}x{height}"),
                         ),
                         &self.vmaf_scaler,
-                        self.probing_rate,
+                        &sampling,
                         self.vmaf_filter.as_deref(),
                         self.vmaf_threads,
                         chunk.frame_rate,
@@ -451,17 +1790,28 @@ impl TargetQuality {
                 aggregate_frame_scores(vmaf_scores)
             },
             TargetMetric::SSIMULACRA2 => {
-                let scores = if let Some(plugins) = plugins {
-                    measure_ssimulacra2(
-                        chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
-                        (chunk.start_frame as u32, chunk.end_frame as u32),
+                let source = chunk.proxy.as_ref().unwrap_or(&chunk.input);
+                let scores = match plugins {
+                    Some(plugins) if ssimulacra2_backend(chunk, Some(plugins)) != "native" => {
+                        measure_ssimulacra2(
+                            source,
+                            probe_name,
+                            (chunk.start_frame as u32, chunk.end_frame as u32),
+                            self.probe_res,
+                            &sampling,
+                            plugins,
+                        )?
+                    },
+                    _ => measure_native(
+                        NativeMetric::Ssimulacra2,
+                        probe_name,
+                        reference_pipe_cmd,
+                        self.vspipe_args.clone(),
                         self.probe_res,
-                        self.probing_rate,
-                        plugins,
-                    )?
-                } else {
-                    bail!("SSIMULACRA2 requires Vapoursynth to be installed");
+                        &self.vmaf_scaler,
+                        &sampling,
+                        self.pix_format,
+                    )?,
                 };
 
                 aggregate_frame_scores(scores)
@@ -469,39 +1819,42 @@ impl TargetQuality {
             TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3 => {
                 let scores = if let Some(plugins) = plugins {
                     measure_butteraugli(
-                        match self.metric {
+                        match metric {
                             TargetMetric::ButteraugliINF => ButteraugliSubMetric::InfiniteNorm,
                             TargetMetric::Butteraugli3 => ButteraugliSubMetric::ThreeNorm,
                             _ => unreachable!(),
                         },
                         chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                        &probe_name,
+                        probe_name,
                         (chunk.start_frame as u32, chunk.end_frame as u32),
                         self.probe_res,
-                        self.probing_rate,
+                        &sampling,
                         plugins,
                     )?
                 } else {
-                    bail!("Butteraugli requires Vapoursynth to be installed");
+                    bail!(
+                        "Butteraugli has no native implementation and requires Vapoursynth to be \
+                         installed"
+                    );
                 };
 
                 aggregate_frame_scores(scores)
             },
             TargetMetric::XPSNR | TargetMetric::XPSNRWeighted => {
-                let submetric = if self.metric == TargetMetric::XPSNR {
+                let submetric = if metric == TargetMetric::XPSNR {
                     XPSNRSubMetric::Minimum
                 } else {
                     XPSNRSubMetric::Weighted
                 };
-                if self.probing_rate > 1 {
+                if sampling.is_sampled() {
                     let scores = if let Some(plugins) = plugins {
                         measure_xpsnr(
                             submetric,
                             chunk.proxy.as_ref().unwrap_or(&chunk.input),
-                            &probe_name,
+                            probe_name,
                             (chunk.start_frame as u32, chunk.end_frame as u32),
                             self.probe_res,
-                            self.probing_rate,
+                            &sampling,
                             plugins,
                         )?
                     } else {
@@ -510,11 +1863,14 @@ impl TargetQuality {
 
                     aggregate_frame_scores(scores)
                 } else {
-                    let fl_path =
-                        Path::new(&chunk.temp).join("split").join(format!("{}.json", chunk.index));
+                    let fl_path = Path::new(&chunk.temp).join("split").join(format!(
+                        "{index}_{q}.json",
+                        index = chunk.index,
+                        q = crate::encoder::format_q(quantizer)
+                    ));
 
                     run_xpsnr(
-                        &probe_name,
+                        probe_name,
                         reference_pipe_cmd,
                         self.vspipe_args.clone(),
                         &fl_path,
@@ -523,36 +1879,75 @@ impl TargetQuality {
                             |(width, height)| format!("{width}x{height}"),
                         ),
                         &self.vmaf_scaler,
-                        self.probing_rate,
+                        &sampling,
                         chunk.frame_rate,
                     )?;
 
                     let (aggregate, scores) = read_xpsnr_file(fl_path, submetric)?;
 
                     match self.probing_statistic.name {
-                        ProbingStatisticName::Automatic => Ok(aggregate),
+                        ProbingStatisticName::Automatic => {
+                            Ok((aggregate, Some(MetricStatistics::new(scores).summary())))
+                        },
                         _ => aggregate_frame_scores(scores),
                     }
                 }
             },
+            TargetMetric::PSNR | TargetMetric::SSIM | TargetMetric::MSSSIM => {
+                let native_metric = match metric {
+                    TargetMetric::PSNR => NativeMetric::Psnr,
+                    TargetMetric::SSIM => NativeMetric::Ssim,
+                    TargetMetric::MSSSIM => NativeMetric::MsSsim,
+                    _ => unreachable!(),
+                };
+                let scores = measure_native(
+                    native_metric,
+                    probe_name,
+                    reference_pipe_cmd,
+                    self.vspipe_args.clone(),
+                    self.probe_res,
+                    &self.vmaf_scaler,
+                    &sampling,
+                    self.pix_format,
+                )?;
+
+                aggregate_frame_scores(scores)
+            },
+            TargetMetric::Bitrate => {
+                // Only the sampled frames are encoded
+                let probed_frames = sampling.frame_count(chunk.frames());
+                let size = std::fs::metadata(probe_name)?.len();
+
+                Ok((probe_bitrate(size, probed_frames, chunk.frame_rate), None))
+            },
         }
     }
 
-    fn encode_probe(&self, chunk: &Chunk, q: f32) -> Result<PathBuf, Box<EncoderCrash>> {
+    /// Encodes a probe of `chunk` at search position `q` with `quantizer`,
+    /// which differs from `q` when a parameter is searched instead
+    fn encode_probe(
+        &self,
+        chunk: &Chunk,
+        q: f32,
+        quantizer: f32,
+    ) -> Result<PathBuf, Box<EncoderCrash>> {
         let vmaf_threads = if self.vmaf_threads == 0 {
             vmaf_auto_threads(self.workers)
         } else {
             self.vmaf_threads
         };
 
+        let search_parameter =
+            self.search_parameter.as_ref().map(|parameter| (parameter, parameter.value(q)));
         let cmd = self.encoder.probe_cmd(
-            self.temp.clone(),
-            chunk.index,
-            q,
+            &Self::probe_path(chunk, self.encoder, self.resize_denominator, q),
+            quantizer,
             self.pix_format,
-            self.probing_rate,
+            &self.sampling(chunk),
+            self.resize_denominator,
             vmaf_threads,
             self.video_params.clone(),
+            search_parameter,
         );
 
         let source_cmd = chunk
@@ -684,54 +2079,89 @@ impl TargetQuality {
             Ok(())
         })?;
 
-        let extension = match self.encoder {
+        Ok(Self::probe_path(
+            chunk,
+            self.encoder,
+            self.resize_denominator,
+            q,
+        ))
+    }
+
+    /// Path of the probe encoded for `chunk` at quantizer or search position
+    /// `q`, resized with `denominator` if it is some
+    pub(crate) fn probe_path(
+        chunk: &Chunk,
+        encoder: Encoder,
+        denominator: Option<u8>,
+        q: f32,
+    ) -> PathBuf {
+        let extension = match encoder {
             crate::encoder::Encoder::x264 => "264",
             crate::encoder::Encoder::x265 => "hevc",
             _ => "ivf",
         };
 
         let q_str = crate::encoder::format_q(q);
-        let probe_name = format!("v_{index:05}_{q_str}.{extension}", index = chunk.index);
+        let probe_name = denominator.map_or_else(
+            || format!("v_{index:05}_{q_str}.{extension}", index = chunk.index),
+            |denominator| {
+                format!(
+                    "v_{index:05}_r{denominator}_{q_str}.{extension}",
+                    index = chunk.index
+                )
+            },
+        );
 
-        Ok(std::path::Path::new(&chunk.temp).join("split").join(&probe_name))
+        std::path::Path::new(&chunk.temp).join("split").join(probe_name)
     }
 
     #[inline]
     pub fn parse_probing_statistic(stat: &str) -> anyhow::Result<ProbingStatistic> {
         Ok(match stat.to_lowercase().as_str() {
             "auto" => ProbingStatistic {
-                name:  ProbingStatisticName::Automatic,
-                value: None,
+                name:       ProbingStatisticName::Automatic,
+                value:      None,
+                expression: None,
             },
             "mean" => ProbingStatistic {
-                name:  ProbingStatisticName::Mean,
-                value: None,
+                name:       ProbingStatisticName::Mean,
+                value:      None,
+                expression: None,
             },
             "harmonic" => ProbingStatistic {
-                name:  ProbingStatisticName::Harmonic,
-                value: None,
+                name:       ProbingStatisticName::Harmonic,
+                value:      None,
+                expression: None,
             },
             "root-mean-square" => ProbingStatistic {
-                name:  ProbingStatisticName::RootMeanSquare,
-                value: None,
+                name:       ProbingStatisticName::RootMeanSquare,
+                value:      None,
+                expression: None,
             },
             "median" => ProbingStatistic {
-                name:  ProbingStatisticName::Median,
-                value: None,
+                name:       ProbingStatisticName::Median,
+                value:      None,
+                expression: None,
             },
             "mode" => ProbingStatistic {
-                name:  ProbingStatisticName::Mode,
-                value: None,
+                name:       ProbingStatisticName::Mode,
+                value:      None,
+                expression: None,
             },
             "minimum" => ProbingStatistic {
-                name:  ProbingStatisticName::Minimum,
-                value: None,
+                name:       ProbingStatisticName::Minimum,
+                value:      None,
+                expression: None,
             },
             "maximum" => ProbingStatistic {
-                name:  ProbingStatisticName::Maximum,
-                value: None,
+                name:       ProbingStatisticName::Maximum,
+                value:      None,
+                expression: None,
             },
-            probe_statistic if probe_statistic.starts_with("percentile") => {
+            // `percentile(5)` is an expression
+            probe_statistic
+                if probe_statistic.starts_with("percentile") && !probe_statistic.contains('(') =>
+            {
                 if probe_statistic.matches('=').count() != 1
                     || !probe_statistic.starts_with("percentile=")
                 {
@@ -751,8 +2181,9 @@ impl TargetQuality {
                         )
                     })?;
                 ProbingStatistic {
-                    name:  ProbingStatisticName::Percentile,
-                    value: Some(value),
+                    name:       ProbingStatisticName::Percentile,
+                    value:      Some(value),
+                    expression: None,
                 }
             },
             probe_statistic if probe_statistic.starts_with("standard-deviation") => {
@@ -772,12 +2203,35 @@ impl TargetQuality {
                         anyhow!("Probing Statistic standard deviation must have a value appended")
                     })?;
                 ProbingStatistic {
-                    name:  ProbingStatisticName::StandardDeviation,
-                    value: Some(value),
+                    name:       ProbingStatisticName::StandardDeviation,
+                    value:      Some(value),
+                    expression: None,
                 }
             },
-            _ => {
-                return Err(anyhow!("Unknown Probing Statistic: {}", stat));
+            probe_statistic if probe_statistic.starts_with("window-minimum") => {
+                let value = probe_statistic
+                    .strip_prefix("window-minimum=")
+                    .and_then(|s| s.parse::<f64>().ok())
+                    .filter(|seconds| *seconds > 0.0)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Probing Statistic window minimum must have a positive window length \
+                             in seconds set using \"=\" (eg. \"--probing-stat \
+                             window-minimum=0.5\")"
+                        )
+                    })?;
+                ProbingStatistic {
+                    name:       ProbingStatisticName::WindowMinimum,
+                    value:      Some(value),
+                    expression: None,
+                }
+            },
+            expression => ProbingStatistic {
+                name:       ProbingStatisticName::Expression,
+                value:      None,
+                expression: Some(expression.parse().map_err(|e| {
+                    anyhow!("Unknown Probing Statistic or invalid expression {stat}: {e}")
+                })?),
             },
         })
     }
@@ -802,6 +2256,82 @@ impl TargetQuality {
         }
     }
 
+    /// Parses a single constraint such as `vmaf>=93` or `butteraugli-3<=1.5`
+    #[inline]
+    pub fn parse_constraint(s: &str) -> Result<QualityConstraint, String> {
+        let constraint = s.trim();
+        let (metric, operator, bound) = if let Some((metric, bound)) = constraint.split_once(">=") {
+            (metric, ">=", bound)
+        } else if let Some((metric, bound)) = constraint.split_once("<=") {
+            (metric, "<=", bound)
+        } else {
+            return Err(format!(
+                "Invalid constraint: {constraint}. Expected <metric>>=<score> or <metric><=<score>"
+            ));
+        };
+        let metric = TargetMetric::from_str(metric.trim())
+            .map_err(|_| format!("Invalid constraint metric: {metric}"))?;
+        let bound = bound
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid constraint score: {bound}"))?;
+        let expected_operator = if is_inverse_metric(metric) {
+            "<="
+        } else {
+            ">="
+        };
+        if operator != expected_operator {
+            return Err(format!(
+                "Invalid constraint: {constraint}. {metric} must be constrained with \
+                 {expected_operator}"
+            ));
+        }
+
+        Ok(QualityConstraint {
+            metric,
+            bound,
+        })
+    }
+
+    /// Parses a frame floor such as `min>=80`, `p1>=85` or, for inverse
+    /// metrics, `max<=2.5`
+    #[inline]
+    pub fn parse_frame_floor(s: &str) -> Result<FrameFloor, String> {
+        let floor = s.trim();
+        let (statistic, bound) = if let Some((statistic, bound)) = floor.split_once(">=") {
+            let statistic = match statistic.trim() {
+                "min" => FloorStatistic::Minimum,
+                "p1" => FloorStatistic::Percentile1,
+                statistic => {
+                    return Err(format!(
+                        "Invalid frame floor statistic: {statistic}. Use min or p1 with >="
+                    ))
+                },
+            };
+            (statistic, bound)
+        } else if let Some((statistic, bound)) = floor.split_once("<=") {
+            if statistic.trim() != "max" {
+                return Err(format!(
+                    "Invalid frame floor statistic: {statistic}. Use max with <="
+                ));
+            }
+            (FloorStatistic::Maximum, bound)
+        } else {
+            return Err(format!(
+                "Invalid frame floor: {floor}. Expected min>=<score>, p1>=<score> or max<=<score>"
+            ));
+        };
+        let bound = bound
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| format!("Invalid frame floor score: {bound}"))?;
+
+        Ok(FrameFloor {
+            statistic,
+            bound,
+        })
+    }
+
     #[inline]
     pub fn parse_interp_method(
         s: &str,
@@ -824,7 +2354,8 @@ impl TargetQuality {
         match method4 {
             InterpolationMethod::Linear
             | InterpolationMethod::Quadratic
-            | InterpolationMethod::Natural => {},
+            | InterpolationMethod::Natural
+            | InterpolationMethod::Monotone => {},
             _ => {
                 return Err(anyhow::anyhow!(
                     "Method '{}' not available for 4th round",
@@ -850,6 +2381,19 @@ impl TargetQuality {
         }
     }
 
+    /// Parses a resize denominator of the convex hull ladder, scaling frames
+    /// to 8/n of their size
+    #[inline]
+    pub fn parse_hull_denominator(denominator: &str) -> Result<u8, String> {
+        match denominator.parse::<u8>() {
+            Ok(denominator @ 9..=16) => Ok(denominator),
+            _ => Err(format!(
+                "Invalid resize denominator: {denominator}. Expected 9 to 16, scaling frames to \
+                 8/n of their size"
+            )),
+        }
+    }
+
     #[inline]
     pub fn parse_probe_res(probe_resolution: &str) -> Result<(u32, u32), String> {
         let parts: Vec<_> = probe_resolution.split('x').collect();
@@ -963,6 +2507,9 @@ fn predict_quantizer(
                         InterpolationMethod::Natural => {
                             natural_cubic_spline(&scores, &quantizers, target)
                         },
+                        InterpolationMethod::Monotone => {
+                            monotone_fit(&scores, &quantizers, target).map(|(q, _)| q)
+                        },
                         _ => None,
                     }
                 },
@@ -988,9 +2535,16 @@ fn predict_quantizer(
                         InterpolationMethod::CubicPolynomial => {
                             cubic_polynomial_interpolate(s, q, target)
                         },
+                        InterpolationMethod::Monotone => {
+                            monotone_fit(&scores, &quantizers, target).map(|(q, _)| q)
+                        },
                     }
                 },
-                _ => None,
+                // Only the monotone fit handles more than 4 probes
+                _ => interp_method
+                    .filter(|(_, m)| *m == InterpolationMethod::Monotone)
+                    .and_then(|_| monotone_fit(&scores, &quantizers, target))
+                    .map(|(q, _)| q),
             };
 
             result.unwrap_or_else(|| {
@@ -1007,6 +2561,210 @@ fn predict_quantizer(
     )
 }
 
+/// Predicts the first two quantizers of a search from the results of
+/// neighbouring chunks. Returns `None` once the search has enough probes of
+/// its own or if the prediction has already been probed.
+fn seeded_quantizer(
+    seed: QuantizerSeed,
+    lower_quantizer_limit: f32,
+    upper_quantizer_limit: f32,
+    quantizer_score_history: &[(f32, f64)],
+    target_range: (f64, f64),
+    step: f32,
+) -> Option<f32> {
+    let predicted_quantizer = match quantizer_score_history {
+        [] => seed.quantizer as f64,
+        [(quantizer, score)] => {
+            // Assume the curve of this chunk has the same slope as its neighbours
+            let target = f64::midpoint(target_range.0, target_range.1);
+            seed.slope?.mul_add(target - score, *quantizer as f64)
+        },
+        _ => return None,
+    };
+
+    let quantizer = (((predicted_quantizer / step as f64).round() * step as f64) as f32)
+        .clamp(lower_quantizer_limit, upper_quantizer_limit);
+    (!quantizer_score_history.iter().any(|(probed, _)| *probed == quantizer)).then_some(quantizer)
+}
+
+/// Whether a monotone fit of the history is confident enough that probing
+/// further cannot find a better quantizer: a probe within the target range is
+/// next to the quantizer it predicts, or the probes on either side of it are
+/// one step apart and bracket the target range
+fn confident_fit(
+    quantizer_score_history: &[(f32, f64)],
+    target_range: (f64, f64),
+    interp_method: Option<(InterpolationMethod, InterpolationMethod)>,
+    step: f32,
+) -> bool {
+    const MIN_CONFIDENCE: f64 = 0.98;
+
+    let uses_monotone_fit = interp_method.is_some_and(|(method4, method5)| {
+        method4 == InterpolationMethod::Monotone || method5 == InterpolationMethod::Monotone
+    });
+    if !uses_monotone_fit || quantizer_score_history.len() < 3 {
+        return false;
+    }
+
+    let (scores, quantizers): (Vec<f64>, Vec<f64>) =
+        quantizer_score_history.iter().map(|(q, s)| (*s, *q as f64)).unzip();
+    let target = f64::midpoint(target_range.0, target_range.1);
+    let Some((predicted, confidence)) = monotone_fit(&scores, &quantizers, target) else {
+        return false;
+    };
+    if confidence < MIN_CONFIDENCE {
+        return false;
+    }
+
+    let near_probe_in_range = quantizer_score_history.iter().any(|(quantizer, score)| {
+        (target_range.0..=target_range.1).contains(score)
+            && (*quantizer as f64 - predicted).abs() <= step as f64
+    });
+    let by_quantizer = |(q1, _): &&(f32, f64), (q2, _): &&(f32, f64)| q1.total_cmp(q2);
+    let below = quantizer_score_history
+        .iter()
+        .filter(|(quantizer, _)| *quantizer as f64 <= predicted)
+        .max_by(by_quantizer);
+    let above = quantizer_score_history
+        .iter()
+        .filter(|(quantizer, _)| *quantizer as f64 >= predicted)
+        .min_by(by_quantizer);
+    // No quantizer between the two probes can score within the target range
+    let brackets_target = below.zip(above).is_some_and(|(below, above)| {
+        above.0 - below.0 <= step && below.1 >= target_range.0 && above.1 <= target_range.1
+    });
+
+    near_probe_in_range || brackets_target
+}
+
+/// Calculates the bitrate in kbps of a probe with the given size in bytes
+fn probe_bitrate(size: u64, frames: usize, frame_rate: f64) -> f64 {
+    let duration = frames as f64 / frame_rate;
+    (size * 8) as f64 / duration / 1000.0
+}
+
+/// Picks the quantizer-score pair to encode with according to `fallback` when
+/// no probe scored within `target_range`. Scores are normalized so that higher
+/// is better.
+fn fallback_probe(
+    history: &[(f32, f64)],
+    target_range: (f64, f64),
+    fallback: TargetFallback,
+    (min_q, max_q): (f32, f32),
+) -> (f32, f64) {
+    let by_quantizer = |(q1, _): &&(f32, f64), (q2, _): &&(f32, f64)| q1.total_cmp(q2);
+    let nearest = || {
+        let target_midpoint = f64::midpoint(target_range.0, target_range.1);
+        history
+            .iter()
+            .min_by(|(_, score1), (_, score2)| {
+                (score1 - target_midpoint).abs().total_cmp(&(score2 - target_midpoint).abs())
+            })
+            .copied()
+    };
+    // The score of an unprobed quantizer is unknown
+    let clamped = |quantizer: f32| {
+        history
+            .iter()
+            .find(|(q, _)| *q == quantizer)
+            .copied()
+            .unwrap_or((quantizer, f64::NAN))
+    };
+
+    match fallback {
+        TargetFallback::Nearest | TargetFallback::Fail | TargetFallback::WidenRetry => nearest(),
+        TargetFallback::ClampMin => Some(clamped(min_q)),
+        TargetFallback::ClampMax => Some(clamped(max_q)),
+        TargetFallback::QualitySafe => history
+            .iter()
+            .filter(|(_, score)| *score >= target_range.0)
+            .max_by(by_quantizer)
+            .or_else(|| history.iter().min_by(by_quantizer))
+            .copied(),
+        TargetFallback::SizeSafe => history
+            .iter()
+            .filter(|(_, score)| *score <= target_range.1)
+            .min_by(by_quantizer)
+            .or_else(|| history.iter().max_by(by_quantizer))
+            .copied(),
+    }
+    .expect("history is not empty")
+}
+
+/// Takes the middle one of the concurrently probed quantizers that are still
+/// within the quantizer limits
+fn next_pending_quantizer(
+    pending_quantizers: &mut Vec<f32>,
+    lower_quantizer_limit: f32,
+    upper_quantizer_limit: f32,
+) -> Option<f32> {
+    pending_quantizers
+        .retain(|quantizer| (lower_quantizer_limit..=upper_quantizer_limit).contains(quantizer));
+    (!pending_quantizers.is_empty())
+        .then(|| pending_quantizers.remove(pending_quantizers.len() / 2))
+}
+
+/// Metrics in `metrics` without a cached probe at `quantizer`
+fn missing_metrics(
+    cache: &ProbeCache,
+    quantizer: f32,
+    metrics: &[TargetMetric],
+    params_hash: u64,
+) -> Vec<TargetMetric> {
+    metrics
+        .iter()
+        .copied()
+        .filter(|&metric| cache.get(quantizer, metric, params_hash).is_none())
+        .collect()
+}
+
+/// Implementation that scores SSIMULACRA2 probes of `chunk`. The plugins need
+/// a VapourSynth source, and each implementation scores slightly differently.
+fn ssimulacra2_backend(chunk: &Chunk, plugins: Option<VapoursynthPlugins>) -> &'static str {
+    let source = chunk.proxy.as_ref().unwrap_or(&chunk.input);
+    match plugins {
+        Some(plugins) if source.is_vapoursynth_script() && plugins.vship => "vship",
+        Some(plugins) if source.is_vapoursynth_script() && plugins.vszip != VSZipVersion::None => {
+            "vszip"
+        },
+        _ => "native",
+    }
+}
+
+/// Whether a lower score of `metric` means a higher quality. Bitrate falls
+/// as the quantizer rises just like the quality metrics, so it is not inverse.
+fn is_inverse_metric(metric: TargetMetric) -> bool {
+    matches!(
+        metric,
+        TargetMetric::ButteraugliINF | TargetMetric::Butteraugli3
+    )
+}
+
+/// Narrows the quantizer limits after probing `quantizer`, given its score
+/// normalized so that higher is better. A score above the target needs a
+/// higher quantizer, one below it a lower quantizer.
+fn update_quantizer_limits(
+    (lower_quantizer_limit, upper_quantizer_limit): (f32, f32),
+    quantizer: f32,
+    score: f64,
+    target_range: (f64, f64),
+    step: f32,
+) -> (f32, f32) {
+    if score > target_range.1 {
+        (
+            (quantizer + step).min(upper_quantizer_limit),
+            upper_quantizer_limit,
+        )
+    } else if score < target_range.0 {
+        (
+            lower_quantizer_limit,
+            (quantizer - step).max(lower_quantizer_limit),
+        )
+    } else {
+        (lower_quantizer_limit, upper_quantizer_limit)
+    }
+}
+
 fn within_range(score: f64, target_range: (f64, f64)) -> bool {
     score >= target_range.0 && score <= target_range.1
 }
@@ -1024,15 +2782,29 @@ pub fn vmaf_auto_threads(workers: usize) -> usize {
     )
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum SkipProbingReason {
     QuantizerTooHigh,
     QuantizerTooLow,
     WithinTolerance,
     ProbeLimitReached,
+    ConfidentFit,
     None,
 }
 
+impl SkipProbingReason {
+    fn suffix(self) -> &'static str {
+        match self {
+            SkipProbingReason::None => "",
+            SkipProbingReason::QuantizerTooHigh => "Early Skip High Quantizer",
+            SkipProbingReason::QuantizerTooLow => " Early Skip Low Quantizer",
+            SkipProbingReason::WithinTolerance => " Early Skip Within Tolerance",
+            SkipProbingReason::ProbeLimitReached => " Early Skip Probe Limit Reached",
+            SkipProbingReason::ConfidentFit => " Early Skip Confident Fit",
+        }
+    }
+}
+
 #[expect(clippy::too_many_arguments)]
 pub fn log_probes(
     quantizer_score_history: &[(f32, f64)],
@@ -1079,13 +2851,7 @@ pub fn log_probes(
             ))
             .unwrap_or_default(),
         history = sorted_quantizer_scores,
-        suffix = match skip {
-            SkipProbingReason::None => "",
-            SkipProbingReason::QuantizerTooHigh => "Early Skip High Quantizer",
-            SkipProbingReason::QuantizerTooLow => " Early Skip Low Quantizer",
-            SkipProbingReason::WithinTolerance => " Early Skip Within Tolerance",
-            SkipProbingReason::ProbeLimitReached => " Early Skip Probe Limit Reached",
-        },
+        suffix = skip.suffix(),
         target_quantizer = target_quantizer,
         target_score = target_score
     );
@@ -1182,4 +2948,339 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn bitrate_target_converges() {
+        // Bitrate falls as the quantizer rises, like a quality metric
+        let bitrate = |quantizer: f32| 8000.0 * (-f64::from(quantizer) / 12.0).exp();
+        let target = (1900.0, 2100.0);
+        assert!(!is_inverse_metric(TargetMetric::Bitrate));
+
+        let mut limits = (1.0f32, 70.0f32);
+        let mut history = vec![];
+        for _ in 0..10 {
+            let quantizer = predict_quantizer(limits.0, limits.1, &history, target, None, 1.0)
+                .expect("predict_quantizer should succeed");
+            let score = bitrate(quantizer);
+            history.push((quantizer, score));
+            if within_range(score, target) {
+                break;
+            }
+            limits = update_quantizer_limits(limits, quantizer, score, target, 1.0);
+        }
+
+        let (quantizer, score) = *history.last().expect("history is not empty");
+        assert!(
+            within_range(score, target),
+            "search ended at Q={quantizer} with {score:.0} kbps: {history:?}"
+        );
+    }
+
+    #[test]
+    fn probe_bitrate_kbps() {
+        // 1 second of video at 24 fps
+        assert!((probe_bitrate(125_000, 24, 24.0) - 1000.0).abs() < f64::EPSILON);
+        // 2 seconds of video at 23.976 fps
+        assert!((probe_bitrate(500_000, 48, 24000.0 / 1001.0) - 1998.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn initial_quantizers_spread_across_range() {
+        let mut tq = TargetQuality::default("temp", Encoder::aom);
+        tq.min_q = 10;
+        tq.max_q = 50;
+        tq.probes = 4;
+        assert!(tq.initial_quantizers(1.0).is_empty());
+
+        tq.parallel_probes = 3;
+        assert_eq!(tq.initial_quantizers(1.0), [20.0, 30.0, 40.0]);
+        tq.parallel_probes = 2;
+        assert_eq!(tq.initial_quantizers(0.25), [23.25, 36.75]);
+        // Never more than the probe limit
+        tq.parallel_probes = 8;
+        assert_eq!(tq.initial_quantizers(1.0).len(), 4);
+
+        let mut pending = vec![20.0, 30.0, 40.0];
+        assert_eq!(next_pending_quantizer(&mut pending, 10.0, 50.0), Some(30.0));
+        assert_eq!(next_pending_quantizer(&mut pending, 31.0, 50.0), Some(40.0));
+        assert_eq!(next_pending_quantizer(&mut pending, 31.0, 50.0), None);
+    }
+
+    #[test]
+    fn parse_quality_constraints() {
+        let constraint = TargetQuality::parse_constraint("vmaf>=93").unwrap();
+        assert_eq!(constraint, QualityConstraint {
+            metric: TargetMetric::VMAF,
+            bound:  93.0,
+        });
+        assert!(constraint.is_satisfied(93.0));
+        assert!(!constraint.is_satisfied(92.9));
+        assert_eq!(constraint.to_string(), "vmaf>=93");
+
+        let constraint = TargetQuality::parse_constraint(" butteraugli-3 <= 1.5 ").unwrap();
+        assert_eq!(constraint.metric, TargetMetric::Butteraugli3);
+        assert!(constraint.is_satisfied(1.2));
+        assert!(!constraint.is_satisfied(1.6));
+
+        assert!(TargetQuality::parse_constraint("vmaf<=93").is_err());
+        assert!(TargetQuality::parse_constraint("butteraugli-inf>=2").is_err());
+        assert!(TargetQuality::parse_constraint("vmaf=93").is_err());
+        assert_eq!(
+            TargetQuality::parse_constraint("ms-ssim>=0.98").unwrap().metric,
+            TargetMetric::MSSSIM
+        );
+        assert!(TargetQuality::parse_constraint("ciede2000>=40").is_err());
+    }
+
+    #[test]
+    fn bitrate_constraint_combined_with_quality() {
+        // Bitrate falls with the quantizer like quality does, so it is a floor
+        let bitrate = TargetQuality::parse_constraint("bitrate>=1500").unwrap();
+        assert!(TargetQuality::parse_constraint("bitrate<=1500").is_err());
+        assert!(bitrate.is_satisfied(1600.0));
+        assert!(!bitrate.is_satisfied(1400.0));
+        assert_eq!(bitrate.target_range(), (1500.0, 1515.0));
+
+        // VMAF >= 93 holds up to Q=28, bitrate >= 1500 only up to Q=20
+        let constraints = [TargetQuality::parse_constraint("vmaf>=93").unwrap(), bitrate];
+        let scores = |quantizer: f32| {
+            let quantizer = f64::from(quantizer);
+            vec![0.25f64.mul_add(-quantizer, 100.0), 8000.0 * (-quantizer / 12.0).exp()]
+        };
+        let satisfies_all =
+            |scores: &[f64]| constraints.iter().zip(scores).all(|(c, &s)| c.is_satisfied(s));
+
+        let (mut lower, mut upper) = (1.0f32, 70.0f32);
+        let mut history: Vec<(f32, Vec<f64>)> = vec![];
+        for _ in 0..10 {
+            let mut quantizer = upper;
+            for (i, constraint) in constraints.iter().enumerate() {
+                let normalized: Vec<(f32, f64)> = history
+                    .iter()
+                    .map(|(quantizer, scores)| (*quantizer, constraint.normalize(scores[i])))
+                    .collect();
+                quantizer = quantizer.min(
+                    predict_quantizer(
+                        lower,
+                        upper,
+                        &normalized,
+                        constraint.target_range(),
+                        None,
+                        1.0,
+                    )
+                    .unwrap(),
+                );
+            }
+            if history.iter().any(|(q, _)| *q == quantizer) {
+                break;
+            }
+            let probe = scores(quantizer);
+            let satisfied = satisfies_all(&probe);
+            history.push((quantizer, probe));
+            if satisfied {
+                lower = (quantizer + 1.0).min(upper);
+            } else {
+                upper = (quantizer - 1.0).max(lower);
+            }
+            if lower > upper
+                || (satisfied && quantizer >= upper)
+                || (!satisfied && quantizer <= lower)
+            {
+                break;
+            }
+        }
+
+        let best = history
+            .iter()
+            .filter(|(_, scores)| satisfies_all(scores))
+            .map(|(quantizer, _)| *quantizer)
+            .max_by(f32::total_cmp);
+        assert_eq!(best, Some(20.0), "{history:?}");
+    }
+
+    #[test]
+    fn parse_frame_floors() {
+        let frames = FrameStatistics {
+            frames:             48,
+            mean:               95.0,
+            median:             95.5,
+            minimum:            78.0,
+            maximum:            98.0,
+            standard_deviation: 2.0,
+            percentile_1:       86.0,
+        };
+
+        let floor = TargetQuality::parse_frame_floor("min>=80").unwrap();
+        assert_eq!(floor.statistic, FloorStatistic::Minimum);
+        assert!(!floor.is_satisfied(&frames));
+        let floor = TargetQuality::parse_frame_floor(" p1 >= 85 ").unwrap();
+        assert!(floor.is_satisfied(&frames));
+        assert_eq!(floor.to_string(), "p1>=85");
+        let floor = TargetQuality::parse_frame_floor("max<=97").unwrap();
+        assert_eq!(floor.statistic, FloorStatistic::Maximum);
+        assert!(!floor.is_satisfied(&frames));
+
+        assert!(TargetQuality::parse_frame_floor("max>=80").is_err());
+        assert!(TargetQuality::parse_frame_floor("min<=80").is_err());
+        assert!(TargetQuality::parse_frame_floor("p5>=80").is_err());
+        assert!(TargetQuality::parse_frame_floor("min>=high").is_err());
+    }
+
+    #[test]
+    fn fallback_policies() {
+        // The target 94-95 lies between two adjacent probes
+        let history = [(20.0, 97.0), (30.0, 95.5), (31.0, 93.0), (40.0, 88.0)];
+        let pick = |fallback| fallback_probe(&history, (94.0, 95.0), fallback, (10.0, 50.0)).0;
+
+        assert!((pick(TargetFallback::Nearest) - 30.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::QualitySafe) - 30.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::SizeSafe) - 31.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::ClampMin) - 10.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::ClampMax) - 50.0).abs() < f32::EPSILON);
+        assert_eq!(
+            "widen-retry".parse::<TargetFallback>().unwrap(),
+            TargetFallback::WidenRetry
+        );
+
+        let mut target_quality = TargetQuality::default("temp", Encoder::svt_av1);
+        target_quality.min_q = 20;
+        target_quality.max_q = 40;
+        // Even the highest quantizer scores too high
+        let history = [(30.0, 98.0), (40.0, 96.0)];
+        assert_eq!(target_quality.widened_limits(&history, (94.0, 95.0)), None);
+        target_quality.fallback = TargetFallback::WidenRetry;
+        assert_eq!(
+            target_quality.widened_limits(&history, (94.0, 95.0)),
+            Some((41.0, 60.0))
+        );
+    }
+
+    #[test]
+    fn hull_denominators_require_resizing_encoder() {
+        let mut target_quality = TargetQuality::default("temp", Encoder::aom);
+        assert!(target_quality.validate_hull_denominators().is_ok());
+
+        target_quality.hull_denominators = vec![12];
+        assert!(target_quality.validate_hull_denominators().is_err());
+        target_quality.target = Some((94.0, 95.0));
+        assert!(target_quality.validate_hull_denominators().is_ok());
+        target_quality.encoder = Encoder::svt_av1;
+        assert!(target_quality.validate_hull_denominators().is_ok());
+        target_quality.encoder = Encoder::x265;
+        assert!(target_quality.validate_hull_denominators().is_err());
+        target_quality.encoder = Encoder::aom;
+        target_quality.metric = TargetMetric::Bitrate;
+        assert!(target_quality.validate_hull_denominators().is_err());
+
+        assert_eq!(TargetQuality::parse_hull_denominator("12"), Ok(12));
+        assert!(TargetQuality::parse_hull_denominator("8").is_err());
+        assert!(TargetQuality::parse_hull_denominator("17").is_err());
+    }
+
+    #[test]
+    fn confident_fit_requires_bracketing_probes() {
+        // Scores of ln(q + 1) = 5 - 0.05 * score
+        let probe = |quantizer: f32| {
+            (
+                quantizer,
+                20.0f64.mul_add(-f64::from(quantizer).ln_1p(), 100.0),
+            )
+        };
+        let monotone = Some((InterpolationMethod::Monotone, InterpolationMethod::Monotone));
+
+        // The probe at Q=22 reaches the lower bound but scores above the range,
+        // and Q=23 has not been probed
+        let history = [probe(20.0), probe(22.0), probe(24.0)];
+        assert!(!confident_fit(&history, (37.0, 37.2), monotone, 1.0));
+
+        // Q=22 scores above the range and Q=23 below it, so no quantizer can hit it
+        let history = [probe(20.0), probe(22.0), probe(23.0)];
+        assert!(confident_fit(&history, (36.8, 37.0), monotone, 1.0));
+
+        // A probe within the range next to the prediction
+        let history = [probe(20.0), probe(22.0), probe(24.0)];
+        assert!(confident_fit(&history, (37.2, 37.4), monotone, 1.0));
+        assert!(!confident_fit(&history, (37.2, 37.4), None, 1.0));
+    }
+
+    #[test]
+    fn search_parameter_requires_fixed_quantizer() -> anyhow::Result<()> {
+        let mut target_quality = TargetQuality::default("temp", Encoder::svt_av1);
+        let video_params = vec!["--preset".to_owned(), "6".to_owned()];
+        assert_eq!(target_quality.fixed_quantizer(&video_params)?, None);
+
+        target_quality.search_parameter =
+            Some("--film-grain:0..50".parse().map_err(anyhow::Error::msg)?);
+        assert!(target_quality.fixed_quantizer(&video_params).is_err());
+        let video_params = vec!["--crf".to_owned(), "30".to_owned()];
+        assert_eq!(target_quality.fixed_quantizer(&video_params)?, Some(30.0));
+        // The probe video params take precedence
+        target_quality.video_params = Some(vec!["--crf".to_owned(), "25".to_owned()]);
+        assert_eq!(target_quality.fixed_quantizer(&video_params)?, Some(25.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn frame_floor_requires_single_quality_target() -> Result<(), String> {
+        let mut target_quality = TargetQuality::default("temp", Encoder::aom);
+        assert!(target_quality.validate_frame_floor().is_ok());
+
+        target_quality.frame_floor = Some(TargetQuality::parse_frame_floor("min>=80")?);
+        assert!(target_quality.validate_frame_floor().is_err());
+        target_quality.target = Some((94.0, 95.0));
+        assert!(target_quality.validate_frame_floor().is_ok());
+
+        target_quality.hull_denominators = vec![12];
+        assert!(target_quality.validate_frame_floor().is_err());
+        target_quality.hull_denominators.clear();
+        target_quality.constraints = vec![TargetQuality::parse_constraint("ssimulacra2>=80")?];
+        assert!(target_quality.validate_frame_floor().is_err());
+        target_quality.constraints.clear();
+
+        // The statistic has to match the direction of the metric
+        target_quality.metric = TargetMetric::Butteraugli3;
+        assert!(target_quality.validate_frame_floor().is_err());
+        target_quality.frame_floor = Some(TargetQuality::parse_frame_floor("max<=2.5")?);
+        assert!(target_quality.validate_frame_floor().is_ok());
+        target_quality.metric = TargetMetric::Bitrate;
+        assert!(target_quality.validate_frame_floor().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn constraint_margins_drive_fallback() {
+        let vmaf = TargetQuality::parse_constraint("vmaf>=93").unwrap();
+        let butteraugli = TargetQuality::parse_constraint("butteraugli-3<=1.5").unwrap();
+        assert!(vmaf.margin(93.0).abs() < f64::EPSILON);
+        assert!((vmaf.margin(vmaf.target_range().1) - MARGIN_RANGE.1).abs() < 1e-9);
+        assert!(butteraugli.margin(1.2) > 0.0);
+        assert!(butteraugli.margin(1.8) < 0.0);
+
+        // No probe satisfies both constraints, butteraugli is the tighter one at Q=20
+        let margins: Vec<(f32, f64)> = [(20.0, 95.0, 1.6), (30.0, 92.0, 1.9), (40.0, 88.0, 2.4)]
+            .into_iter()
+            .map(|(quantizer, vmaf_score, butteraugli_score)| {
+                (
+                    quantizer,
+                    vmaf.margin(vmaf_score).min(butteraugli.margin(butteraugli_score)),
+                )
+            })
+            .collect();
+        let pick = |fallback| fallback_probe(&margins, MARGIN_RANGE, fallback, (10.0, 50.0)).0;
+        assert!((pick(TargetFallback::Nearest) - 20.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::QualitySafe) - 20.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::SizeSafe) - 20.0).abs() < f32::EPSILON);
+        assert!((pick(TargetFallback::ClampMax) - 50.0).abs() < f32::EPSILON);
+
+        let mut target_quality = TargetQuality::default("temp", Encoder::svt_av1);
+        target_quality.min_q = 20;
+        target_quality.max_q = 40;
+        target_quality.fallback = TargetFallback::WidenRetry;
+        assert_eq!(
+            target_quality.widened_limits(&margins, MARGIN_RANGE),
+            Some((0.0, 19.0))
+        );
+    }
 }
@@ -0,0 +1,158 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    probe_cache::CachedProbe,
+    sampling::ProbingWindows,
+    search_param::SearchParameter,
+    target_quality::{FrameFloor, QualityConstraint, SkipProbingReason, TargetFallback},
+    ProbingStatistic,
+    TargetMetric,
+};
+
+/// Name of the directory inside the temporary directory holding the target
+/// quality report of every chunk
+pub(crate) const TQ_REPORT_DIR: &str = "tq_reports";
+
+/// Machine-readable record of the target quality search of a single chunk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChunkReport {
+    pub chunk:              String,
+    pub frames:             usize,
+    pub metric:             TargetMetric,
+    pub target:             Option<(f64, f64)>,
+    pub constraints:        Vec<QualityConstraint>,
+    /// Statistic used to aggregate the per-frame scores into the probe score
+    pub probing_statistic:  ProbingStatistic,
+    pub probing_rate:       usize,
+    pub probing_windows:    Option<ProbingWindows>,
+    /// Parameter searched instead of the quantizer. Quantizers of the probes
+    /// are then positions of the search, see [`SearchParameter::value`].
+    #[serde(default)]
+    pub search_parameter:   Option<SearchParameter>,
+    /// Resize denominator the probes were encoded with when probing a
+    /// resolution ladder
+    #[serde(default)]
+    pub resize_denominator: Option<u8>,
+    /// Probes in the order they were made, with their raw metric scores
+    pub probes:             Vec<CachedProbe>,
+    pub skip_reason:        SkipProbingReason,
+    pub final_quantizer:    f32,
+    /// Fallback policy applied because no probe scored within the target
+    #[serde(default)]
+    pub fallback:           Option<TargetFallback>,
+    /// Bound on the worst frames that the final quantizer had to satisfy
+    #[serde(default)]
+    pub frame_floor:        Option<FrameFloor>,
+}
+
+impl ChunkReport {
+    /// Writes the report to `<temp>/tq_reports/<chunk name>.json`, with the
+    /// resize denominator appended to the name when probing a resolution ladder
+    pub(crate) fn write(&self, temp: &str) -> anyhow::Result<()> {
+        let dir = Path::new(temp).join(TQ_REPORT_DIR);
+        fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let path = dir.join(self.resize_denominator.map_or_else(
+            || format!("{}.json", self.chunk),
+            |denominator| format!("{}_r{denominator}.json", self.chunk),
+        ));
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Path of the combined report written next to the output file
+pub(crate) fn report_path(output_file: &str) -> PathBuf {
+    let mut path = PathBuf::from(output_file).into_os_string();
+    path.push(".tq.json");
+    PathBuf::from(path)
+}
+
+/// Combines the reports of every chunk into a single JSON array ordered by
+/// chunk and writes it to `path`
+pub(crate) fn write_combined_report(temp: &str, path: &Path) -> anyhow::Result<()> {
+    let dir = Path::new(temp).join(TQ_REPORT_DIR);
+    // The directory is missing if no chunk used target quality
+    let mut report_paths: Vec<PathBuf> = fs::read_dir(&dir).map_or_else(
+        |_| Vec::new(),
+        |entries| {
+            entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect()
+        },
+    );
+    report_paths.sort();
+
+    let reports = report_paths
+        .iter()
+        .map(|report_path| {
+            let contents = fs::read_to_string(report_path)
+                .with_context(|| format!("Failed to read {}", report_path.display()))?;
+            serde_json::from_str(&contents)
+                .with_context(|| format!("Invalid target quality report {}", report_path.display()))
+        })
+        .collect::<anyhow::Result<Vec<ChunkReport>>>()?;
+
+    fs::write(path, serde_json::to_string_pretty(&reports)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProbingStatisticName;
+
+    #[test]
+    fn combined_report_is_ordered_by_chunk() -> anyhow::Result<()> {
+        let temp = tempfile::tempdir()?;
+        let temp_str = temp.path().to_string_lossy().to_string();
+        let report = |chunk: &str, final_quantizer: f32| ChunkReport {
+            chunk: chunk.to_string(),
+            frames: 48,
+            metric: TargetMetric::VMAF,
+            target: Some((94.0, 95.0)),
+            constraints: vec![],
+            probing_statistic: ProbingStatistic {
+                name:       ProbingStatisticName::Automatic,
+                value:      None,
+                expression: None,
+            },
+            probing_rate: 1,
+            probing_windows: None,
+            search_parameter: None,
+            resize_denominator: None,
+            probes: vec![],
+            skip_reason: SkipProbingReason::WithinTolerance,
+            final_quantizer,
+            fallback: None,
+            frame_floor: None,
+        };
+
+        report("00001", 30.0).write(&temp_str).expect("report should be written");
+        report("00000", 28.0).write(&temp_str).expect("report should be written");
+        let combined = temp.path().join("output.mkv.tq.json");
+        write_combined_report(&temp_str, &combined).expect("combined report should be written");
+
+        let reports: Vec<ChunkReport> = serde_json::from_str(&fs::read_to_string(&combined)?)?;
+        assert_eq!(
+            reports
+                .iter()
+                .map(|r| (r.chunk.as_str(), r.final_quantizer))
+                .collect::<Vec<_>>(),
+            [("00000", 28.0), ("00001", 30.0)]
+        );
+        assert_eq!(
+            report_path("output.mkv"),
+            PathBuf::from("output.mkv.tq.json")
+        );
+
+        Ok(())
+    }
+}
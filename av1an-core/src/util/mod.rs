@@ -3,6 +3,8 @@ mod tests;
 
 use std::path::{Path, PathBuf};
 
+use serde::Serialize;
+
 /// Count the number of elements passed to this macro.
 ///
 /// Extra commas in between other commas are counted as an element.
@@ -131,3 +133,36 @@ pub fn read_in_dir(path: &Path) -> anyhow::Result<impl Iterator<Item = PathBuf>>
         d.file_type().map_or(None, |file_type| (!file_type.is_dir()).then(|| d.path()))
     }))
 }
+
+/// 64-bit FNV-1a hasher. Unlike `DefaultHasher`, its output does not change
+/// between Rust versions, builds or platforms, so it can key files that are
+/// read again by later runs. Only bytes are fed to it, as the `Hash` impls of
+/// integers depend on the endianness and width of the platform.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct StableHasher(u64);
+
+impl StableHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    pub(crate) const fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+        }
+    }
+
+    pub(crate) const fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+/// [`StableHasher`] hash of the JSON serialization of `value`
+pub(crate) fn stable_hash<T: Serialize + ?Sized>(value: &T) -> u64 {
+    let mut hasher = StableHasher::new();
+    hasher.write(&serde_json::to_vec(value).expect("value should serialize to JSON"));
+    hasher.finish()
+}
@@ -1,5 +1,7 @@
 use std::borrow::Cow;
 
+use crate::util::{stable_hash, StableHasher};
+
 #[test]
 fn count_macro() {
     assert_eq!(crate::count!["rav1e", "-s", "10",], 3);
@@ -33,3 +35,19 @@ fn inplace_vec_is_sound() {
 
     assert_eq!(v1, v2);
 }
+
+#[test]
+fn stable_hash_is_fnv1a() {
+    let hash = |bytes: &[u8]| {
+        let mut hasher = StableHasher::new();
+        hasher.write(bytes);
+        hasher.finish()
+    };
+    // Reference values of 64-bit FNV-1a
+    assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+    assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    // Values are hashed as their JSON text
+    assert_eq!(stable_hash("a"), hash(b"\"a\""));
+    assert_eq!(stable_hash(&(1u32, [2.5f64])), hash(b"[1,[2.5]]"));
+}
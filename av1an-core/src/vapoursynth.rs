@@ -23,6 +23,7 @@ use crate::{
         butteraugli::ButteraugliSubMetric,
         xpsnr::{weight_xpsnr, XPSNRSubMetric},
     },
+    sampling::FrameSampling,
     ClipInfo,
     Input,
     InputPixelFormat,
@@ -508,6 +509,31 @@ fn select_every<'core>(
         .map_err(|_| anyhow::anyhow!(error_message.clone()))
 }
 
+/// Joins the given frame ranges of `node`, with exclusive ends
+fn splice_windows<'core>(
+    core: CoreRef<'core>,
+    node: &Node<'core>,
+    windows: &[(usize, usize)],
+) -> anyhow::Result<Node<'core>> {
+    let api = API::get().ok_or_else(|| anyhow::anyhow!("Failed to get VapourSynth API"))?;
+    let std = get_plugin(core, PluginId::Std)?;
+
+    let mut arguments = vapoursynth::map::OwnedMap::new(api);
+    for &(start, end) in windows {
+        arguments.append_node(
+            "clips",
+            &trim_node(core, node, start as u32, end as u32 - 1)?,
+        )?;
+    }
+
+    let error_message = format!("Failed to splice {} probing windows", windows.len());
+
+    std.invoke("Splice", &arguments)
+        .map_err(|_| anyhow::anyhow!(error_message.clone()))?
+        .get_node("clip")
+        .map_err(|_| anyhow::anyhow!(error_message.clone()))
+}
+
 fn compare_ssimulacra2<'core>(
     core: CoreRef<'core>,
     source: &Node<'core>,
@@ -872,7 +898,7 @@ pub fn get_source_chunk<'core>(
     source_node: &Node<'core>,
     frame_range: (u32, u32),
     probe_res: Option<(u32, u32)>,
-    sample_rate: usize,
+    sampling: &FrameSampling,
 ) -> anyhow::Result<Node<'core>> {
     let mut chunk_node = trim_node(core, source_node, frame_range.0, frame_range.1 - 1)?;
 
@@ -880,8 +906,14 @@ pub fn get_source_chunk<'core>(
         chunk_node = resize_node(core, &chunk_node, Some(width), Some(height), None, None)?;
     }
 
-    if sample_rate > 1 {
-        chunk_node = select_every(core, &chunk_node, sample_rate)?;
+    match sampling {
+        FrameSampling::Every(n) if *n > 1 => {
+            chunk_node = select_every(core, &chunk_node, *n)?;
+        },
+        FrameSampling::Every(_) => (),
+        FrameSampling::Windows(windows) => {
+            chunk_node = splice_windows(core, &chunk_node, windows)?;
+        },
     }
 
     Ok(chunk_node)
@@ -894,9 +926,9 @@ pub fn get_comparands<'core>(
     encoded: &Path,
     frame_range: (u32, u32),
     probe_res: Option<(u32, u32)>,
-    sample_rate: usize,
+    sampling: &FrameSampling,
 ) -> anyhow::Result<(Node<'core>, Node<'core>)> {
-    let chunk_node = get_source_chunk(core, source_node, frame_range, probe_res, sample_rate)?;
+    let chunk_node = get_source_chunk(core, source_node, frame_range, probe_res, sampling)?;
     let encoded_node = import_video(core, encoded, Some(false))?;
     let resized_encoded_node = if let Some((width, height)) = probe_res {
         resize_node(core, &encoded_node, Some(width), Some(height), None, None)?
@@ -922,7 +954,7 @@ pub fn measure_butteraugli(
     encoded: &Path,
     frame_range: (u32, u32),
     probe_res: Option<(u32, u32)>,
-    sample_rate: usize,
+    sampling: &FrameSampling,
     plugins: VapoursynthPlugins,
 ) -> anyhow::Result<Vec<f64>> {
     let mut environment = Environment::new()?;
@@ -941,7 +973,7 @@ pub fn measure_butteraugli(
         encoded,
         frame_range,
         probe_res,
-        sample_rate,
+        sampling,
     )?;
     let (compared_node, butteraugli_key) =
         compare_butteraugli(core, &chunk_node, &encoded_node, submetric, plugins)?;
@@ -961,7 +993,7 @@ pub fn measure_ssimulacra2(
     encoded: &Path,
     frame_range: (u32, u32),
     probe_res: Option<(u32, u32)>,
-    sample_rate: usize,
+    sampling: &FrameSampling,
     plugins: VapoursynthPlugins,
 ) -> anyhow::Result<Vec<f64>> {
     let mut environment = Environment::new()?;
@@ -979,7 +1011,7 @@ pub fn measure_ssimulacra2(
         encoded,
         frame_range,
         probe_res,
-        sample_rate,
+        sampling,
     )?;
     let (compared_node, ssimulacra_key) =
         compare_ssimulacra2(core, &chunk_node, &encoded_node, plugins)?;
@@ -1000,7 +1032,7 @@ pub fn measure_xpsnr(
     encoded: &Path,
     frame_range: (u32, u32),
     probe_res: Option<(u32, u32)>,
-    sample_rate: usize,
+    sampling: &FrameSampling,
     plugins: VapoursynthPlugins,
 ) -> anyhow::Result<Vec<f64>> {
     let mut environment = Environment::new()?;
@@ -1018,7 +1050,7 @@ pub fn measure_xpsnr(
         encoded,
         frame_range,
         probe_res,
-        sample_rate,
+        sampling,
     )?;
     let compared_node = compare_xpsnr(core, &chunk_node, &encoded_node, plugins)?;
 
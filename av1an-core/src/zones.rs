@@ -40,11 +40,11 @@ pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Resu
             zone.zone_overrides
                 .as_ref()
                 .and_then(|ovr| ovr.target_quality.as_ref())
-                .and_then(|tq| tq.target.as_ref().filter(|_| condition(tq)))
-                .is_some()
+                .is_some_and(|tq| tq.is_enabled() && condition(tq))
         })
     };
-    let tq_used_and_metric_is = |metric: TargetMetric| tq_used_and(&|tq| tq.metric == metric);
+    let tq_used_and_metric_is =
+        |metric: TargetMetric| tq_used_and(&|tq| tq.metrics().contains(&metric));
 
     if tq_used_and_metric_is(TargetMetric::VMAF) {
         validate_libvmaf()?;
@@ -64,22 +64,26 @@ pub(crate) fn validate_zones(args: &EncodeArgs, zones: &[Scene]) -> anyhow::Resu
         args.validate_butteraugli_3()?;
     }
 
-    // Using XPSNR and a probing rate > 1, validate XPSNR
+    // Using XPSNR and a probing rate > 1 or probing windows, validate XPSNR
     if tq_used_and(&|tq| {
-        matches!(tq.metric, TargetMetric::XPSNR | TargetMetric::XPSNRWeighted)
-            && tq.probing_rate > 1
+        tq.metrics()
+            .iter()
+            .any(|metric| matches!(metric, TargetMetric::XPSNR | TargetMetric::XPSNRWeighted))
+            && tq.samples_frames()
     }) {
-        // Any value greater than 1, uses VapourSynth
-        args.validate_xpsnr(TargetMetric::XPSNR, 2)?;
+        // Sampled frames use VapourSynth
+        args.validate_xpsnr(TargetMetric::XPSNR, true)?;
     }
 
-    // Using XPSNR and a probing rate of 1, validate XPSNR
+    // Using XPSNR on every frame, validate XPSNR
     if tq_used_and(&|tq| {
-        matches!(tq.metric, TargetMetric::XPSNR | TargetMetric::XPSNRWeighted)
-            && tq.probing_rate == 1
+        tq.metrics()
+            .iter()
+            .any(|metric| matches!(metric, TargetMetric::XPSNR | TargetMetric::XPSNRWeighted))
+            && !tq.samples_frames()
     }) {
-        // 1, uses FFmpeg
-        args.validate_xpsnr(TargetMetric::XPSNR, 1)?;
+        // Every frame uses FFmpeg
+        args.validate_xpsnr(TargetMetric::XPSNR, false)?;
     }
 
     Ok(())
@@ -9,29 +9,42 @@ use std::{
 
 use anyhow::{anyhow, bail, ensure, Context};
 use av1an_core::{
+    default_scene_cache_dir,
     ffmpeg::FFPixelFormat,
     hash_path,
     into_vec,
+    measure_metric,
+    parse_target_size,
     read_in_dir,
     vapoursynth::{get_vapoursynth_plugins, VSZipVersion},
+    write_metric_scores,
+    AlignmentCheck,
+    AnalysisOptions,
     Av1anContext,
     ChunkMethod,
     ChunkOrdering,
     ConcatMethod,
     EncodeArgs,
     Encoder,
+    FrameFloor,
     Input,
     InputPixelFormat,
     InterpolationMethod,
     PixelFormat,
+    ProbingWindows,
+    QualityConstraint,
+    SceneExport,
+    SceneFormat,
     ScenecutMethod,
+    SearchParameter,
     SplitMethod,
+    TargetFallback,
     TargetMetric,
     TargetQuality,
     Verbosity,
     VmafFeature,
 };
-use clap::{value_parser, CommandFactory, Parser};
+use clap::{value_parser, Args, CommandFactory, Parser, Subcommand};
 use clap_complete::generate;
 use num_traits::cast::ToPrimitive;
 use once_cell::sync::OnceCell;
@@ -175,8 +188,11 @@ fn version() -> &'static str {
 /// Cross-platform command-line AV1 / VP9 / HEVC / H264 encoding framework with
 /// per-scene quality encoding
 #[derive(Parser, Debug)]
-#[clap(name = "av1an", version = version())]
+#[clap(name = "av1an", version = version(), subcommand_negates_reqs = true)]
 pub struct CliOpts {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// Input file to encode
     ///
     /// Can be a video or VapourSynth (.py, .vpy) script.
@@ -244,6 +260,14 @@ pub struct CliOpts {
     pub resume: bool,
 
     /// Do not delete the temporary folder after encoding has finished
+    ///
+    /// The target quality probes stored in the temporary folder are kept
+    /// even without --resume, and reused by later encodes of the same input
+    /// path with the same probing settings. A probe is only reused if the
+    /// content of the input (and of --proxy) still has the same fingerprint:
+    /// the size and 1 MiB samples at the start, the middle and the end of
+    /// video files, and the text, arguments and named files of VapourSynth
+    /// scripts.
     #[clap(short, long)]
     pub keep: bool,
 
@@ -295,15 +319,71 @@ pub struct CliOpts {
     pub vspipe_args: Vec<String>,
 
     /// File location for scenes
+    ///
+    /// Scene lists written by other tools are imported instead of running
+    /// scene detection, see --scenes-format.
     #[clap(short, long, help_heading = "Scene Detection")]
     pub scenes: Option<PathBuf>,
 
+    #[rustfmt::skip]
+    /// Format of the file given with --scenes
+    ///
+    /// auto          - Guess the format from the extension and contents of the
+    ///                 file
+    /// av1an         - Scenes JSON written by av1an
+    /// pyscenedetect - Scene list CSV written by PySceneDetect
+    /// ffmpeg        - Log of the FFmpeg scdet filter, or of the showinfo filter
+    ///                 after a scene selection filter
+    /// qpfile        - x264/x265 qpfile, where I and K frames start a scene
+    /// chapters      - Matroska XML chapters or simple OGM chapters
+    /// frames        - Frame numbers separated by commas or whitespace
+    /// timecodes     - Timecodes ([[HH:]MM:]SS[.sss] or HH:MM:SS:FF) separated
+    ///                 by commas or whitespace
+    ///
+    /// Imported scene lists only hold the frames starting a scene. Zones,
+    /// --force-keyframes and --extra-split are applied to them.
+    #[clap(long, default_value_t = SceneFormat::Auto, requires = "scenes", help_heading = "Scene Detection", verbatim_doc_comment)]
+    pub scenes_format: SceneFormat,
+    /// Directory of the scene detection cache
+    ///
+    /// Scene detection results are stored there, keyed by the content of the
+    /// input (or of --proxy), the scene detection options and the frames and
+    /// --min-scene-len of zones, and reused by later encodes of the same
+    /// source without --scenes, even if the encoder settings change. Defaults
+    /// to av1an/scenes in the cache directory of the user.
+    ///
+    /// Video files are identified by their size and 1 MiB samples at the
+    /// start, the middle and the end, so an edit that keeps the size and
+    /// misses the samples reuses stale scenes. VapourSynth scripts are
+    /// identified by their text, arguments and the files they name. Use
+    /// --no-scene-cache for such sources.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub scene_cache: Option<PathBuf>,
+
+    /// Always run scene detection instead of using the scene detection cache
+    #[clap(long, conflicts_with = "scene_cache", help_heading = "Scene Detection")]
+    pub no_scene_cache: bool,
+
     /// Run the scene detection only before exiting
     ///
     /// Requires a scene file with --scenes.
     #[clap(long, requires("scenes"), help_heading = "Scene Detection")]
     pub sc_only: bool,
 
+    #[rustfmt::skip]
+    /// Write the scenes as <FORMAT>=<PATH> after scene detection, for example
+    /// with --sc-only (can be given multiple times)
+    ///
+    /// chapters  - Matroska XML chapters, one chapter per scene
+    /// qpfile    - x264/x265 qpfile with a keyframe at the start of every scene
+    /// edl       - CMX 3600 edit decision list, one event per scene
+    /// timecodes - Start timecode of every scene, one per line
+    ///
+    /// The scenes include the extra splits and forced keyframes. Chapters and
+    /// EDL events of scenes in a zone name the encoder and parameters of the
+    /// zone.
+    #[clap(long, help_heading = "Scene Detection", verbatim_doc_comment)]
+    pub sc_export: Vec<SceneExport>,
     /// Method used to determine chunk boundaries
     ///
     /// "av-scenechange" uses an algorithm to analyze which frames of the video
@@ -334,6 +414,14 @@ pub struct CliOpts {
     #[clap(long, help_heading = "Scene Detection")]
     pub sc_downscale_height: Option<usize>,
 
+    /// Refine the scenecuts of av-scenechange using its frame scores
+    ///
+    /// Scenecuts found inside fades and dissolves are moved to the first frame
+    /// after the transition, and scenecuts caused by a flash lasting a few
+    /// frames are removed. Scenecuts at zone boundaries are never changed.
+    #[clap(long, help_heading = "Scene Detection")]
+    pub sc_refine: bool,
+
     /// Perform scene detection with this pixel format
     #[clap(long, help_heading = "Scene Detection")]
     pub sc_pix_format: Option<FFPixelFormat>,
@@ -476,7 +564,8 @@ pub struct CliOpts {
     /// complexity).
     ///
     /// segment - Create chunks based on keyframes in the source. Not frame
-    /// exact, as it can only split on keyframes in the source.
+    /// exact, as it can only split on keyframes in the source. Zone
+    /// boundaries must be keyframes of the source.
     /// Requires intermediate files (which can be large).
     ///
     /// Default: bestsource (if available), otherwise lsmash (if available),
@@ -615,6 +704,27 @@ pub struct CliOpts {
     #[clap(long, help_heading = "VMAF")]
     pub vmaf: bool,
 
+    /// Calculate metrics on the whole encode once it is finished
+    ///
+    /// For every metric, the scores of every frame are written to
+    /// "<output>.<metric>.csv", with statistics of every chunk and of the whole
+    /// encode in "<output>.<metric>.json", including the scores aggregated with
+    /// --probing-stat. "<output>.<metric>.svg" plots the scores with the chunk
+    /// boundaries marked.
+    ///
+    /// Every target metric except bitrate can be analyzed, with the same
+    /// requirements as with --target-metric. VMAF and XPSNR are calculated at
+    /// --vmaf-res, the other metrics at --probe-res.
+    ///
+    /// Example: --analysis-metrics vmaf,ssimulacra2,xpsnr
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help_heading = "VMAF",
+        verbatim_doc_comment
+    )]
+    pub analysis_metrics: Vec<TargetMetric>,
+
     /// Path to VMAF model (used by --vmaf and --target-quality)
     ///
     /// If not specified, ffmpeg's default is used.
@@ -662,12 +772,86 @@ pub struct CliOpts {
     /// The XPSNR score minimum is 0 as the worst quality and increases as
     /// quality increases towards infinity.
     ///
-    /// Specify as a range: --target-quality 75-85 for VMAF/SSIMULACRA2
-    /// or --target-quality 1.0-1.5 for butteraugli metrics.
+    /// The bitrate "score" is the bitrate of the probe in kbps, so the
+    /// quantizer is searched for to hit a bitrate range rather than a quality
+    /// range.
+    ///
+    /// Specify as a range: --target-quality 75-85 for VMAF/SSIMULACRA2,
+    /// --target-quality 1.0-1.5 for butteraugli metrics
+    /// or --target-quality 2500-3000 for bitrate.
     /// Floating-point values are allowed for all metrics.
     #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_target_qp_range)]
     pub target_quality: Option<(f64, f64)>,
 
+    /// Constrain several metrics at once instead of targeting a single range
+    /// (disabled by default)
+    ///
+    /// For each chunk, the highest quantizer/crf at which every constraint is
+    /// satisfied is used. Each probe is scored with all of the constrained
+    /// metrics, so the requirements of every metric listed in
+    /// --target-metric apply.
+    ///
+    /// Specify as a comma separated list of constraints, using "<=" for
+    /// butteraugli metrics and ">=" for the others. Bitrate falls as the
+    /// quantizer rises, so it takes ">=" as a minimum kbps:
+    /// --target-constraints "vmaf>=93,ssimulacra2>=75,butteraugli-3<=1.5"
+    #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_constraint, value_delimiter = ',', conflicts_with = "target_quality")]
+    pub target_constraints: Vec<QualityConstraint>,
+
+    #[rustfmt::skip]
+    /// What to do with a chunk when no probe scores within --target-quality
+    ///
+    /// nearest      - Use the probe with the score closest to the middle of the
+    ///                range
+    /// fail         - Fail the encode
+    /// clamp-min    - Use the lowest quantizer of --qp-range
+    /// clamp-max    - Use the highest quantizer of --qp-range
+    /// quality-safe - Use the highest quantizer probed that scores above the
+    ///                range, or the lowest quantizer probed if none do
+    /// size-safe    - Use the lowest quantizer probed that scores below the
+    ///                range, or the highest quantizer probed if none do
+    /// widen-retry  - Widen --qp-range past the side where the target was missed
+    ///                by its width, up to the full range of the encoder, and
+    ///                search again with as many probes. Then use the nearest
+    ///                probe if the target is still missed.
+    ///
+    /// With --target-constraints, the fallback applies when no probe satisfies
+    /// every constraint, scoring each probe by its tightest constraint.
+    ///
+    /// A warning is logged for every chunk where the fallback is applied, and
+    /// the target quality report (see --tq-report) records it.
+    #[clap(long, default_value_t = TargetFallback::Nearest, help_heading = "Target Quality", verbatim_doc_comment)]
+    pub target_fallback: TargetFallback,
+    /// Bound on the worst frames of each chunk, checked alongside
+    /// --target-quality (disabled by default)
+    ///
+    /// min>=<score> - No frame may score below the score
+    /// p1>=<score>  - The 1st percentile of the frame scores may not be below
+    ///                the score
+    /// max<=<score> - No frame may score above the score, for butteraugli
+    ///
+    /// If the worst frames of the quantizer picked for the target do not
+    /// satisfy the floor, lower quantizers are probed with up to --probes more
+    /// probes, and the highest quantizer satisfying it is used. The lowest
+    /// quantizer probed is used if none does. Cannot be used with
+    /// --target-constraints or --hull-denominators.
+    #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_frame_floor, requires = "target_quality", verbatim_doc_comment)]
+    pub frame_floor: Option<FrameFloor>,
+
+    #[rustfmt::skip]
+    /// Check that the frames of the first probe of each chunk line up with the
+    /// frames of the source before trusting its scores
+    ///
+    /// off  - Do not check the alignment
+    /// warn - Log a warning when the probe is offset from the source
+    /// fail - Fail the encode when the probe is offset from the source
+    ///
+    /// The first frames are compared at offsets of up to 2 frames in either
+    /// direction. A filter in --ffmpeg, a --proxy or a variable frame rate
+    /// source can shift the frames by one, which makes every metric collapse
+    /// and drives the quantizer to the bottom of --qp-range.
+    #[clap(long, default_value_t = AlignmentCheck::Warn, help_heading = "Target Quality", verbatim_doc_comment)]
+    pub alignment_check: AlignmentCheck,
     /// Quantizer range bounds for target quality search (disabled by default)
     ///
     /// Specifies the minimum and maximum quantizer/CRF/qp values to use during
@@ -691,6 +875,7 @@ pub struct CliOpts {
     ///   linear    - Simple linear interpolation using the 2 closest points. Fast and stable, good for monotonic data.
     ///   quadratic - Quadratic interpolation using all 3 points. Better curve fitting than linear, moderate accuracy.
     ///   natural   - Natural cubic spline interpolation. Smooth curves with natural boundary conditions. (default)
+    ///   monotone  - Monotone log-linear fit of all points after isotonic regression. Robust to noisy, non-monotonic scores.
     ///
     /// 5th round methods (4 known points):
     ///   linear                  - Simple linear interpolation using 2 closest points. Most stable for narrow ranges.
@@ -700,6 +885,12 @@ pub struct CliOpts {
     ///   catmull                 - Catmull-Rom spline interpolation. Smooth curves that pass through all points.
     ///   akima                   - Akima spline interpolation. Reduces oscillations. Beware: Designed for 5 data points originally.
     ///   cubic | cubicpolynomial - Cubic polynomial through all 4 points. High accuracy but can overshoot dramatically.
+    ///   monotone | isotonic     - Monotone log-linear fit of all points. Also used for every later round, so --probes
+    ///                             can be higher than 5 without falling back to binary search.
+    ///
+    /// When monotone is used in either round, probing also stops early once the fit is confident (R² reduced by the
+    /// share of non-monotonic points of at least 0.98) and predicts a quantizer next to one already in the target range,
+    /// or between two probes a step apart that score on either side of the target range.
     ///
     /// Recommendations:
     ///   - For most content:      natural-pchip      - Good balance of accuracy and stability (tested)
@@ -710,25 +901,30 @@ pub struct CliOpts {
     ///   --interp-method natural-pchip      # Default: balanced accuracy and stability
     ///   --interp-method quadratic-akima    # Experimental
     ///   --interp-method linear-catmull     # Simple start, smooth finish
+    ///   --interp-method monotone-monotone  # Noisy metrics such as butteraugli
     #[clap(long, help_heading = "Target Quality", value_parser = TargetQuality::parse_interp_method, verbatim_doc_comment)]
     pub interp_method: Option<(InterpolationMethod, InterpolationMethod)>,
     /// The metric used for Target Quality mode
     ///
     /// vmaf - Requires FFmpeg with VMAF enabled.
     ///
-    /// ssimulacra2 - Requires Vapoursynth-HIP or VapourSynth-Zig Image Process
-    /// plugin. Also requires Chunk method to be set to "lsmash", "ffms2",
-    /// "bestsource", or "dgdecnv".
+    /// ssimulacra2 - Uses Vapoursynth-HIP or VapourSynth-Zig Image Process
+    /// plugin when installed and Chunk method is set to "lsmash", "ffms2",
+    /// "bestsource", or "dgdecnv". Otherwise it is calculated by Av1an itself,
+    /// which works with every Chunk method but is slower, and a warning is
+    /// shown. The scores of the two differ slightly, so the same target can
+    /// give other quantizers with and without the plugins.
     ///
     /// butteraugli-inf - Uses the Infinite-Norm value of butteraugli with a
-    /// target intensity of 203 nits. Requires Vapoursynth-HIP or Julek
-    /// plugin. Also requires Chunk method to be set to "lsmash", "ffms2",
-    /// "bestsource", or "dgdecnv".
+    /// target intensity of 203 nits. A native implementation of butteraugli
+    /// is out of scope, so it requires Vapoursynth-HIP or Julek plugin. Also
+    /// requires Chunk method to be set to "lsmash", "ffms2", "bestsource", or
+    /// "dgdecnv".
     ///
     /// butteraugli-3  - Uses the 3-Norm value of butteraugli with a target
-    /// intensity of 203 nits. Requires Vapoursynth-HIP plugin. Also
-    /// requires Chunk method to be set to "lsmash", "ffms2", "bestsource",
-    /// or "dgdecnv".
+    /// intensity of 203 nits. A native implementation of butteraugli is out
+    /// of scope, so it requires Vapoursynth-HIP plugin. Also requires Chunk
+    /// method to be set to "lsmash", "ffms2", "bestsource", or "dgdecnv".
     ///
     /// xpsnr -  Uses the minimum of Y, U, and V. Requires FFmpeg with XPSNR
     /// enabled when Probing Rate is unspecified or set to 1. When Probing Rate
@@ -742,6 +938,17 @@ pub struct CliOpts {
     /// the VapourSynth-Zig Image Process plugin version R7 or newer is required
     /// and the Chunk method must be set to "lsmash", "ffms2", "bestsource", or
     /// "dgdecnv".
+    ///
+    /// bitrate - Targets the bitrate of each chunk in kbps, calculated from the
+    /// size of the probe. No metric is calculated. When Probing Rate is
+    /// higher than 1, only every nth frame is encoded, which usually
+    /// overestimates the bitrate of the final encode.
+    ///
+    /// psnr, ssim, ms-ssim - Luma PSNR in dB, SSIM, and multi-scale SSIM,
+    /// computed by Av1an itself on a single thread. Only the luma plane is
+    /// compared. No libvmaf, FFmpeg filter or VapourSynth plugin is required,
+    /// but the reference and the probe are still decoded and scaled by the
+    /// ffmpeg executable.
     #[clap(long, default_value_t = TargetMetric::VMAF, help_heading = "Target Quality")]
     pub target_metric: TargetMetric,
     /// Maximum number of probes allowed for target quality
@@ -757,6 +964,95 @@ pub struct CliOpts {
     #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=4), help_heading = "Target Quality")]
     pub probing_rate: u16,
 
+    /// Probe contiguous windows of frames instead of the whole chunk
+    /// (disabled by default)
+    ///
+    /// Encoders behave differently on decimated frames than on continuous
+    /// motion, so probing a few contiguous windows can be more representative
+    /// than a probing rate. The windows are spread evenly from the start to the
+    /// end of each chunk, and both the probe encode and the metric use only the
+    /// frames in the windows. Chunks that are not longer than the windows
+    /// combined are probed whole.
+    ///
+    /// With XPSNR, the VapourSynth-Zig Image Process plugin version R7 or newer
+    /// is required and the Chunk method must be set to "lsmash", "ffms2",
+    /// "bestsource", or "dgdecnv".
+    ///
+    /// Specify as <count>x<frames>: --probing-windows 3x24 probes 24 frames at
+    /// the start, middle and end of each chunk.
+    #[clap(long, conflicts_with = "probing_rate", help_heading = "Target Quality")]
+    pub probing_windows: Option<ProbingWindows>,
+
+    /// Encoder parameter to search instead of the quantizer
+    /// (disabled by default)
+    ///
+    /// The quantizer stays fixed at the value in --probe-video-params, or in
+    /// --video-params otherwise, and target quality searches the value of
+    /// this parameter instead, e.g. the film grain strength or the preset.
+    /// The parameter is set in the probes and in the final encode.
+    ///
+    /// Specify as <name>[=]:<min>..<max>[:<step>][:<direction>]. A trailing
+    /// "=" on the name passes the value as <name>=<value> instead of
+    /// <name> <value>. The step defaults to 1. The direction is "decreasing"
+    /// (default) if higher values lower the quality, or "increasing" if they
+    /// raise it.
+    ///
+    /// Examples: --search-param --film-grain:0..50:1 or --search-param
+    /// --sharpness=:-7..7:1:increasing
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        conflicts_with = "target_size",
+        help_heading = "Target Quality"
+    )]
+    pub search_param: Option<SearchParameter>,
+
+    /// Resize denominators to probe in addition to the source resolution,
+    /// building a rate-quality convex hull for each chunk (disabled by default)
+    ///
+    /// A denominator of n scales the frames to 8/n of their size, from 9 for
+    /// 8/9 down to 16 for half the size. 12 turns 1080p into 720p.
+    ///
+    /// Target quality searches for the quantizer meeting --target-quality at
+    /// the source resolution and at each of these scales. All of their probes
+    /// form the convex hull of the chunk, and the cheapest point on it that
+    /// meets the target is encoded, or the point matching --hull-slope.
+    ///
+    /// The encoder scales the frames of chunks picked at a lower resolution
+    /// itself, so every chunk keeps the frame size of the sequence header,
+    /// which is the size of the frames after --ffmpeg filters, and can be
+    /// concatenated with any method. The decoder outputs those frames at the
+    /// lower resolution, with the full size as their render size. Requires
+    /// aom or svt-av1. Metrics compare the probes scaled to --probe-res, or to
+    /// the source resolution if it is not set.
+    ///
+    /// Specify as a comma separated list: --hull-denominators 12,16
+    #[clap(long, value_parser = TargetQuality::parse_hull_denominator, value_delimiter = ',', requires = "target_quality", conflicts_with = "target_size", help_heading = "Target Quality")]
+    pub hull_denominators: Vec<u8>,
+
+    /// Pick the point on the convex hull where the score gained per Mbps of
+    /// bitrate drops below this slope, instead of the cheapest point meeting
+    /// the target
+    ///
+    /// Using the same slope for every chunk spends bits where they improve the
+    /// quality the most.
+    #[clap(long, requires = "hull_denominators", help_heading = "Target Quality")]
+    pub hull_slope: Option<f64>,
+
+    /// Number of probes to make at the same time at the start of the target
+    /// quality search of each chunk
+    ///
+    /// The first probes are spread evenly across --qp-range and run
+    /// concurrently, each with its own probe encode and metric calculation.
+    /// The search then continues one probe at a time from their results. This
+    /// keeps cores busy with few workers and long chunks, at the cost of
+    /// probes that the search might not have needed. The metric threads of
+    /// each worker (--vmaf-threads) are split between the concurrent probes.
+    /// Chunks that start from the results of their neighbouring chunks probe
+    /// one at a time.
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=8), help_heading = "Target Quality")]
+    pub parallel_probes: u16,
+
     /// Parameters for video encoder during Target Quality probing
     ///
     /// It is recommended to specify a faster speed/preset/cpu-used and omit
@@ -809,12 +1105,257 @@ pub struct CliOpts {
     ///   minimum                    - Lowest quality value
     ///   maximum                    - Highest quality value
     ///   root-mean-square           - Root Mean Square (quadratic mean)
+    ///   window-minimum=<FLOAT>     - Lowest mean score of any window of the specified length in seconds, so that sustained drops weigh more than single frames
+    ///   <EXPRESSION>               - Arithmetic expression over the statistics of the probe, e.g. "min(percentile(5), mean - 1.5*stddev)" or "0.7*mean + 0.3*harmonic"
+    ///
+    /// Expressions:
+    ///   Statistics: mean, median, harmonic, mode, min, max, stddev, variance, rms
+    ///   Functions:  percentile(<EXPR>), min(<EXPR>, ...), max(<EXPR>, ...), abs(<EXPR>), clamp(<EXPR>, <LOW>, <HIGH>)
+    ///               window_min(<SECONDS>), window_percentile(<SECONDS>, <PERCENTILE>) - Lowest or percentile of the mean scores of sliding windows
+    ///   Operators:  + - * / and parentheses
     ///
     /// Warning:
     ///   "root-mean-square" should only be used with inverse target metrics such as "butteraugli".
     ///   "harmonic" works as expected when there are no negative scores. Use with caution with target metrics such as "ssimulacra2".
     #[clap(long, default_value_t = String::from("auto"), help_heading = "Target Quality", verbatim_doc_comment)]
     pub probing_stat: String,
+    /// Target size of the encoded video stream (disabled by default)
+    ///
+    /// Before encoding, every chunk is probed at quantizers spread across
+    /// --qp-range (--probes times) and scored with --target-metric. The
+    /// quantizer of each chunk is then chosen so that the estimated sizes add
+    /// up to the target size while keeping the quality of all chunks as even
+    /// as possible. With "--target-metric bitrate", the quantizer is kept as
+    /// even as possible instead.
+    ///
+    /// Unless the probes encode every frame with the final parameters
+    /// ("--probe-video-params copy" and a probing rate of 1), every chunk is
+    /// also encoded once with the final parameters at the middle quantizer,
+    /// and the sizes of its probes are scaled to match. The result can still
+    /// be off where the probes scale differently across the range, or with
+    /// two-pass encoding, as the probes use a single pass. Audio is not
+    /// included in the budget. Cannot be used with --search-param.
+    ///
+    /// Specify in bytes or with a suffix: --target-size 4G, --target-size
+    /// 700MB or --target-size 1.5GiB
+    #[clap(long, value_parser = parse_target_size, help_heading = "Target Quality")]
+    pub target_size: Option<u64>,
+
+    /// Write a report of the target quality search next to the output file
+    ///
+    /// A JSON record of the search of every chunk is always written to the
+    /// "tq_reports" directory in the temporary folder. It lists every probed
+    /// quantizer with its raw score, a summary of the per-frame scores, the
+    /// size and encode time of the probe, the probing statistic, the reason
+    /// probing stopped and the final quantizer. With this option, the records
+    /// of all chunks are also combined into "<output>.tq.json".
+    #[clap(long, help_heading = "Target Quality")]
+    pub tq_report: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Score a distorted video against its reference with the metric
+    /// pipelines of target quality
+    Metrics(MetricsOpts),
+}
+
+#[derive(Args, Debug)]
+pub struct MetricsOpts {
+    /// Reference video or VapourSynth (.py, .vpy) script
+    #[clap(short, long)]
+    pub reference: PathBuf,
+
+    /// Distorted video
+    #[clap(short, long)]
+    pub distorted: PathBuf,
+
+    /// Metrics to calculate, any target metric except bitrate
+    ///
+    /// Example: --metrics vmaf,ssimulacra2,butteraugli-3
+    #[clap(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "vmaf",
+        verbatim_doc_comment
+    )]
+    pub metrics: Vec<TargetMetric>,
+
+    /// Frames of the reference the distorted video was encoded from, as
+    /// `start-end` with an exclusive end, e.g. the frames of a chunk
+    ///
+    /// If not specified, the distorted video is compared to the whole
+    /// reference.
+    #[clap(long, value_parser = parse_frame_range)]
+    pub frames: Option<(usize, usize)>,
+
+    /// Write the scores and per-frame scores of every metric to a JSON file
+    /// instead of printing them
+    #[clap(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Method used to load the reference for the VapourSynth metrics
+    ///
+    /// Default: the same as for encoding.
+    #[clap(long)]
+    pub chunk_method: Option<ChunkMethod>,
+
+    /// Pass python argument(s) to the script environment
+    /// --vspipe-args "message=fluffy kittens" "head=empty"
+    #[clap(long, num_args(0..))]
+    pub vspipe_args: Vec<String>,
+
+    /// Resolution VMAF and XPSNR are calculated at
+    ///
+    /// If set to inputres, the distorted video is scaled to the resolution of
+    /// the reference.
+    #[clap(long, default_value = "1920x1080")]
+    pub vmaf_res: String,
+
+    /// Resolution the other metrics are calculated at, in the form of
+    /// `widthxheight`
+    ///
+    /// If not specified, the distorted video is scaled to the resolution of the
+    /// reference.
+    #[clap(long, value_parser = TargetQuality::parse_probe_res)]
+    pub probe_res: Option<(u32, u32)>,
+
+    /// Scaler used to scale the videos to the metric resolution
+    #[clap(long, default_value = "bicubic")]
+    pub scaler: String,
+
+    /// Statistic aggregating the per-frame scores, as --probing-stat
+    ///
+    /// "auto" is the 1st percentile for VMAF and the mean for other metrics.
+    #[clap(long, default_value_t = String::from("auto"))]
+    pub probing_stat: String,
+
+    /// Path to VMAF model
+    #[clap(long)]
+    pub vmaf_path: Option<PathBuf>,
+
+    /// Filter applied to the reference at VMAF calculation
+    #[clap(long)]
+    pub vmaf_filter: Option<String>,
+
+    /// Number of threads to use for VMAF calculation
+    #[clap(long)]
+    pub vmaf_threads: Option<usize>,
+
+    /// VMAF features, as --probing-vmaf-features
+    #[clap(long, num_args = 0.., value_enum)]
+    pub vmaf_features: Vec<VmafFeature>,
+
+    /// Pixel format the native metrics (PSNR, SSIM and MS-SSIM) are calculated
+    /// in
+    #[clap(long, default_value = "yuv420p10le")]
+    pub pix_format: FFPixelFormat,
+}
+
+fn parse_frame_range(range: &str) -> Result<(usize, usize), String> {
+    let (start, end) = range
+        .split_once('-')
+        .ok_or_else(|| format!("Frame range \"{range}\" must be in the form start-end"))?;
+    let start: usize = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("Invalid start frame \"{start}\": {e}"))?;
+    let end: usize = end.trim().parse().map_err(|e| format!("Invalid end frame \"{end}\": {e}"))?;
+    if start >= end {
+        return Err(format!("Frame range \"{range}\" must end after it starts"));
+    }
+
+    Ok((start, end))
+}
+
+/// Runs the `metrics` subcommand
+fn run_metrics(opts: &MetricsOpts) -> anyhow::Result<()> {
+    ensure!(
+        opts.reference.exists(),
+        "Reference file {:?} does not exist!",
+        opts.reference
+    );
+    ensure!(
+        opts.distorted.exists(),
+        "Distorted file {:?} does not exist!",
+        opts.distorted
+    );
+
+    let vapoursynth_plugins = get_vapoursynth_plugins().ok();
+    let chunk_method = opts.chunk_method.unwrap_or_else(|| {
+        vapoursynth_plugins.map_or(ChunkMethod::Hybrid, |p| p.best_available_chunk_method())
+    });
+    let temp = format!(".{}", hash_path(&opts.reference));
+    let reference = Input::new(
+        &opts.reference,
+        opts.vspipe_args.clone(),
+        &temp,
+        chunk_method,
+        None,
+        None,
+        None,
+        false,
+    )?;
+
+    let statistic = TargetQuality::parse_probing_statistic(&opts.probing_stat)?;
+    let vmaf_res = if opts.vmaf_res == "inputres" {
+        let (width, height) = reference.clip_info()?.resolution;
+        format!("{width}x{height}")
+    } else {
+        opts.vmaf_res.clone()
+    };
+    let options = AnalysisOptions {
+        model:         opts.vmaf_path.as_deref(),
+        res:           vmaf_res,
+        probe_res:     opts.probe_res,
+        scaler:        &opts.scaler,
+        filter:        opts.vmaf_filter.as_deref(),
+        threads:       opts.vmaf_threads.unwrap_or_else(|| {
+            available_parallelism()
+                .expect("Unrecoverable: Failed to get thread count")
+                .get()
+        }),
+        vmaf_features: &opts.vmaf_features,
+        pix_format:    opts.pix_format,
+        plugins:       vapoursynth_plugins,
+    };
+
+    let mut all_scores = Vec::with_capacity(opts.metrics.len());
+    for &metric in &opts.metrics {
+        let scores = measure_metric(
+            metric,
+            &opts.distorted,
+            &reference,
+            opts.frames,
+            &statistic,
+            &options,
+        )
+        .with_context(|| format!("Failed to calculate {metric}"))?;
+
+        if opts.output.is_none() {
+            let statistics = &scores.statistics;
+            println!(
+                "{metric}: {score:.4} ({name}) | mean {mean:.4} | median {median:.4} | min \
+                 {minimum:.4} | max {maximum:.4} | 1% {percentile_1:.4} | {frames} frames",
+                score = scores.score,
+                name = scores.statistic.name,
+                mean = statistics.mean,
+                median = statistics.median,
+                minimum = statistics.minimum,
+                maximum = statistics.maximum,
+                percentile_1 = statistics.percentile_1,
+                frames = statistics.frames,
+            );
+        }
+        all_scores.push(scores);
+    }
+
+    if let Some(output) = &opts.output {
+        write_metric_scores(output, &all_scores)?;
+    }
+
+    Ok(())
 }
 
 impl CliOpts {
@@ -872,6 +1413,16 @@ impl CliOpts {
                 self.probing_vmaf_features.clone()
             },
             probing_statistic,
+            constraints: self.target_constraints.clone(),
+            parallel_probes: self.parallel_probes as usize,
+            probing_windows: self.probing_windows,
+            search_parameter: self.search_param.clone(),
+            hull_denominators: self.hull_denominators.clone(),
+            hull_slope: self.hull_slope,
+            resize_denominator: None,
+            fallback: self.target_fallback,
+            frame_floor: self.frame_floor,
+            alignment_check: self.alignment_check,
         })
     }
 }
@@ -1131,20 +1682,31 @@ pub fn parse_cli(args: CliOpts) -> anyhow::Result<Vec<EncodeArgs>> {
             output_pix_format,
             resume: args.resume,
             scenes: args.scenes.clone(),
+            scenes_format: args.scenes_format,
+            scene_cache: if args.no_scene_cache {
+                None
+            } else {
+                args.scene_cache.clone().or_else(default_scene_cache_dir)
+            },
             split_method: args.split_method.clone(),
             sc_method: args.sc_method,
             sc_only: args.sc_only,
+            sc_export: args.sc_export.clone(),
             sc_downscale_height: args.sc_downscale_height,
+            sc_refine: args.sc_refine,
             force_keyframes: parse_comma_separated_numbers(
                 args.force_keyframes.as_deref().unwrap_or(""),
             )?,
             target_quality,
             vmaf: args.vmaf,
+            analysis_metrics: args.analysis_metrics.clone(),
             vmaf_path: args.vmaf_path.clone(),
             vmaf_res: args.vmaf_res.clone(),
             probe_res: args.probe_res.clone(),
             vmaf_threads: args.vmaf_threads,
             vmaf_filter: args.vmaf_filter.clone(),
+            target_size: args.target_size,
+            tq_report: args.tq_report,
             verbosity,
             workers: args.workers,
             tiles: (1, 1), // default value; will be adjusted if tile_auto set
@@ -1224,6 +1786,10 @@ pub fn run() -> anyhow::Result<()> {
         log_level,
     )?;
 
+    if let Some(Command::Metrics(opts)) = &cli_options.command {
+        return run_metrics(opts);
+    }
+
     let args = parse_cli(cli_options)?;
     for arg in args {
         Av1anContext::new(arg)?.encode_file()?;